@@ -0,0 +1,52 @@
+//! Generate `memory.x` for the chip selected by cargo feature, `has_i2sN` cfgs for its I2S
+//! capable SPI peripherals besides SPI2 and SPI3, and `has_i2sext` if it has I2S2ext and I2S3ext.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// (feature, flash, ram, extra I2S instances, I2Sext), memory in KiB for the largest part of each
+// line. The F405/F407 CCM RAM isn't contiguous with the main RAM and is left out.
+const CHIPS: &[(&str, u32, u32, &[u32], bool)] = &[
+    ("STM32F401", 512, 96, &[], true),
+    ("STM32F405", 1024, 128, &[], true),
+    ("STM32F407", 1024, 128, &[], true),
+    ("STM32F411", 512, 128, &[1, 4, 5], true),
+    ("STM32F412", 1024, 256, &[1, 4, 5], false),
+    ("STM32F413", 1536, 320, &[1, 4, 5], false),
+    ("STM32F446", 512, 128, &[1], false),
+];
+
+fn main() {
+    let selected: Vec<_> = CHIPS
+        .iter()
+        .filter(|(chip, ..)| env::var_os(format!("CARGO_FEATURE_{}", chip)).is_some())
+        .collect();
+    let (chip, flash, ram, i2s_instances, i2s_ext) = match selected.as_slice() {
+        [chip] => **chip,
+        [] => panic!("no chip feature selected"),
+        _ => panic!("several chip features selected"),
+    };
+
+    let memory = format!(
+        "/* {} memory layout, generated by build.rs */
+MEMORY
+{{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  FLASH : ORIGIN = 0x08000000, LENGTH = {}K
+  RAM : ORIGIN = 0x20000000, LENGTH = {}K
+}}
+",
+        chip, flash, ram
+    );
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("memory.x"), memory).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    for n in i2s_instances {
+        println!("cargo:rustc-cfg=has_i2s{}", n);
+    }
+    if i2s_ext {
+        println!("cargo:rustc-cfg=has_i2sext");
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
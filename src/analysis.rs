@@ -0,0 +1,346 @@
+//! Analysis of received tones
+//!
+//! Frequency is estimated from zero crossings, then the spectrum is measured by Goertzel bins
+//! over a 7 terms Blackman-Harris window. Bins within the main lobe of DC, the fundamental or a
+//! harmonic are set apart, the others are counted as noise. Everything is `f32` to run on the
+//! FPU, the window sidelobes and the bins being well below the 32 bits noise floor.
+
+use libm::{cosf, log10f, roundf, sqrtf};
+
+use crate::block::Block;
+
+/// Highest harmonic measured
+pub const MAX_HARMONIC: u32 = 5;
+
+/// Bins on each side of a component belonging to it, the main lobe of the window
+const LOBE_BINS: usize = 7;
+
+/// 7 terms Blackman-Harris window, sidelobes below -180 dB
+const WINDOW: [f32; 7] = [
+    0.271_051_4,
+    0.433_297_94,
+    0.218_123,
+    0.065_925_45,
+    0.010_811_742,
+    0.000_776_584_8,
+    0.000_013_887_217,
+];
+
+const TAU: f32 = 2.0 * core::f32::consts::PI;
+
+/// Collect both channels of received blocks.
+pub struct Capture<const N: usize> {
+    pub left: [i32; N],
+    pub right: [i32; N],
+    len: usize,
+    shift: u32,
+}
+
+impl<const N: usize> Capture<N> {
+    /// Samples are shifted left by `shift` bits to get full scale values, 16 for 16 bits data.
+    pub fn new(shift: u32) -> Self {
+        Self {
+            left: [0; N],
+            right: [0; N],
+            len: 0,
+            shift,
+        }
+    }
+
+    /// Append as many frames of `block` as possible.
+    pub fn extend<const B: usize>(&mut self, block: &Block<B>) {
+        for &(l, r) in block.frames.iter() {
+            if self.is_full() {
+                return;
+            }
+            self.left[self.len] = l << self.shift;
+            self.right[self.len] = r << self.shift;
+            self.len += 1;
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+}
+
+/// Convert full scale `i32` samples to `f32` relative to full scale.
+pub fn normalize(samples: &[i32], out: &mut [f32]) {
+    for (o, s) in out.iter_mut().zip(samples.iter()) {
+        *o = *s as f32 / 2147483648.0;
+    }
+}
+
+// periodic window, so that tones centred on a bin leak into their main lobe only
+fn window(n: usize, len: usize) -> f32 {
+    let x = TAU * n as f32 / len as f32;
+    WINDOW
+        .iter()
+        .enumerate()
+        .map(|(k, a)| if k % 2 == 0 { *a } else { -*a } * cosf(k as f32 * x))
+        .sum()
+}
+
+/// Power of the component at `freq` times the sample rate
+fn power(samples: &[f32], freq: f32) -> f32 {
+    let coef = 2.0 * cosf(TAU * freq);
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for x in samples {
+        let s = x + coef * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    (s1 * s1 + s2 * s2 - coef * s1 * s2).max(0.0)
+}
+
+/// Amplitude of the `freq` component, using the window.
+pub fn goertzel(samples: &[f32], freq: f32, sample_rate: f32) -> f32 {
+    let coef = 2.0 * cosf(TAU * freq / sample_rate);
+    let (mut s1, mut s2, mut gain) = (0.0, 0.0, 0.0);
+    for (n, x) in samples.iter().enumerate() {
+        let w = window(n, samples.len());
+        let s = x * w + coef * s1 - s2;
+        s2 = s1;
+        s1 = s;
+        gain += w;
+    }
+    let power = s1 * s1 + s2 * s2 - coef * s1 * s2;
+    2.0 * sqrtf(power.max(0.0)) / gain
+}
+
+/// Frequency of the dominant tone from its rising zero crossings, `None` if the signal doesn't
+/// cross zero enough.
+pub fn estimate_frequency(samples: &[f32], sample_rate: f32) -> Option<f32> {
+    let mut crossings = 0;
+    let mut first = None;
+    let mut last = 0.0;
+    for (i, w) in samples.windows(2).enumerate() {
+        if w[0] < 0.0 && w[1] >= 0.0 {
+            // interpolate the crossing position between the two samples
+            let t = i as f32 + w[0] / (w[0] - w[1]);
+            first.get_or_insert(t);
+            last = t;
+            crossings += 1;
+        }
+    }
+    match first {
+        Some(first) if crossings >= 2 => {
+            Some((crossings - 1) as f32 * sample_rate / (last - first))
+        }
+        _ => None,
+    }
+}
+
+fn db(ratio: f32) -> f32 {
+    20.0 * log10f(ratio)
+}
+
+/// Measurements of a received tone
+#[derive(Copy, Clone, Debug)]
+pub struct ToneAnalysis {
+    pub frequency: f32,
+    /// Peak amplitude of the fundamental
+    pub amplitude: f32,
+    pub dc: f32,
+    /// Harmonics over fundamental, as a ratio of RMS values
+    pub thd: f32,
+    /// Everything but the fundamental and DC over fundamental, as a ratio of RMS values
+    pub thd_n: f32,
+    /// Fundamental over everything but harmonics and DC, as a ratio of RMS values
+    pub snr: f32,
+}
+
+impl ToneAnalysis {
+    pub fn thd_db(&self) -> f32 {
+        db(self.thd)
+    }
+
+    pub fn thd_n_db(&self) -> f32 {
+        db(self.thd_n)
+    }
+
+    pub fn snr_db(&self) -> f32 {
+        db(self.snr)
+    }
+}
+
+/// Analyse the dominant tone of `samples`, that are left windowed. Return `None` if no tone is
+/// found.
+pub fn analyze_tone(samples: &mut [f32], sample_rate: f32) -> Option<ToneAnalysis> {
+    let len = samples.len();
+    let frequency = estimate_frequency(samples, sample_rate)?;
+    let dc = samples.iter().sum::<f32>() / len as f32;
+    let mut gain = 0.0;
+    for (n, x) in samples.iter_mut().enumerate() {
+        let w = window(n, len);
+        *x *= w;
+        gain += w;
+    }
+    let amplitude = 2.0 * sqrtf(power(samples, frequency / sample_rate)) / gain;
+
+    // sort the bins up to Nyquist
+    let bin = |freq: f32| roundf(freq * len as f32 / sample_rate) as usize;
+    let near = |b: usize, centre: usize| b.abs_diff(centre) <= LOBE_BINS;
+    let harmonics_bins = (2..=MAX_HARMONIC)
+        .map(|k| k as f32 * frequency)
+        .take_while(|f| *f < sample_rate / 2.0)
+        .map(bin);
+    let (mut fundamental, mut harmonics, mut noise) = (0.0, 0.0, 0.0);
+    let (mut harmonics_count, mut noise_count) = (0, 0);
+    for b in 0..=len / 2 {
+        let p = power(samples, b as f32 / len as f32);
+        if near(b, 0) {
+            continue;
+        } else if near(b, bin(frequency)) {
+            fundamental += p;
+        } else if harmonics_bins.clone().any(|h| near(b, h)) {
+            harmonics += p;
+            harmonics_count += 1;
+        } else {
+            noise += p;
+            noise_count += 1;
+        }
+    }
+    if fundamental == 0.0 || noise_count == 0 {
+        return None;
+    }
+    // noise under the set apart bins is taken as the average of the others
+    let noise_bin = noise / noise_count as f32;
+    let noise = noise_bin * (len / 2) as f32;
+    let harmonics = (harmonics - noise_bin * harmonics_count as f32).max(0.0);
+    Some(ToneAnalysis {
+        frequency,
+        amplitude,
+        dc,
+        thd: sqrtf(harmonics / fundamental),
+        thd_n: sqrtf((harmonics + noise) / fundamental),
+        snr: sqrtf(fundamental / noise.max(f32::MIN_POSITIVE)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::{Generator, Signal};
+
+    const FS: f32 = 48000.0;
+    const LEN: usize = 1024;
+
+    fn tone(freq: f32, amplitude: f32) -> [f32; LEN] {
+        let mut samples = [0.0; LEN];
+        for (n, s) in samples.iter_mut().enumerate() {
+            *s = (amplitude as f64 * libm::sin(TAU as f64 * (freq * n as f32 / FS) as f64 + 0.3))
+                as f32;
+        }
+        samples
+    }
+
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            libm::fabsf(value - expected) <= tolerance,
+            "{} is not within {} of {}",
+            value,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn goertzel_amplitude() {
+        let samples = tone(3000.0, 0.5);
+        assert_close(goertzel(&samples, 3000.0, FS), 0.5, 0.01);
+        assert!(goertzel(&samples, 9000.0, FS) < 1e-3);
+    }
+
+    #[test]
+    fn frequency_and_amplitude() {
+        let mut samples = tone(1234.5, 0.25);
+        let analysis = analyze_tone(&mut samples, FS).unwrap();
+        assert_close(analysis.frequency, 1234.5, 0.1);
+        assert_close(analysis.amplitude, 0.25, 1e-3);
+        assert!(analysis.thd_n_db() < -80.0, "{}", analysis.thd_n_db());
+    }
+
+    #[test]
+    fn harmonic_distortion() {
+        let mut samples = tone(1000.0, 0.5);
+        let h2 = tone(2000.0, 0.005);
+        for (s, h) in samples.iter_mut().zip(h2.iter()) {
+            *s += h;
+        }
+        let analysis = analyze_tone(&mut samples, FS).unwrap();
+        // 1% second harmonic
+        assert_close(analysis.thd_db(), -40.0, 0.5);
+        assert_close(analysis.thd_n_db(), -40.0, 0.5);
+        assert!(analysis.snr_db() > 70.0, "{}", analysis.snr_db());
+    }
+
+    #[test]
+    fn noise_floor() {
+        let mut samples = tone(1000.0, 0.5);
+        let mut noise = Generator::new(
+            Signal::Noise {
+                seed: 42,
+                amplitude: 0.005,
+            },
+            FS as u32,
+        );
+        for s in samples.iter_mut() {
+            *s += noise.next_sample() as f32 / 2147483648.0;
+        }
+        let analysis = analyze_tone(&mut samples, FS).unwrap();
+        // uniform noise RMS is amplitude / sqrt(3)
+        let expected = db((0.5 / core::f32::consts::SQRT_2) / (0.005 / sqrtf(3.0)));
+        assert_close(analysis.snr_db(), expected, 1.0);
+        assert_close(analysis.thd_n_db(), -expected, 1.0);
+    }
+
+    #[test]
+    fn sixteen_bits_quantization() {
+        let mut generator = Generator::new(
+            Signal::Sine {
+                freq: 997.0,
+                amplitude: 0.9,
+            },
+            FS as u32,
+        );
+        let mut samples = [0i32; LEN];
+        for s in samples.iter_mut() {
+            *s = (generator.next_frame_16().0 as i32) << 16;
+        }
+        let mut normalized = [0.0; LEN];
+        normalize(&samples, &mut normalized);
+        let analysis = analyze_tone(&mut normalized, FS).unwrap();
+        assert_close(analysis.frequency, 997.0, 0.1);
+        // ideal 16 bits SNR for a full scale sine is about 98 dB
+        assert!(analysis.snr_db() > 85.0, "{}", analysis.snr_db());
+    }
+
+    #[test]
+    fn no_tone_in_silence() {
+        let mut samples = [0.0; LEN];
+        assert!(analyze_tone(&mut samples, FS).is_none());
+    }
+
+    #[test]
+    fn thirty_two_bits_on_bin() {
+        // as streamed by the signal tests, 16 periods in 256 samples
+        let mut generator = Generator::new(
+            Signal::Sine {
+                freq: FS / 16.0,
+                amplitude: 0.5,
+            },
+            FS as u32,
+        );
+        let mut samples = [0i32; 256];
+        for s in samples.iter_mut() {
+            *s = generator.next_sample();
+        }
+        let mut normalized = [0.0; 256];
+        normalize(&samples, &mut normalized);
+        let analysis = analyze_tone(&mut normalized, FS).unwrap();
+        assert_close(analysis.frequency, FS / 16.0, 0.1);
+        assert_close(analysis.amplitude, 0.5, 1e-4);
+        assert!(analysis.thd_n_db() < -110.0, "{}", analysis.thd_n_db());
+    }
+}
@@ -0,0 +1,415 @@
+//! Fixed-size sample blocks exchanged between interrupt handlers and tasks
+//!
+//! Blocks live in statically allocated storage and are handed over by ownership through two
+//! spsc queues: a free list and a filled list. The filling end takes a free block, fills it
+//! and pass it as a whole to the draining end, that process it and give it back to the free
+//! list. Both ends can work frame by frame (interrupt handler side) or block by block (task
+//! side).
+
+use heapless::spsc::*;
+
+use crate::driver_wrap::{FrameSink, FrameSource};
+
+/// Number of frames in a block
+pub const BLOCK_LEN: usize = 32;
+
+/// Number of blocks in a pool
+pub const BLOCK_COUNT: usize = 4;
+
+/// Queue able to hold every block of a pool (spsc queues keep one slot empty).
+pub type BlockQueue<'a, const N: usize> = Queue<&'a mut Block<N>, { BLOCK_COUNT + 1 }>;
+
+pub struct Block<const N: usize> {
+    /// Sequence number, incremented for each filled block.
+    pub seq: u32,
+    /// Cycle count when the block was completed.
+    pub time: u32,
+    pub frames: [(i32, i32); N],
+}
+
+impl<const N: usize> Block<N> {
+    pub const EMPTY: Self = Self {
+        seq: 0,
+        time: 0,
+        frames: [(0, 0); N],
+    };
+}
+
+/// Put `blocks` in the free list and split the pool into its filling and draining end.
+pub fn split<'a, const N: usize>(
+    blocks: &'a mut [Block<N>],
+    free: &'a mut BlockQueue<'a, N>,
+    filled: &'a mut BlockQueue<'a, N>,
+) -> (BlockFiller<'a, N>, BlockDrainer<'a, N>) {
+    let (mut free_p, free_c) = free.split();
+    let (filled_p, filled_c) = filled.split();
+    for block in blocks.iter_mut() {
+        free_p.enqueue(block).ok();
+    }
+    (
+        BlockFiller {
+            free: free_c,
+            filled: filled_p,
+            current: None,
+            pos: 0,
+            seq: 0,
+            dropped: 0,
+            completed: false,
+        },
+        BlockDrainer {
+            filled: filled_c,
+            free: free_p,
+            current: None,
+            pos: 0,
+            underflows: 0,
+        },
+    )
+}
+
+/// Filling end of a block pool, fed frame by frame.
+pub struct BlockFiller<'a, const N: usize> {
+    free: Consumer<'a, &'a mut Block<N>, { BLOCK_COUNT + 1 }>,
+    filled: Producer<'a, &'a mut Block<N>, { BLOCK_COUNT + 1 }>,
+    current: Option<&'a mut Block<N>>,
+    pos: usize,
+    seq: u32,
+    dropped: u32,
+    completed: bool,
+}
+
+impl<'a, const N: usize> BlockFiller<'a, N> {
+    /// Store a frame in the current block and hand the block over once full. Return `false`
+    /// when no free block was available and the frame was dropped.
+    pub fn push(&mut self, time: u32, frame: (i32, i32)) -> bool {
+        let block = match self.current.take() {
+            Some(block) => block,
+            None => match self.free.dequeue() {
+                Some(block) => {
+                    self.pos = 0;
+                    block
+                }
+                None => {
+                    self.dropped = self.dropped.wrapping_add(1);
+                    return false;
+                }
+            },
+        };
+        block.frames[self.pos] = frame;
+        self.pos += 1;
+        if self.pos == N {
+            self.commit(block, time);
+            self.completed = true;
+        } else {
+            self.current = Some(block);
+        }
+        true
+    }
+
+    /// `true` when there is no room left for a new frame.
+    pub fn is_full(&self) -> bool {
+        self.current.is_none() && !self.free.ready()
+    }
+
+    /// `true` if `push` completed a block since the last call.
+    pub fn take_completed(&mut self) -> bool {
+        core::mem::replace(&mut self.completed, false)
+    }
+
+    /// Take a free block to fill as a whole, if any.
+    pub fn acquire(&mut self) -> Option<&'a mut Block<N>> {
+        self.free.dequeue()
+    }
+
+    /// Hand over a block got from `acquire`.
+    pub fn commit(&mut self, block: &'a mut Block<N>, time: u32) {
+        block.seq = self.seq;
+        block.time = time;
+        self.seq = self.seq.wrapping_add(1);
+        // can't fail, the queue is sized for the whole pool
+        self.filled.enqueue(block).ok();
+    }
+
+    /// Number of frames dropped because no free block was available.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Discard the content of the partially filled block and restart sequence numbering.
+    pub fn reset(&mut self) {
+        self.pos = 0;
+        self.seq = 0;
+        self.dropped = 0;
+        self.completed = false;
+    }
+}
+
+impl<'a, const N: usize> FrameSink<i32> for BlockFiller<'a, N> {
+    fn push(&mut self, time: u32, frame: (i32, i32)) -> bool {
+        BlockFiller::push(self, time, frame)
+    }
+
+    fn is_full(&self) -> bool {
+        BlockFiller::is_full(self)
+    }
+}
+
+impl<'a, const N: usize> FrameSink<i16> for BlockFiller<'a, N> {
+    fn push(&mut self, time: u32, frame: (i16, i16)) -> bool {
+        BlockFiller::push(self, time, (frame.0 as i32, frame.1 as i32))
+    }
+
+    fn is_full(&self) -> bool {
+        BlockFiller::is_full(self)
+    }
+}
+
+/// Draining end of a block pool.
+pub struct BlockDrainer<'a, const N: usize> {
+    filled: Consumer<'a, &'a mut Block<N>, { BLOCK_COUNT + 1 }>,
+    free: Producer<'a, &'a mut Block<N>, { BLOCK_COUNT + 1 }>,
+    current: Option<&'a mut Block<N>>,
+    pos: usize,
+    underflows: u32,
+}
+
+impl<'a, const N: usize> BlockDrainer<'a, N> {
+    /// Get the oldest filled block, if any.
+    pub fn dequeue(&mut self) -> Option<&'a mut Block<N>> {
+        self.filled.dequeue()
+    }
+
+    /// Give a processed block back to the filling end.
+    pub fn release(&mut self, block: &'a mut Block<N>) {
+        // can't fail, the queue is sized for the whole pool
+        self.free.enqueue(block).ok();
+    }
+
+    /// Release every pending block without processing it.
+    pub fn flush(&mut self) {
+        while let Some(block) = self.filled.dequeue() {
+            self.free.enqueue(block).ok();
+        }
+    }
+
+    /// Get the next frame, releasing blocks as they are consumed. Return `None` when no filled
+    /// block is available.
+    pub fn pop(&mut self) -> Option<(i32, i32)> {
+        let block = match self.current.take() {
+            Some(block) => block,
+            None => match self.filled.dequeue() {
+                Some(block) => {
+                    self.pos = 0;
+                    block
+                }
+                None => {
+                    self.underflows = self.underflows.wrapping_add(1);
+                    return None;
+                }
+            },
+        };
+        let frame = block.frames[self.pos];
+        self.pos += 1;
+        if self.pos == N {
+            self.release(block);
+        } else {
+            self.current = Some(block);
+        }
+        Some(frame)
+    }
+
+    /// Number of `pop` calls that found no block.
+    pub fn underflows(&self) -> u32 {
+        self.underflows
+    }
+
+    /// Release the block being consumed and every pending one.
+    pub fn reset(&mut self) {
+        if let Some(block) = self.current.take() {
+            self.release(block);
+        }
+        self.flush();
+        self.pos = 0;
+        self.underflows = 0;
+    }
+}
+
+impl<'a, const N: usize> FrameSource<i32> for BlockDrainer<'a, N> {
+    fn pop(&mut self) -> Option<(i32, i32)> {
+        BlockDrainer::pop(self)
+    }
+}
+
+impl<'a, const N: usize> FrameSource<i16> for BlockDrainer<'a, N> {
+    fn pop(&mut self) -> Option<(i16, i16)> {
+        BlockDrainer::pop(self).map(|(l, r)| (l as i16, r as i16))
+    }
+}
+
+/// Frame `n` of a counting pattern, the right channel is the complement of the left one so a
+/// pattern frame can't be mistaken with silence.
+pub fn counter_frame(n: u32) -> (i32, i32) {
+    (n as i32, !(n as i32))
+}
+
+/// Check a stream of blocks carrying a counting pattern, starting at the first pattern frame.
+#[derive(Default)]
+pub struct SequenceCheck {
+    next_seq: Option<u32>,
+    next_count: Option<u32>,
+    /// Blocks received out of order.
+    pub seq_errors: u32,
+    /// Frames missing or altered.
+    pub data_errors: u32,
+    /// Pattern frames checked.
+    pub frames: u32,
+    /// Pattern frames skipped, seen as forward jumps of the counter.
+    pub lost: u32,
+    /// Correct frames since the last error.
+    pub run: u32,
+}
+
+/// Larger counter jumps are taken as garbage rather than lost frames.
+const MAX_GAP: u32 = 1 << 16;
+
+impl SequenceCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check<const N: usize>(&mut self, block: &Block<N>) {
+        if let Some(seq) = self.next_seq {
+            if block.seq != seq {
+                self.seq_errors += 1;
+            }
+        }
+        self.next_seq = Some(block.seq.wrapping_add(1));
+        for &(l, r) in block.frames.iter() {
+            match self.next_count {
+                // wait for the pattern to begin
+                None if r == !l => self.next_count = Some(l as u32),
+                None => continue,
+                Some(count) if (l, r) != counter_frame(count) => {
+                    self.data_errors += 1;
+                    self.run = 0;
+                    // resynchronize on what we got
+                    if r == !l {
+                        let gap = (l as u32).wrapping_sub(count);
+                        if gap < MAX_GAP {
+                            self.lost += gap;
+                        }
+                        self.next_count = Some(l as u32);
+                    }
+                }
+                Some(_) => self.run += 1,
+            }
+            if let Some(count) = self.next_count.as_mut() {
+                *count = count.wrapping_add(1);
+                self.frames += 1;
+            }
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.frames > 0 && self.seq_errors == 0 && self.data_errors == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+
+    fn pool(count: usize) -> (BlockFiller<'static, 4>, BlockDrainer<'static, 4>) {
+        let blocks = Box::leak(Box::new([Block::<4>::EMPTY; BLOCK_COUNT]));
+        let free = Box::leak(Box::new(Queue::new()));
+        let filled = Box::leak(Box::new(Queue::new()));
+        split(&mut blocks[..count], free, filled)
+    }
+
+    #[test]
+    fn blocks_in_order() {
+        let (mut filler, mut drainer) = pool(BLOCK_COUNT);
+        let mut check = SequenceCheck::new();
+        let mut n = 0;
+        for _ in 0..10 {
+            for _ in 0..8 {
+                assert!(filler.push(0, counter_frame(n)));
+                n += 1;
+            }
+            while let Some(block) = drainer.dequeue() {
+                check.check(block);
+                drainer.release(block);
+            }
+        }
+        assert!(check.is_ok());
+        assert_eq!(check.frames, 80);
+    }
+
+    #[test]
+    fn drop_when_no_free_block() {
+        let (mut filler, mut drainer) = pool(2);
+        for n in 0..8 {
+            assert!(filler.push(0, counter_frame(n)));
+        }
+        assert!(filler.is_full());
+        assert!(!filler.push(0, counter_frame(8)));
+        assert_eq!(filler.dropped(), 1);
+        let mut check = SequenceCheck::new();
+        while let Some(block) = drainer.dequeue() {
+            check.check(block);
+            drainer.release(block);
+        }
+        for n in 9..13 {
+            filler.push(0, counter_frame(n));
+        }
+        let block = drainer.dequeue().unwrap();
+        check.check(block);
+        assert_eq!(check.seq_errors, 0);
+        assert_eq!(check.data_errors, 1);
+    }
+
+    #[test]
+    fn loss_and_recovery() {
+        let (mut filler, mut drainer) = pool(BLOCK_COUNT);
+        let mut check = SequenceCheck::new();
+        // 4 frames skipped then 2 garbage frames
+        let stream = (0..6)
+            .chain(10..14)
+            .map(counter_frame)
+            .chain([(0, 0), (0, 0)])
+            .chain((16..24).map(counter_frame));
+        for frame in stream {
+            filler.push(0, frame);
+            while let Some(block) = drainer.dequeue() {
+                check.check(block);
+                drainer.release(block);
+            }
+        }
+        assert_eq!(check.lost, 4);
+        assert_eq!(check.data_errors, 3);
+        assert_eq!(check.run, 8);
+    }
+
+    #[test]
+    fn frame_by_frame_drain() {
+        let (mut filler, mut drainer) = pool(BLOCK_COUNT);
+        for i in 0..2 {
+            let block = filler.acquire().unwrap();
+            for (n, frame) in block.frames.iter_mut().enumerate() {
+                *frame = counter_frame(i * 4 + n as u32);
+            }
+            filler.commit(block, 0);
+        }
+        for n in 0..8 {
+            assert_eq!(drainer.pop(), Some(counter_frame(n)));
+        }
+        assert_eq!(drainer.pop(), None);
+        assert_eq!(drainer.underflows(), 1);
+        // every block went back to the free list
+        for _ in 0..BLOCK_COUNT {
+            assert!(filler.acquire().is_some());
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Board support, selected by cargo feature
+//!
+//! Each board defines the I2S pins, the TIM2 inputs used for WS capture, its HSE clock and the
+//! chip name for the probe, clock limits come from [`chip`](crate::chip). On every board the
+//! suite needs WS, CK and SD of i2s2 wired to the same pins of i2s3, i2s2 WS wired to PA0 and
+//! i2s3 WS wired to PA1.
+//!
+//! i2s2 WS stays on PB12 and i2s3 WS on PA4, so the WS interrupts are always EXTI15_10 and
+//! EXTI4.
+//!
+//! On chips with more I2S instances, i2s1 and i2s5 transmit to i2s2 and their WS, CK and SD must
+//! be wired to the i2s2 ones too. Instances out of a scenario are kept in reset, leaving their
+//! pins floating. i2s1 WS is on PA15 (EXTI15_10 shared with i2s2) and i2s5 WS on PB1 (EXTI1).
+//!
+//! i2s4 isn't tested although `has_i2s4` is set for the F411, F412 and F413. Outside port E its
+//! WS and CK are only on PB12 and PB13, the i2s2 pins, and its SD on PA1, the i2s3 WS capture
+//! input. Port E has the others on the 100 and 144 pins packages only, and the Nucleo-144 boards
+//! use the pins of the Nucleo-64 ones.
+//!
+//! On chips with I2S2ext, the loopback self-test only needs i2s2 SD (PB15) wired to I2S2ext SD
+//! (PB14).
+//!
+//! The codec scenario streams from i2s3, MCLK included, to the board codec configured over
+//! I2C1. Nucleo and Black Pill boards need a WM8731 module, the Discovery uses its CS43L22.
+
+use crate::hal;
+
+use hal::gpio::{gpioa, gpiob, gpioc, gpiod, NoPin, Pin};
+use hal::pac::Interrupt;
+
+use crate::codec::*;
+use crate::wiring::Line;
+
+#[cfg(not(any(
+    feature = "nucleo",
+    feature = "blackpill-f411ce",
+    feature = "disco-f407"
+)))]
+compile_error!("select a board feature: nucleo, nucleo-f411re, blackpill-f411ce or disco-f407");
+
+#[cfg(any(
+    all(feature = "nucleo", feature = "blackpill-f411ce"),
+    all(feature = "nucleo", feature = "disco-f407"),
+    all(feature = "blackpill-f411ce", feature = "disco-f407")
+))]
+compile_error!("only one board feature can be selected");
+
+/// Interrupt of the i2s2 WS pin
+pub const I2S2_WS_EXTI: Interrupt = Interrupt::EXTI15_10;
+
+/// Interrupt of the i2s3 WS pin
+pub const I2S3_WS_EXTI: Interrupt = Interrupt::EXTI4;
+
+/// Interrupt of the i2s1 WS pin
+#[cfg(has_i2s1)]
+pub const I2S1_WS_EXTI: Interrupt = Interrupt::EXTI15_10;
+
+/// Interrupt of the i2s5 WS pin
+#[cfg(has_i2s5)]
+pub const I2S5_WS_EXTI: Interrupt = Interrupt::EXTI1;
+
+/// i2s2 (WS, CK, SD) lines
+pub const I2S2_LINES: [Line; 3] = [Line::new('B', 12), Line::new('B', 13), Line::new('B', 15)];
+
+/// TIM2 inputs, wired to i2s2 WS and i2s3 WS
+pub const WS_CAPTURE_LINES: [Line; 2] = [Line::new('A', 0), Line::new('A', 1)];
+
+/// I2S2ext SD pin, receiving i2s2 SD in loopback
+#[cfg(has_i2sext)]
+pub const I2S2EXT_SD: Line = Line::new('B', 14);
+
+/// TIM2 inputs capturing the i2s2 and i2s3 WS lines
+pub type WsCapturePins = (Pin<'A', 0_u8>, Pin<'A', 1_u8>);
+
+/// Pins used by the test suite
+pub struct Pins {
+    pub i2s2: I2s2Pins,
+    pub i2s3: I2s3Pins,
+    #[cfg(has_i2s1)]
+    pub i2s1: I2s1Pins,
+    #[cfg(has_i2s5)]
+    pub i2s5: I2s5Pins,
+    #[cfg(has_i2sext)]
+    pub i2s2ext_sd: Pin<'B', 14_u8>,
+    pub ws_capture: WsCapturePins,
+    pub codec_i2c: CodecI2cPins,
+    /// Held high to take the codec out of reset
+    #[cfg(feature = "disco-f407")]
+    pub codec_reset: Pin<'D', 4_u8>,
+}
+
+// Nucleo-64 and Nucleo-144 boards share the Arduino and Morpho pins used here
+#[cfg(feature = "nucleo")]
+mod imp {
+    use super::*;
+
+    pub const NAME: &str = "Nucleo";
+    #[cfg(feature = "stm32f401")]
+    pub const PROBE_CHIP: &str = "STM32F401RETx";
+    #[cfg(feature = "stm32f411")]
+    pub const PROBE_CHIP: &str = "STM32F411RETx";
+    #[cfg(feature = "stm32f412")]
+    pub const PROBE_CHIP: &str = "STM32F412ZGTx";
+    #[cfg(feature = "stm32f413")]
+    pub const PROBE_CHIP: &str = "STM32F413ZHTx";
+    #[cfg(feature = "stm32f446")]
+    pub const PROBE_CHIP: &str = "STM32F446RETx";
+    #[cfg(any(feature = "stm32f405", feature = "stm32f407"))]
+    compile_error!("no Nucleo board with this chip");
+    /// 8 MHz from the ST-Link MCO
+    pub const HSE_HZ: u32 = 8_000_000;
+
+    /// (WS, CK, MCLK, SD)
+    pub type I2s2Pins = (
+        Pin<'B', 12_u8>,
+        Pin<'B', 13_u8>,
+        Pin<'C', 6_u8>,
+        Pin<'B', 15_u8>,
+    );
+    /// (WS, CK, MCLK, SD)
+    pub type I2s3Pins = (
+        Pin<'A', 4_u8>,
+        Pin<'C', 10_u8>,
+        Pin<'C', 7_u8>,
+        Pin<'C', 12_u8>,
+    );
+    /// i2s3 (WS, CK, SD) lines
+    pub const I2S3_LINES: [Line; 3] = [Line::new('A', 4), Line::new('C', 10), Line::new('C', 12)];
+    /// (SCL, SDA) on the Arduino D15 and D14 pins
+    pub type CodecI2cPins = (Pin<'B', 8_u8>, Pin<'B', 9_u8>);
+    pub type CodecDriver<I2C> = Wm8731<I2C>;
+
+    /// (WS, CK, MCLK, SD), PA5 is the LED
+    #[cfg(has_i2s1)]
+    pub type I2s1Pins = (Pin<'A', 15_u8>, Pin<'B', 3_u8>, NoPin, Pin<'B', 5_u8>);
+    /// i2s1 (WS, CK, SD) lines
+    #[cfg(has_i2s1)]
+    pub const I2S1_LINES: [Line; 3] = [Line::new('A', 15), Line::new('B', 3), Line::new('B', 5)];
+    /// (WS, CK, MCLK, SD)
+    #[cfg(has_i2s5)]
+    pub type I2s5Pins = (Pin<'B', 1_u8>, Pin<'B', 0_u8>, NoPin, Pin<'A', 10_u8>);
+    /// i2s5 (WS, CK, SD) lines
+    #[cfg(has_i2s5)]
+    pub const I2S5_LINES: [Line; 3] = [Line::new('B', 1), Line::new('B', 0), Line::new('A', 10)];
+
+    impl Pins {
+        pub fn new(
+            gpioa: gpioa::Parts,
+            gpiob: gpiob::Parts,
+            gpioc: gpioc::Parts,
+            _gpiod: gpiod::Parts,
+        ) -> Self {
+            Self {
+                i2s2: (gpiob.pb12, gpiob.pb13, gpioc.pc6, gpiob.pb15),
+                i2s3: (gpioa.pa4, gpioc.pc10, gpioc.pc7, gpioc.pc12),
+                #[cfg(has_i2s1)]
+                i2s1: (gpioa.pa15, gpiob.pb3, NoPin, gpiob.pb5),
+                #[cfg(has_i2s5)]
+                i2s5: (gpiob.pb1, gpiob.pb0, NoPin, gpioa.pa10),
+                #[cfg(has_i2sext)]
+                i2s2ext_sd: gpiob.pb14,
+                ws_capture: (gpioa.pa0, gpioa.pa1),
+                codec_i2c: (gpiob.pb8, gpiob.pb9),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "blackpill-f411ce")]
+mod imp {
+    use super::*;
+
+    pub const NAME: &str = "Black Pill";
+    pub const PROBE_CHIP: &str = "STM32F411CEUx";
+    pub const HSE_HZ: u32 = 25_000_000;
+
+    // the 48 pins package has no PC6, PC10 and PC12
+
+    /// (WS, CK, MCLK, SD)
+    pub type I2s2Pins = (
+        Pin<'B', 12_u8>,
+        Pin<'B', 13_u8>,
+        Pin<'A', 3_u8>,
+        Pin<'B', 15_u8>,
+    );
+    /// (WS, CK, MCLK, SD)
+    pub type I2s3Pins = (
+        Pin<'A', 4_u8>,
+        Pin<'B', 3_u8>,
+        Pin<'B', 10_u8>,
+        Pin<'B', 5_u8>,
+    );
+    /// i2s3 (WS, CK, SD) lines
+    pub const I2S3_LINES: [Line; 3] = [Line::new('A', 4), Line::new('B', 3), Line::new('B', 5)];
+    /// (SCL, SDA)
+    pub type CodecI2cPins = (Pin<'B', 6_u8>, Pin<'B', 7_u8>);
+    pub type CodecDriver<I2C> = Wm8731<I2C>;
+    /// (WS, CK, MCLK, SD)
+    pub type I2s1Pins = (Pin<'A', 15_u8>, Pin<'A', 5_u8>, NoPin, Pin<'A', 7_u8>);
+    /// i2s1 (WS, CK, SD) lines
+    pub const I2S1_LINES: [Line; 3] = [Line::new('A', 15), Line::new('A', 5), Line::new('A', 7)];
+    /// (WS, CK, MCLK, SD)
+    pub type I2s5Pins = (Pin<'B', 1_u8>, Pin<'B', 0_u8>, NoPin, Pin<'A', 10_u8>);
+    /// i2s5 (WS, CK, SD) lines
+    pub const I2S5_LINES: [Line; 3] = [Line::new('B', 1), Line::new('B', 0), Line::new('A', 10)];
+
+    impl Pins {
+        pub fn new(
+            gpioa: gpioa::Parts,
+            gpiob: gpiob::Parts,
+            _gpioc: gpioc::Parts,
+            _gpiod: gpiod::Parts,
+        ) -> Self {
+            Self {
+                i2s2: (gpiob.pb12, gpiob.pb13, gpioa.pa3, gpiob.pb15),
+                i2s3: (gpioa.pa4, gpiob.pb3, gpiob.pb10, gpiob.pb5),
+                i2s1: (gpioa.pa15, gpioa.pa5, NoPin, gpioa.pa7),
+                i2s5: (gpiob.pb1, gpiob.pb0, NoPin, gpioa.pa10),
+                #[cfg(has_i2sext)]
+                i2s2ext_sd: gpiob.pb14,
+                ws_capture: (gpioa.pa0, gpioa.pa1),
+                codec_i2c: (gpiob.pb6, gpiob.pb7),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "disco-f407")]
+mod imp {
+    use super::*;
+
+    pub const NAME: &str = "Discovery";
+    pub const PROBE_CHIP: &str = "STM32F407VGTx";
+    pub const HSE_HZ: u32 = 8_000_000;
+
+    // i2s3 pins are also wired to the CS43L22 codec, PA0 to the user button
+
+    /// (WS, CK, MCLK, SD)
+    pub type I2s2Pins = (
+        Pin<'B', 12_u8>,
+        Pin<'B', 13_u8>,
+        Pin<'C', 6_u8>,
+        Pin<'B', 15_u8>,
+    );
+    /// (WS, CK, MCLK, SD)
+    pub type I2s3Pins = (
+        Pin<'A', 4_u8>,
+        Pin<'C', 10_u8>,
+        Pin<'C', 7_u8>,
+        Pin<'C', 12_u8>,
+    );
+    /// i2s3 (WS, CK, SD) lines
+    pub const I2S3_LINES: [Line; 3] = [Line::new('A', 4), Line::new('C', 10), Line::new('C', 12)];
+    /// (SCL, SDA)
+    pub type CodecI2cPins = (Pin<'B', 6_u8>, Pin<'B', 9_u8>);
+    pub type CodecDriver<I2C> = Cs43l22<I2C>;
+
+    impl Pins {
+        pub fn new(
+            gpioa: gpioa::Parts,
+            gpiob: gpiob::Parts,
+            gpioc: gpioc::Parts,
+            gpiod: gpiod::Parts,
+        ) -> Self {
+            Self {
+                i2s2: (gpiob.pb12, gpiob.pb13, gpioc.pc6, gpiob.pb15),
+                i2s3: (gpioa.pa4, gpioc.pc10, gpioc.pc7, gpioc.pc12),
+                #[cfg(has_i2sext)]
+                i2s2ext_sd: gpiob.pb14,
+                ws_capture: (gpioa.pa0, gpioa.pa1),
+                codec_i2c: (gpiob.pb6, gpiob.pb9),
+                codec_reset: gpiod.pd4,
+            }
+        }
+    }
+}
+
+pub use imp::*;
@@ -0,0 +1,134 @@
+//! Channel modes
+//!
+//! How the samples an application deals with map to the left and right channels of a frame.
+//! Mono modes carry single samples, the other channel being filled on transmit and discarded
+//! on receive, muted modes replace a channel of stereo frames by the fill value. Mono samples
+//! and fill values are left aligned `i32`, 16 bits frames use their upper half.
+
+/// Sample type of frames
+pub trait Sample: Copy {
+    fn from_aligned(sample: i32) -> Self;
+    fn to_aligned(self) -> i32;
+}
+
+impl Sample for i16 {
+    fn from_aligned(sample: i32) -> Self {
+        (sample >> 16) as i16
+    }
+
+    fn to_aligned(self) -> i32 {
+        (self as i32) << 16
+    }
+}
+
+impl Sample for i32 {
+    fn from_aligned(sample: i32) -> Self {
+        sample
+    }
+
+    fn to_aligned(self) -> i32 {
+        self
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ChannelMode {
+    /// `(l, r)` frames
+    Stereo,
+    /// Samples on the left channel
+    MonoLeft,
+    /// Samples on the right channel
+    MonoRight,
+    /// Samples on both channels, the left one is kept on receive
+    DuplicatedMono,
+    /// Stereo frames with the left channel replaced by the fill value
+    MutedLeft,
+    /// Stereo frames with the right channel replaced by the fill value
+    MutedRight,
+}
+
+/// All modes, for scenarios comparing them
+pub const CHANNEL_MODES: [ChannelMode; 6] = [
+    ChannelMode::Stereo,
+    ChannelMode::MonoLeft,
+    ChannelMode::MonoRight,
+    ChannelMode::DuplicatedMono,
+    ChannelMode::MutedLeft,
+    ChannelMode::MutedRight,
+];
+
+impl ChannelMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelMode::Stereo => "stereo",
+            ChannelMode::MonoLeft => "mono left",
+            ChannelMode::MonoRight => "mono right",
+            ChannelMode::DuplicatedMono => "duplicated mono",
+            ChannelMode::MutedLeft => "muted left",
+            ChannelMode::MutedRight => "muted right",
+        }
+    }
+
+    /// `true` if the application deals in single samples
+    pub fn is_mono(self) -> bool {
+        matches!(
+            self,
+            ChannelMode::MonoLeft | ChannelMode::MonoRight | ChannelMode::DuplicatedMono
+        )
+    }
+
+    /// Frame carrying a mono `sample`, stereo modes duplicate it.
+    pub fn frame_of<T: Sample>(self, sample: T, fill: T) -> (T, T) {
+        match self {
+            ChannelMode::MonoLeft => (sample, fill),
+            ChannelMode::MonoRight => (fill, sample),
+            _ => (sample, sample),
+        }
+    }
+
+    /// Mono sample kept from a received frame
+    pub fn sample_of<T: Sample>(self, frame: (T, T)) -> T {
+        match self {
+            ChannelMode::MonoRight => frame.1,
+            _ => frame.0,
+        }
+    }
+
+    /// `frame` with the muted channel replaced by `fill`, unchanged in other modes
+    pub fn mask<T: Sample>(self, frame: (T, T), fill: T) -> (T, T) {
+        match self {
+            ChannelMode::MutedLeft => (fill, frame.1),
+            ChannelMode::MutedRight => (frame.0, fill),
+            _ => frame,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mono_frames() {
+        assert_eq!(ChannelMode::MonoLeft.frame_of(1, 9), (1, 9));
+        assert_eq!(ChannelMode::MonoRight.frame_of(1, 9), (9, 1));
+        assert_eq!(ChannelMode::DuplicatedMono.frame_of(1, 9), (1, 1));
+        assert_eq!(ChannelMode::MonoRight.sample_of((1, 2)), 2);
+        assert_eq!(ChannelMode::DuplicatedMono.sample_of((1, 2)), 1);
+    }
+
+    #[test]
+    fn muted_frames() {
+        assert_eq!(ChannelMode::MutedLeft.mask((1, 2), 9), (9, 2));
+        assert_eq!(ChannelMode::MutedRight.mask((1, 2), 9), (1, 9));
+        assert_eq!(ChannelMode::Stereo.mask((1, 2), 9), (1, 2));
+        assert_eq!(ChannelMode::MonoLeft.mask((1, 2), 9), (1, 2));
+    }
+
+    #[test]
+    fn aligned_16_bits() {
+        assert_eq!(i16::from_aligned(0x1234_5678), 0x1234);
+        assert_eq!((-2i16).to_aligned(), -2 << 16);
+        assert_eq!(i32::from_aligned(-5), -5);
+    }
+}
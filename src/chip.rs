@@ -0,0 +1,95 @@
+//! Chip support, selected by cargo feature
+//!
+//! Clock limits of each supported STM32F4. Memory layouts and I2S capable peripherals come
+//! from `build.rs`.
+
+use crate::hal;
+
+use hal::prelude::*;
+use hal::rcc::CFGR;
+
+#[cfg(not(any(
+    feature = "stm32f401",
+    feature = "stm32f405",
+    feature = "stm32f407",
+    feature = "stm32f411",
+    feature = "stm32f412",
+    feature = "stm32f413",
+    feature = "stm32f446"
+)))]
+compile_error!(
+    "select a chip feature: stm32f401, stm32f405, stm32f407, stm32f411, stm32f412, stm32f413 \
+     or stm32f446"
+);
+
+/// Frequency of the I2S clocks, a multiple of 48 kHz and 32 kHz sample rates
+pub const I2S_CLK_HZ: u32 = 61_440_000;
+
+#[cfg(feature = "stm32f401")]
+mod imp {
+    use super::*;
+
+    pub const NAME: &str = "STM32F401";
+    pub const SYSCLK_MAX_HZ: u32 = 84_000_000;
+    pub const PCLK1_MAX_HZ: u32 = 42_000_000;
+    pub const PCLK2_MAX_HZ: u32 = 84_000_000;
+}
+
+#[cfg(any(feature = "stm32f405", feature = "stm32f407"))]
+mod imp {
+    use super::*;
+
+    #[cfg(feature = "stm32f405")]
+    pub const NAME: &str = "STM32F405";
+    #[cfg(feature = "stm32f407")]
+    pub const NAME: &str = "STM32F407";
+    pub const SYSCLK_MAX_HZ: u32 = 168_000_000;
+    pub const PCLK1_MAX_HZ: u32 = 42_000_000;
+    pub const PCLK2_MAX_HZ: u32 = 84_000_000;
+}
+
+#[cfg(feature = "stm32f411")]
+mod imp {
+    use super::*;
+
+    pub const NAME: &str = "STM32F411";
+    pub const SYSCLK_MAX_HZ: u32 = 100_000_000;
+    pub const PCLK1_MAX_HZ: u32 = 50_000_000;
+    pub const PCLK2_MAX_HZ: u32 = 100_000_000;
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+mod imp {
+    use super::*;
+
+    #[cfg(feature = "stm32f412")]
+    pub const NAME: &str = "STM32F412";
+    #[cfg(feature = "stm32f413")]
+    pub const NAME: &str = "STM32F413";
+    pub const SYSCLK_MAX_HZ: u32 = 100_000_000;
+    pub const PCLK1_MAX_HZ: u32 = 50_000_000;
+    pub const PCLK2_MAX_HZ: u32 = 100_000_000;
+}
+
+#[cfg(feature = "stm32f446")]
+mod imp {
+    use super::*;
+
+    pub const NAME: &str = "STM32F446";
+    pub const SYSCLK_MAX_HZ: u32 = 180_000_000;
+    pub const PCLK1_MAX_HZ: u32 = 45_000_000;
+    pub const PCLK2_MAX_HZ: u32 = 90_000_000;
+}
+
+pub use imp::*;
+
+/// Request the I2S clocks. Some chips have a single I2S clock, others one for each APB.
+pub fn i2s_clocks(cfgr: CFGR) -> CFGR {
+    #[cfg(any(feature = "stm32f412", feature = "stm32f413", feature = "stm32f446"))]
+    let cfgr = cfgr
+        .i2s_apb1_clk(I2S_CLK_HZ.Hz())
+        .i2s_apb2_clk(I2S_CLK_HZ.Hz());
+    #[cfg(not(any(feature = "stm32f412", feature = "stm32f413", feature = "stm32f446")))]
+    let cfgr = cfgr.i2s_clk(I2S_CLK_HZ.Hz());
+    cfgr
+}
@@ -0,0 +1,130 @@
+//! Audio codecs configured over I2C
+//!
+//! Drivers only build register sequences and write them through the embedded-hal blocking I2C
+//! traits, so the sequences are checked on the host against a mock bus. Codecs are slaves of
+//! the I2S link, MCLK being 256 times the sample rate, and [`Format`] describes the link the
+//! same way the standard and `DataFormat` of the I2S driver do.
+
+use core::fmt::Debug;
+
+pub mod cs43l22;
+pub mod wm8731;
+
+pub use cs43l22::Cs43l22;
+pub use wm8731::Wm8731;
+
+/// Frame standard of the I2S link
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Standard {
+    Philips,
+    /// Left justified
+    Msb,
+    /// Right justified
+    Lsb,
+}
+
+/// Format of the I2S link
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Format {
+    pub standard: Standard,
+    /// Significant bits of a sample
+    pub data_bits: u8,
+    /// Bits of a channel slot, 16 or 32
+    pub channel_bits: u8,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error<E> {
+    /// The I2C transfer failed
+    Bus(E),
+    /// The codec can't use this format
+    Format,
+    /// The codec can't run at this sample rate
+    SampleRate,
+    /// The chip answering isn't the expected codec, with the ID read
+    Id(u8),
+}
+
+/// Operations common to the codecs
+pub trait Codec {
+    type BusError: Debug;
+
+    const NAME: &'static str;
+
+    /// Check the codec answers, `Error::Bus` if nothing acknowledges its address.
+    fn probe(&mut self) -> Result<(), Error<Self::BusError>>;
+
+    /// Power up and configure for `format` at `sample_rate`, with the output muted. I2S clocks
+    /// should be running.
+    fn init(&mut self, format: Format, sample_rate: u32) -> Result<(), Error<Self::BusError>>;
+
+    /// Set the output volume in dB, clamped to the codec range.
+    fn set_volume(&mut self, db: i8) -> Result<(), Error<Self::BusError>>;
+
+    fn set_mute(&mut self, mute: bool) -> Result<(), Error<Self::BusError>>;
+
+    /// `Some(true)` if the codec latched a clock error since the last call, `None` if it can't
+    /// tell.
+    fn clock_error(&mut self) -> Result<Option<bool>, Error<Self::BusError>>;
+
+    /// Mute and power down.
+    fn power_down(&mut self) -> Result<(), Error<Self::BusError>>;
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use embedded_hal::blocking::i2c::{Write, WriteRead};
+    use heapless::Vec;
+
+    /// I2C device recording the writes it gets and answering reads from a register file
+    pub struct Bus {
+        pub address: u8,
+        pub writes: Vec<Vec<u8, 2>, 64>,
+        pub registers: [u8; 256],
+    }
+
+    impl Bus {
+        pub fn new(address: u8) -> Self {
+            Self {
+                address,
+                writes: Vec::new(),
+                registers: [0; 256],
+            }
+        }
+
+        /// Written (register, value) pairs of a byte wide register map
+        pub fn byte_writes(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+            self.writes
+                .iter()
+                .filter(|w| w.len() == 2)
+                .map(|w| (w[0], w[1]))
+        }
+    }
+
+    impl Write for Bus {
+        type Error = ();
+
+        // a wrong address is not acknowledged
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            if address != self.address {
+                return Err(());
+            }
+            if let [register, value] = *bytes {
+                self.registers[register as usize] = value;
+            }
+            self.writes.push(Vec::from_slice(bytes)?).map_err(|_| ())
+        }
+    }
+
+    impl WriteRead for Bus {
+        type Error = ();
+
+        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            if address != self.address {
+                return Err(());
+            }
+            buffer[0] = self.registers[bytes[0] as usize];
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Cirrus Logic CS43L22 driver, the DAC of the STM32F4 Discovery
+//!
+//! The headphone output is used, speakers stay powered down. RESET must be high before any
+//! access, the speed mode is detected from MCLK.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use super::*;
+
+/// Address with AD0 low
+pub const ADDRESS: u8 = 0x4A;
+
+// registers
+const ID: u8 = 0x01;
+const POWER_CTL1: u8 = 0x02;
+const POWER_CTL2: u8 = 0x04;
+const CLOCKING_CTL: u8 = 0x05;
+const INTERFACE_CTL1: u8 = 0x06;
+const PLAYBACK_CTL2: u8 = 0x0F;
+const MASTER_A_VOL: u8 = 0x20;
+const MASTER_B_VOL: u8 = 0x21;
+const STATUS: u8 = 0x2E;
+
+// chip ID in the upper 5 bits of ID
+const CHIP_ID: u8 = 0xE0;
+const CHIP_ID_MASK: u8 = 0xF8;
+
+// power control 1
+const POWERED_DOWN: u8 = 0x01;
+const POWERED_UP: u8 = 0x9E;
+const POWER_SAVE: u8 = 0x9F;
+// power control 2, headphones on, speakers off
+const HEADPHONES: u8 = 0xAF;
+// clocking control, auto speed detection
+const AUTO: u8 = 0x80;
+// playback control 2, headphones muted
+const HP_MUTE: u8 = 0xC0;
+// status, serial port clock error
+const SPCLKERR: u8 = 0x40;
+
+// master volume, 0.5 dB steps
+const VOL_MIN_DB: i8 = -102;
+const VOL_MAX_DB: i8 = 12;
+
+// auto detection covers single speed mode only with MCLK at 256 fs
+const MIN_SAMPLE_RATE: u32 = 4000;
+const MAX_SAMPLE_RATE: u32 = 50000;
+
+// required initialization settings from the datasheet, (register, value) or (register, set bits,
+// cleared bits) for read modify write
+const REQUIRED_INIT: [(u8, u8, u8); 5] = [
+    (0x00, 0x99, 0xFF),
+    (0x47, 0x80, 0xFF),
+    (0x32, 0x80, 0x00),
+    (0x32, 0x00, 0x80),
+    (0x00, 0x00, 0xFF),
+];
+
+pub struct Cs43l22<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C, E> Cs43l22<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I2C) -> Self {
+        Self::with_address(i2c, ADDRESS)
+    }
+
+    pub fn with_address(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> Result<(), Error<E>> {
+        self.i2c
+            .write(self.address, &[register, value])
+            .map_err(Error::Bus)
+    }
+
+    fn read(&mut self, register: u8) -> Result<u8, Error<E>> {
+        let mut value = [0];
+        self.i2c
+            .write_read(self.address, &[register], &mut value)
+            .map_err(Error::Bus)?;
+        Ok(value[0])
+    }
+
+    fn modify(&mut self, register: u8, set: u8, clear: u8) -> Result<(), Error<E>> {
+        // a full clear is a plain write
+        let value = if clear == 0xFF {
+            set
+        } else {
+            self.read(register)? & !clear | set
+        };
+        self.write(register, value)
+    }
+}
+
+/// Interface control 1 register for `format`, the codec being slave
+fn interface(format: Format) -> Result<u8, ()> {
+    if format.channel_bits != 16 && format.channel_bits != 32
+        || format.data_bits > format.channel_bits
+    {
+        return Err(());
+    }
+    match (format.standard, format.data_bits) {
+        (Standard::Msb, 16 | 24) => Ok(0x00),
+        (Standard::Philips, 16 | 24) => Ok(0x04),
+        (Standard::Lsb, 24) => Ok(0x08),
+        (Standard::Lsb, 20) => Ok(0x09),
+        (Standard::Lsb, 18) => Ok(0x0A),
+        (Standard::Lsb, 16) => Ok(0x0B),
+        _ => Err(()),
+    }
+}
+
+impl<I2C, E> Codec for Cs43l22<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: Debug,
+{
+    type BusError = E;
+
+    const NAME: &'static str = "CS43L22";
+
+    fn probe(&mut self) -> Result<(), Error<E>> {
+        let id = self.read(ID)?;
+        if id & CHIP_ID_MASK != CHIP_ID {
+            return Err(Error::Id(id));
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, format: Format, sample_rate: u32) -> Result<(), Error<E>> {
+        let interface = interface(format).map_err(|_| Error::Format)?;
+        if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+            return Err(Error::SampleRate);
+        }
+        self.probe()?;
+        // configured while powered down, then powered up with the clocks running
+        self.write(POWER_CTL1, POWERED_DOWN)?;
+        for (register, set, clear) in REQUIRED_INIT {
+            self.modify(register, set, clear)?;
+        }
+        self.write(POWER_CTL2, HEADPHONES)?;
+        self.write(CLOCKING_CTL, AUTO)?;
+        self.write(INTERFACE_CTL1, interface)?;
+        self.write(PLAYBACK_CTL2, HP_MUTE)?;
+        self.write(POWER_CTL1, POWERED_UP)
+    }
+
+    fn set_volume(&mut self, db: i8) -> Result<(), Error<E>> {
+        let code = (db.clamp(VOL_MIN_DB, VOL_MAX_DB) as i16 * 2) as u8;
+        self.write(MASTER_A_VOL, code)?;
+        self.write(MASTER_B_VOL, code)
+    }
+
+    fn set_mute(&mut self, mute: bool) -> Result<(), Error<E>> {
+        self.write(PLAYBACK_CTL2, if mute { HP_MUTE } else { 0 })
+    }
+
+    fn clock_error(&mut self) -> Result<Option<bool>, Error<E>> {
+        Ok(Some(self.read(STATUS)? & SPCLKERR != 0))
+    }
+
+    fn power_down(&mut self) -> Result<(), Error<E>> {
+        self.write(PLAYBACK_CTL2, HP_MUTE)?;
+        self.write(POWER_CTL1, POWER_SAVE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::mock::Bus;
+
+    const PHILIPS_24: Format = Format {
+        standard: Standard::Philips,
+        data_bits: 24,
+        channel_bits: 32,
+    };
+
+    fn cs43l22() -> Cs43l22<Bus> {
+        let mut bus = Bus::new(ADDRESS);
+        // revision B1
+        bus.registers[ID as usize] = 0xE3;
+        bus.registers[0x32] = 0x3B;
+        Cs43l22::new(bus)
+    }
+
+    #[test]
+    fn init_sequence() {
+        let mut codec = cs43l22();
+        codec.init(PHILIPS_24, 48000).unwrap();
+        let bus = codec.release();
+        let expected = [
+            (POWER_CTL1, POWERED_DOWN),
+            (0x00, 0x99),
+            (0x47, 0x80),
+            (0x32, 0xBB),
+            (0x32, 0x3B),
+            (0x00, 0x00),
+            (POWER_CTL2, 0xAF),
+            (CLOCKING_CTL, 0x80),
+            (INTERFACE_CTL1, 0x04),
+            (PLAYBACK_CTL2, 0xC0),
+            (POWER_CTL1, POWERED_UP),
+        ];
+        assert!(bus.byte_writes().eq(expected));
+    }
+
+    #[test]
+    fn wrong_chip() {
+        let mut codec = cs43l22();
+        codec.i2c.registers[ID as usize] = 0x00;
+        assert_eq!(codec.probe(), Err(Error::Id(0)));
+        assert_eq!(codec.init(PHILIPS_24, 48000), Err(Error::Id(0)));
+        assert!(codec.release().writes.is_empty());
+    }
+
+    #[test]
+    fn unsupported_config() {
+        let mut codec = cs43l22();
+        let philips_32 = Format {
+            data_bits: 32,
+            ..PHILIPS_24
+        };
+        assert_eq!(codec.init(philips_32, 48000), Err(Error::Format));
+        assert_eq!(codec.init(PHILIPS_24, 96000), Err(Error::SampleRate));
+        let lsb_18 = Format {
+            standard: Standard::Lsb,
+            data_bits: 18,
+            channel_bits: 32,
+        };
+        assert_eq!(interface(lsb_18), Ok(0x0A));
+    }
+
+    #[test]
+    fn volume_codes() {
+        let mut codec = cs43l22();
+        for db in [0, -20, -120, 12] {
+            codec.set_volume(db).unwrap();
+        }
+        let bus = codec.release();
+        let codes: heapless::Vec<u8, 8> = bus.byte_writes().map(|(_, code)| code).collect();
+        assert_eq!(codes, [0x00, 0x00, 0xD8, 0xD8, 0x34, 0x34, 0x18, 0x18]);
+    }
+
+    #[test]
+    fn clock_error_bit() {
+        let mut codec = cs43l22();
+        assert_eq!(codec.clock_error(), Ok(Some(false)));
+        codec.i2c.registers[STATUS as usize] = SPCLKERR;
+        assert_eq!(codec.clock_error(), Ok(Some(true)));
+    }
+}
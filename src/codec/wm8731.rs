@@ -0,0 +1,238 @@
+//! Wolfson/Cirrus WM8731 driver
+//!
+//! The control interface is write only, each write carrying a 7 bits register address and 9
+//! bits of data. Only the DAC to headphone and line outputs is used, MCLK comes from the I2S
+//! master without the crystal oscillator.
+
+use embedded_hal::blocking::i2c::Write;
+
+use super::*;
+
+/// Address with CSB low
+pub const ADDRESS: u8 = 0x1A;
+
+// registers
+const LEFT_HP_OUT: u8 = 0x02;
+const ANALOGUE_PATH: u8 = 0x04;
+const DIGITAL_PATH: u8 = 0x05;
+const POWER_DOWN: u8 = 0x06;
+const INTERFACE: u8 = 0x07;
+const SAMPLING: u8 = 0x08;
+const ACTIVE: u8 = 0x09;
+const RESET: u8 = 0x0F;
+
+// headphone volume, 1 dB steps, 0 dB code and range
+const LRHPBOTH: u16 = 1 << 8;
+const HP_0DB: i16 = 0x79;
+const HP_MIN_DB: i8 = -73;
+const HP_MAX_DB: i8 = 6;
+
+// analogue path, DAC selected and microphone muted
+const DACSEL_MUTEMIC: u16 = 0x12;
+// digital path
+const DACMU: u16 = 1 << 3;
+
+// power down, everything but the DAC path and the outputs
+const POWER_DAC: u16 = 0x67;
+const OUTPD: u16 = 1 << 4;
+const POWER_OFF: u16 = 0xFF;
+
+// sampling control: (sample rate, SR bits, CLKIDIV2) in normal mode, MCLK at 256 fs
+const RATES: [(u32, u16, bool); 6] = [
+    (8000, 0b0011, false),
+    (32000, 0b0110, false),
+    (44100, 0b1000, false),
+    (48000, 0b0000, false),
+    (88200, 0b1111, true),
+    (96000, 0b0111, true),
+];
+
+pub struct Wm8731<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: Write> Wm8731<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self::with_address(i2c, ADDRESS)
+    }
+
+    pub fn with_address(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    fn write(&mut self, register: u8, value: u16) -> Result<(), Error<I2C::Error>> {
+        let bytes = [(register << 1) | (value >> 8) as u8 & 1, value as u8];
+        self.i2c.write(self.address, &bytes).map_err(Error::Bus)
+    }
+}
+
+/// Digital audio interface format register for `format`, the codec being slave
+fn interface(format: Format) -> Result<u16, ()> {
+    let iwl = match format.data_bits {
+        16 => 0b00,
+        20 => 0b01,
+        24 => 0b10,
+        32 => 0b11,
+        _ => return Err(()),
+    };
+    if format.channel_bits != 16 && format.channel_bits != 32
+        || format.data_bits > format.channel_bits
+    {
+        return Err(());
+    }
+    let standard = match format.standard {
+        Standard::Lsb => 0b00,
+        Standard::Msb => 0b01,
+        Standard::Philips => 0b10,
+    };
+    Ok(iwl << 2 | standard)
+}
+
+/// Sampling control register for `sample_rate`
+fn sampling(sample_rate: u32) -> Option<u16> {
+    RATES
+        .iter()
+        .find(|(rate, ..)| *rate == sample_rate)
+        .map(|&(_, sr, clkidiv2)| (clkidiv2 as u16) << 6 | sr << 2)
+}
+
+impl<I2C> Codec for Wm8731<I2C>
+where
+    I2C: Write,
+    I2C::Error: Debug,
+{
+    type BusError = I2C::Error;
+
+    const NAME: &'static str = "WM8731";
+
+    // write only, a reset is the only harmless access
+    fn probe(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.write(RESET, 0)
+    }
+
+    fn init(&mut self, format: Format, sample_rate: u32) -> Result<(), Error<I2C::Error>> {
+        let interface = interface(format).map_err(|_| Error::Format)?;
+        let sampling = sampling(sample_rate).ok_or(Error::SampleRate)?;
+        self.write(RESET, 0)?;
+        // outputs are powered last to avoid pops
+        self.write(POWER_DOWN, POWER_DAC | OUTPD)?;
+        self.write(INTERFACE, interface)?;
+        self.write(SAMPLING, sampling)?;
+        self.write(ANALOGUE_PATH, DACSEL_MUTEMIC)?;
+        self.write(DIGITAL_PATH, DACMU)?;
+        self.write(ACTIVE, 1)?;
+        self.write(POWER_DOWN, POWER_DAC)
+    }
+
+    fn set_volume(&mut self, db: i8) -> Result<(), Error<I2C::Error>> {
+        let code = HP_0DB + db.clamp(HP_MIN_DB, HP_MAX_DB) as i16;
+        self.write(LEFT_HP_OUT, LRHPBOTH | code as u16)
+    }
+
+    fn set_mute(&mut self, mute: bool) -> Result<(), Error<I2C::Error>> {
+        self.write(DIGITAL_PATH, if mute { DACMU } else { 0 })
+    }
+
+    fn clock_error(&mut self) -> Result<Option<bool>, Error<I2C::Error>> {
+        Ok(None)
+    }
+
+    fn power_down(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.write(DIGITAL_PATH, DACMU)?;
+        self.write(ACTIVE, 0)?;
+        self.write(POWER_DOWN, POWER_OFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::mock::Bus;
+
+    const PHILIPS_24: Format = Format {
+        standard: Standard::Philips,
+        data_bits: 24,
+        channel_bits: 32,
+    };
+
+    // (register, value) of the writes
+    fn writes(bus: &Bus) -> impl Iterator<Item = (u8, u16)> + '_ {
+        bus.writes
+            .iter()
+            .map(|w| (w[0] >> 1, (w[0] as u16 & 1) << 8 | w[1] as u16))
+    }
+
+    #[test]
+    fn init_sequence() {
+        let mut codec = Wm8731::new(Bus::new(ADDRESS));
+        codec.init(PHILIPS_24, 48000).unwrap();
+        let bus = codec.release();
+        let expected = [
+            (RESET, 0),
+            (POWER_DOWN, 0x77),
+            (INTERFACE, 0b1010),
+            (SAMPLING, 0),
+            (ANALOGUE_PATH, 0x12),
+            (DIGITAL_PATH, 0x08),
+            (ACTIVE, 1),
+            (POWER_DOWN, 0x67),
+        ];
+        assert!(writes(&bus).eq(expected));
+    }
+
+    #[test]
+    fn high_rates_divide_mclk() {
+        assert_eq!(sampling(96000), Some(0x5C));
+        assert_eq!(sampling(44100), Some(0x20));
+        assert_eq!(sampling(22050), None);
+    }
+
+    #[test]
+    fn formats() {
+        let lsb_16 = Format {
+            standard: Standard::Lsb,
+            data_bits: 16,
+            channel_bits: 16,
+        };
+        assert_eq!(interface(lsb_16), Ok(0));
+        let too_wide = Format {
+            channel_bits: 16,
+            ..PHILIPS_24
+        };
+        assert_eq!(interface(too_wide), Err(()));
+    }
+
+    #[test]
+    fn nothing_written_on_bad_config() {
+        let mut codec = Wm8731::new(Bus::new(ADDRESS));
+        assert_eq!(codec.init(PHILIPS_24, 22050), Err(Error::SampleRate));
+        assert!(codec.release().writes.is_empty());
+    }
+
+    #[test]
+    fn nack_is_bus_error() {
+        let mut codec = Wm8731::with_address(Bus::new(ADDRESS), ADDRESS + 1);
+        assert_eq!(codec.probe(), Err(Error::Bus(())));
+        assert_eq!(codec.init(PHILIPS_24, 48000), Err(Error::Bus(())));
+    }
+
+    #[test]
+    fn volume_is_clamped() {
+        let mut codec = Wm8731::new(Bus::new(ADDRESS));
+        codec.set_volume(0).unwrap();
+        codec.set_volume(20).unwrap();
+        codec.set_volume(-100).unwrap();
+        let bus = codec.release();
+        let volumes: [(u8, u16); 3] = [
+            (LEFT_HP_OUT, 0x179),
+            (LEFT_HP_OUT, 0x17F),
+            (LEFT_HP_OUT, 0x130),
+        ];
+        assert!(writes(&bus).eq(volumes));
+    }
+}
@@ -0,0 +1,423 @@
+//! Crash records
+//!
+//! Without a probe attached, what the panic handler prints is lost. A panic or a HardFault
+//! also writes a [`CrashRecord`] to RAM left alone by the startup code, reported and cleared on
+//! the next boot. The record is kept encoded, so a dump of that RAM can be decoded on the host
+//! with `tools/decode` too.
+//!
+//! The encoding is little endian, with text fields padded to their capacity and a checksum
+//! of the previous bytes at the end.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+/// Marks a record written by a crash
+const MAGIC: u32 = 0x4352_5348;
+
+/// Capacity of the message, longer ones are truncated
+pub const MESSAGE_LEN: usize = 96;
+
+/// Capacity of the source file path, longer ones keep their end
+pub const FILE_LEN: usize = 48;
+
+/// Instances whose driver mode is recorded
+pub const INSTANCES: [&str; 4] = ["i2s1", "i2s2", "i2s3", "i2s5"];
+
+/// Driver modes, in `DriverMode::index()` order
+pub const MODES: [&str; 16] = [
+    "slave transmit 16 bits",
+    "master transmit 16 bits",
+    "slave receive 16 bits",
+    "master receive 16 bits",
+    "slave transmit 32 bits",
+    "master transmit 32 bits",
+    "slave receive 32 bits",
+    "master receive 32 bits",
+    "slave transmit TDM short",
+    "master transmit TDM short",
+    "slave receive TDM short",
+    "master receive TDM short",
+    "slave transmit TDM long",
+    "master transmit TDM long",
+    "slave receive TDM long",
+    "master receive TDM long",
+];
+
+/// No driver
+const NO_MODE: u8 = u8::MAX;
+
+/// No scenario running
+const NO_SCENARIO: u32 = u32::MAX;
+
+/// Length of an encoded record: magic, kind and text lengths, modes, 9 words, texts, checksum
+pub const RECORD_LEN: usize = 8 + INSTANCES.len() + 9 * 4 + MESSAGE_LEN + FILE_LEN + 4;
+
+static SCENARIO: AtomicU32 = AtomicU32::new(NO_SCENARIO);
+static DRIVER_MODES: [AtomicU8; 4] = [
+    AtomicU8::new(NO_MODE),
+    AtomicU8::new(NO_MODE),
+    AtomicU8::new(NO_MODE),
+    AtomicU8::new(NO_MODE),
+];
+
+/// Note the running scenario for a crash record.
+pub fn set_scenario(index: u32) {
+    SCENARIO.store(index, Ordering::Relaxed);
+}
+
+/// Note the mode of the driver of instance `name`, `None` without driver.
+pub fn set_driver_mode(name: &str, mode: Option<u8>) {
+    if let Some(slot) = INSTANCES.iter().position(|instance| *instance == name) {
+        DRIVER_MODES[slot].store(mode.unwrap_or(NO_MODE), Ordering::Relaxed);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Panic,
+    HardFault,
+}
+
+/// Fixed capacity text, truncated when written past its capacity
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Text<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Text<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole chars are written
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+
+    /// Keep the end of `s` if it's too long, a path tells more by its end.
+    fn set_tail(&mut self, s: &str) {
+        let mut start = s.len().saturating_sub(N);
+        while !s.is_char_boundary(start) {
+            start += 1;
+        }
+        self.len = s.len() - start;
+        self.buf[..self.len].copy_from_slice(&s.as_bytes()[start..]);
+    }
+}
+
+impl<const N: usize> Default for Text<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for Text<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = s.len().min(N - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buf[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+/// Fault status registers, and the stacked PC and LR for a HardFault
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct FaultStatus {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    pub pc: u32,
+    pub lr: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    /// Fewer than `RECORD_LEN` bytes
+    Short,
+    /// No crash recorded
+    NoRecord,
+    Checksum,
+    /// Field out of range
+    Invalid,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CrashRecord {
+    pub kind: Kind,
+    pub scenario: Option<u32>,
+    /// Mode of the driver of each of `INSTANCES`, as an index in `MODES`
+    pub modes: [Option<u8>; 4],
+    pub file: Text<FILE_LEN>,
+    pub line: u32,
+    pub column: u32,
+    pub fault: FaultStatus,
+    pub message: Text<MESSAGE_LEN>,
+}
+
+impl CrashRecord {
+    /// Record of a crash now, with the noted scenario and driver modes
+    pub fn new(kind: Kind) -> Self {
+        let scenario = SCENARIO.load(Ordering::Relaxed);
+        let mut modes = [None; 4];
+        for (mode, noted) in modes.iter_mut().zip(&DRIVER_MODES) {
+            *mode = Some(noted.load(Ordering::Relaxed)).filter(|mode| *mode != NO_MODE);
+        }
+        Self {
+            kind,
+            scenario: Some(scenario).filter(|scenario| *scenario != NO_SCENARIO),
+            modes,
+            file: Text::new(),
+            line: 0,
+            column: 0,
+            fault: FaultStatus::default(),
+            message: Text::new(),
+        }
+    }
+
+    pub fn set_location(&mut self, file: &str, line: u32, column: u32) {
+        self.file.set_tail(file);
+        self.line = line;
+        self.column = column;
+    }
+
+    pub fn encode(&self, buf: &mut [u8; RECORD_LEN]) {
+        let mut w = Writer { buf, pos: 0 };
+        w.bytes(&MAGIC.to_le_bytes());
+        w.bytes(&[
+            self.kind as u8,
+            self.message.len as u8,
+            self.file.len as u8,
+            0,
+        ]);
+        for mode in self.modes {
+            w.bytes(&[mode.unwrap_or(NO_MODE)]);
+        }
+        let fault = &self.fault;
+        for word in [
+            self.scenario.unwrap_or(NO_SCENARIO),
+            self.line,
+            self.column,
+            fault.cfsr,
+            fault.hfsr,
+            fault.mmfar,
+            fault.bfar,
+            fault.pc,
+            fault.lr,
+        ] {
+            w.bytes(&word.to_le_bytes());
+        }
+        w.bytes(&self.message.buf);
+        w.bytes(&self.file.buf);
+        let sum = checksum(&w.buf[..w.pos]);
+        w.bytes(&sum.to_le_bytes());
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let buf = buf.get(..RECORD_LEN).ok_or(DecodeError::Short)?;
+        let mut r = Reader { buf, pos: 0 };
+        if r.word() != MAGIC {
+            return Err(DecodeError::NoRecord);
+        }
+        let (body, sum) = buf.split_at(RECORD_LEN - 4);
+        if checksum(body).to_le_bytes() != sum {
+            return Err(DecodeError::Checksum);
+        }
+        let [kind, message_len, file_len, _] = r.array();
+        let kind = match kind {
+            0 => Kind::Panic,
+            1 => Kind::HardFault,
+            _ => return Err(DecodeError::Invalid),
+        };
+        let mut modes = [None; 4];
+        for mode in modes.iter_mut() {
+            let [index] = r.array();
+            *mode = match index {
+                NO_MODE => None,
+                index if (index as usize) < MODES.len() => Some(index),
+                _ => return Err(DecodeError::Invalid),
+            };
+        }
+        let scenario = Some(r.word()).filter(|scenario| *scenario != NO_SCENARIO);
+        let (line, column) = (r.word(), r.word());
+        let fault = FaultStatus {
+            cfsr: r.word(),
+            hfsr: r.word(),
+            mmfar: r.word(),
+            bfar: r.word(),
+            pc: r.word(),
+            lr: r.word(),
+        };
+        let message = r.text(message_len)?;
+        let file = r.text(file_len)?;
+        Ok(Self {
+            kind,
+            scenario,
+            modes,
+            file,
+            line,
+            column,
+            fault,
+            message,
+        })
+    }
+}
+
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::Panic => write!(f, "panic")?,
+            Kind::HardFault => write!(f, "HardFault")?,
+        }
+        if let Some(scenario) = self.scenario {
+            write!(f, " in scenario #{}", scenario)?;
+        }
+        if self.file.len > 0 {
+            write!(
+                f,
+                " at {}:{}:{}",
+                self.file.as_str(),
+                self.line,
+                self.column
+            )?;
+        }
+        if self.message.len > 0 {
+            write!(f, ": {}", self.message.as_str())?;
+        }
+        for (name, mode) in INSTANCES.iter().zip(self.modes) {
+            if let Some(mode) = mode {
+                write!(f, "\n  {} {}", name, MODES[mode as usize])?;
+            }
+        }
+        if self.kind == Kind::HardFault {
+            let fault = &self.fault;
+            write!(
+                f,
+                "\n  PC {:#010x} LR {:#010x} CFSR {:#010x} HFSR {:#010x} MMFAR {:#010x} BFAR {:#010x}",
+                fault.pc, fault.lr, fault.cfsr, fault.hfsr, fault.mmfar, fault.bfar
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C_9DC5, |sum, byte| {
+        (sum ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8; RECORD_LEN],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let mut array = [0; N];
+        array.copy_from_slice(&self.buf[self.pos..self.pos + N]);
+        self.pos += N;
+        array
+    }
+
+    fn word(&mut self) -> u32 {
+        u32::from_le_bytes(self.array())
+    }
+
+    fn text<const N: usize>(&mut self, len: u8) -> Result<Text<N>, DecodeError> {
+        let buf = self.array();
+        let len = len as usize;
+        if len > N || core::str::from_utf8(&buf[..len]).is_err() {
+            return Err(DecodeError::Invalid);
+        }
+        Ok(Text { buf, len })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    fn record() -> CrashRecord {
+        let mut record = CrashRecord::new(Kind::HardFault);
+        record.scenario = Some(12);
+        record.modes = [None, Some(6), Some(5), None];
+        record.set_location("src/driver_wrap.rs", 421, 9);
+        write!(record.message, "index out of bounds").unwrap();
+        record.fault = FaultStatus {
+            cfsr: 0x0000_8200,
+            hfsr: 0x4000_0000,
+            mmfar: 0xE000_ED34,
+            bfar: 0x2002_0000,
+            pc: 0x0800_1234,
+            lr: 0xFFFF_FFF9,
+        };
+        record
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = record();
+        let mut buf = [0; RECORD_LEN];
+        record.encode(&mut buf);
+        assert_eq!(CrashRecord::decode(&buf), Ok(record));
+    }
+
+    #[test]
+    fn rejects_damaged_records() {
+        let mut buf = [0; RECORD_LEN];
+        assert_eq!(CrashRecord::decode(&buf), Err(DecodeError::NoRecord));
+        record().encode(&mut buf);
+        assert_eq!(
+            CrashRecord::decode(&buf[..RECORD_LEN - 1]),
+            Err(DecodeError::Short)
+        );
+        buf[20] ^= 1;
+        assert_eq!(CrashRecord::decode(&buf), Err(DecodeError::Checksum));
+    }
+
+    #[test]
+    fn truncated_text() {
+        let mut text = Text::<4>::new();
+        write!(text, "ab").unwrap();
+        // the 2 bytes 'é' doesn't fit after "abc"
+        write!(text, "cé").unwrap();
+        assert_eq!(text.as_str(), "abc");
+        let mut path = Text::<8>::new();
+        path.set_tail("a/long/path/main.rs");
+        assert_eq!(path.as_str(), "/main.rs");
+    }
+
+    #[test]
+    fn display() {
+        let mut s = Text::<256>::new();
+        write!(s, "{}", record()).unwrap();
+        assert_eq!(
+            s.as_str(),
+            "HardFault in scenario #12 at src/driver_wrap.rs:421:9: index out of bounds\n  \
+             i2s2 slave receive 32 bits\n  i2s3 master transmit 32 bits\n  \
+             PC 0x08001234 LR 0xfffffff9 CFSR 0x00008200 HFSR 0x40000000 MMFAR 0xe000ed34 \
+             BFAR 0x20020000"
+        );
+    }
+}
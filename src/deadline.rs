@@ -0,0 +1,163 @@
+//! Deadlines bounding the waits of scenarios
+//!
+//! A dead peer or a miswired pin must fail a scenario, not hang the whole run. Every wait goes
+//! through a [`Deadline`] counted on a free running clock, the DWT cycle counter on target, so
+//! the scenario can tear its peripherals down and report the timeout.
+
+/// The awaited condition didn't come before the deadline
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TimedOut;
+
+pub struct Deadline {
+    clock: fn() -> u32,
+    start: u32,
+    cycles: u32,
+}
+
+impl Deadline {
+    /// Deadline `cycles` ticks of `clock` from now, the clock wrapping around.
+    pub fn new(clock: fn() -> u32, cycles: u32) -> Self {
+        Self {
+            clock,
+            start: clock(),
+            cycles,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        (self.clock)().wrapping_sub(self.start) > self.cycles
+    }
+
+    /// Spin until `done` returns `true`.
+    pub fn wait(&self, mut done: impl FnMut() -> bool) -> Result<(), TimedOut> {
+        while !done() {
+            if self.is_expired() {
+                return Err(TimedOut);
+            }
+        }
+        Ok(())
+    }
+
+    /// Retry a non blocking `op` until it succeeds.
+    pub fn retry<T, E>(&self, mut op: impl FnMut() -> Result<T, E>) -> Result<T, TimedOut> {
+        loop {
+            if let Ok(value) = op() {
+                return Ok(value);
+            }
+            if self.is_expired() {
+                return Err(TimedOut);
+            }
+        }
+    }
+
+    /// Items of `iter` until the deadline expires, for blocking calls pulling from an iterator.
+    /// The deadline is only checked between items.
+    pub fn until<I: Iterator>(&self, iter: I) -> Until<'_, I> {
+        Until {
+            deadline: self,
+            iter,
+            expired: false,
+        }
+    }
+}
+
+/// Iterator cut short by a [`Deadline`], see [`Deadline::until`]
+pub struct Until<'a, I> {
+    deadline: &'a Deadline,
+    iter: I,
+    expired: bool,
+}
+
+impl<I> Until<'_, I> {
+    /// `Err` if the deadline cut the iterator short.
+    pub fn result(&self) -> Result<(), TimedOut> {
+        if self.expired {
+            Err(TimedOut)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for Until<'_, I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        if self.expired || self.deadline.is_expired() {
+            self.expired = true;
+            return None;
+        }
+        self.iter.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn wait_times_out() {
+        static NOW: AtomicU32 = AtomicU32::new(u32::MAX - 5);
+        fn clock() -> u32 {
+            NOW.fetch_add(1, Ordering::Relaxed)
+        }
+        let deadline = Deadline::new(clock, 10);
+        assert_eq!(deadline.wait(|| false), Err(TimedOut));
+        // the clock wrapped around meanwhile
+        assert!(NOW.load(Ordering::Relaxed) < 10);
+    }
+
+    #[test]
+    fn wait_done_in_time() {
+        static NOW: AtomicU32 = AtomicU32::new(0);
+        fn clock() -> u32 {
+            NOW.fetch_add(1, Ordering::Relaxed)
+        }
+        let deadline = Deadline::new(clock, 10);
+        let mut polls = 0;
+        assert_eq!(
+            deadline.wait(|| {
+                polls += 1;
+                polls == 3
+            }),
+            Ok(())
+        );
+        assert!(!deadline.is_expired());
+    }
+
+    #[test]
+    fn retry_returns_value() {
+        static NOW: AtomicU32 = AtomicU32::new(0);
+        fn clock() -> u32 {
+            NOW.fetch_add(1, Ordering::Relaxed)
+        }
+        let deadline = Deadline::new(clock, 10);
+        let mut tries = 0;
+        let op = || {
+            tries += 1;
+            if tries < 4 {
+                Err(())
+            } else {
+                Ok(tries)
+            }
+        };
+        assert_eq!(deadline.retry(op), Ok(4));
+        assert_eq!(deadline.retry(|| Err::<(), _>(())), Err(TimedOut));
+    }
+
+    #[test]
+    fn until_stops_at_deadline() {
+        static NOW: AtomicU32 = AtomicU32::new(0);
+        fn clock() -> u32 {
+            NOW.fetch_add(1, Ordering::Relaxed)
+        }
+        let deadline = Deadline::new(clock, 10);
+        let mut items = deadline.until(0..3);
+        assert_eq!(items.by_ref().count(), 3);
+        assert_eq!(items.result(), Ok(()));
+        let mut items = deadline.until(0..);
+        assert!(items.by_ref().count() < 10);
+        assert_eq!(items.result(), Err(TimedOut));
+    }
+}
@@ -1,12 +1,32 @@
 use crate::app::log;
+#[cfg(has_i2s1)]
+use crate::app::I2s1;
+#[cfg(has_i2s5)]
+use crate::app::I2s5;
 use crate::app::{I2s2, I2s3};
+use crate::block::{BlockDrainer, BlockFiller, BLOCK_LEN};
+use crate::board;
+use crate::channel::{ChannelMode, Sample};
+use crate::crash;
+use crate::deadline::{Deadline, TimedOut};
+use crate::hal::bb;
 use crate::hal::gpio::ExtiPin;
 use crate::hal::i2s::stm32_i2s_v12x::driver::*;
 use crate::hal::i2s::stm32_i2s_v12x::I2sPeripheral;
 use crate::hal::pac::DWT;
-use crate::hal::pac::EXTI;
+#[cfg(has_i2s1)]
+use crate::hal::pac::SPI1;
+#[cfg(has_i2s5)]
+use crate::hal::pac::SPI5;
+use crate::hal::pac::{exti, EXTI};
+use crate::hal::pac::{Interrupt, RCC, SPI2, SPI3};
+use crate::hal::rcc::Reset;
+use crate::recovery::{Recover, Recovery, RecoveryPolicy, RecoveryStats};
+use crate::snapshot::{RccRegs, Snapshot, SpiRegs};
+use crate::sync::{SlaveSync, SyncStats, SyncStrategy};
+use crate::ws_capture::WsLine;
+use crate::SYSCLK_HZ;
 use heapless::spsc::*;
-use rtic::mutex::prelude::*;
 
 type I2sStd = Philips;
 
@@ -20,304 +40,863 @@ enum FrameState {
 }
 use FrameState::*;
 
-pub enum DriverMode<I> {
+/// Most slots in a TDM frame
+pub const MAX_SLOTS: usize = 8;
+
+/// Samples of a TDM frame, left aligned, slots past the configured count stay zero
+pub type SlotFrame = [i32; MAX_SLOTS];
+
+// Position in a TDM frame. PCM standards have no channel side and the I2S block emits a frame
+// sync for every slot, so slots are counted from the first word after enable.
+#[derive(Copy, Clone)]
+struct SlotState {
+    slots: usize,
+    // 16 bits words per slot
+    words: usize,
+    // next word of the frame
+    pos: usize,
+    frame: [u32; MAX_SLOTS],
+}
+
+impl SlotState {
+    const fn new(slots: usize, words: usize) -> Self {
+        Self {
+            slots,
+            words,
+            pos: 0,
+            frame: [0; MAX_SLOTS],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.frame = [0; MAX_SLOTS];
+    }
+
+    fn advance(&mut self) -> (usize, bool) {
+        let (slot, msb) = (self.pos / self.words, self.pos % self.words == 0);
+        self.pos = (self.pos + 1) % (self.slots * self.words);
+        (slot, msb)
+    }
+
+    // next word to transmit, a new frame is popped on the first one
+    fn next_word(&mut self, tdm_c: &mut Consumer<'static, SlotFrame, 8>) -> u16 {
+        if self.pos == 0 {
+            let frame = tdm_c.dequeue().unwrap_or_default();
+            for (slot, sample) in self.frame.iter_mut().zip(frame) {
+                *slot = sample as u32;
+            }
+        }
+        let (slot, msb) = self.advance();
+        if msb {
+            (self.frame[slot] >> 16) as u16
+        } else {
+            self.frame[slot] as u16
+        }
+    }
+
+    // store a received word, the frame is returned once complete
+    fn store_word(&mut self, data: u16) -> Option<SlotFrame> {
+        let (slot, msb) = self.advance();
+        if msb {
+            self.frame[slot] = (data as u32) << 16;
+        } else {
+            self.frame[slot] |= data as u32;
+        }
+        if self.pos != 0 {
+            return None;
+        }
+        let mut frame = [0; MAX_SLOTS];
+        for (sample, slot) in frame.iter_mut().zip(self.frame) {
+            *sample = slot as i32;
+        }
+        Some(frame)
+    }
+}
+
+/// Transmit modes
+pub enum TxMode<I> {
     SlaveTransmit16bits(I2sDriver<I, Slave, Transmit, I2sStd>),
     MasterTransmit16bits(I2sDriver<I, Master, Transmit, I2sStd>),
-    SlaveReceive16bits(I2sDriver<I, Slave, Receive, I2sStd>),
-    MasterReceive16bits(I2sDriver<I, Master, Receive, I2sStd>),
     SlaveTransmit32bits(I2sDriver<I, Slave, Transmit, I2sStd>),
     MasterTransmit32bits(I2sDriver<I, Master, Transmit, I2sStd>),
+    /// TDM slots over PCM short frame sync, see `DriverWrap::set_slots()`
+    SlaveTransmitTdmShort(I2sDriver<I, Slave, Transmit, PcmShortSync>),
+    MasterTransmitTdmShort(I2sDriver<I, Master, Transmit, PcmShortSync>),
+    /// TDM slots over PCM long frame sync, see `DriverWrap::set_slots()`
+    SlaveTransmitTdmLong(I2sDriver<I, Slave, Transmit, PcmLongSync>),
+    MasterTransmitTdmLong(I2sDriver<I, Master, Transmit, PcmLongSync>),
+}
+use TxMode::*;
+
+/// Receive modes
+pub enum RxMode<I> {
+    SlaveReceive16bits(I2sDriver<I, Slave, Receive, I2sStd>),
+    MasterReceive16bits(I2sDriver<I, Master, Receive, I2sStd>),
     SlaveReceive32bits(I2sDriver<I, Slave, Receive, I2sStd>),
     MasterReceive32bits(I2sDriver<I, Master, Receive, I2sStd>),
+    /// TDM slots over PCM short frame sync, see `DriverWrap::set_slots()`
+    SlaveReceiveTdmShort(I2sDriver<I, Slave, Receive, PcmShortSync>),
+    MasterReceiveTdmShort(I2sDriver<I, Master, Receive, PcmShortSync>),
+    /// TDM slots over PCM long frame sync, see `DriverWrap::set_slots()`
+    SlaveReceiveTdmLong(I2sDriver<I, Slave, Receive, PcmLongSync>),
+    MasterReceiveTdmLong(I2sDriver<I, Master, Receive, PcmLongSync>),
+}
+use RxMode::*;
+
+/// Driver held by a `DriverWrap`, only served by the interrupt handlers of its direction
+pub enum DriverMode<I> {
+    Tx(TxMode<I>),
+    Rx(RxMode<I>),
 }
 use DriverMode::*;
 
-pub struct DriverWrap<I> {
-    drv: Option<DriverMode<I>>,
+impl<I> From<TxMode<I>> for DriverMode<I> {
+    fn from(mode: TxMode<I>) -> Self {
+        Tx(mode)
+    }
+}
+
+impl<I> From<RxMode<I>> for DriverMode<I> {
+    fn from(mode: RxMode<I>) -> Self {
+        Rx(mode)
+    }
+}
+
+impl<I> DriverMode<I> {
+    /// Index of the mode, transmit then receive for each of 16 bits, 32 bits, TDM short and TDM
+    /// long, slave before master. Used by crash records.
+    pub fn index(&self) -> u8 {
+        match self {
+            Tx(SlaveTransmit16bits(_)) => 0,
+            Tx(MasterTransmit16bits(_)) => 1,
+            Rx(SlaveReceive16bits(_)) => 2,
+            Rx(MasterReceive16bits(_)) => 3,
+            Tx(SlaveTransmit32bits(_)) => 4,
+            Tx(MasterTransmit32bits(_)) => 5,
+            Rx(SlaveReceive32bits(_)) => 6,
+            Rx(MasterReceive32bits(_)) => 7,
+            Tx(SlaveTransmitTdmShort(_)) => 8,
+            Tx(MasterTransmitTdmShort(_)) => 9,
+            Rx(SlaveReceiveTdmShort(_)) => 10,
+            Rx(MasterReceiveTdmShort(_)) => 11,
+            Tx(SlaveTransmitTdmLong(_)) => 12,
+            Tx(MasterTransmitTdmLong(_)) => 13,
+            Rx(SlaveReceiveTdmLong(_)) => 14,
+            Rx(MasterReceiveTdmLong(_)) => 15,
+        }
+    }
+}
+
+/// Interrupt a `DriverWrap` has no driver to serve, masked and counted
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Unhandled {
+    /// No driver
+    Empty,
+    /// Driver of the other direction
+    WrongDirection,
+    /// WS edge without a slave waiting for it
+    NoSlave,
+}
+
+impl Unhandled {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unhandled::Empty => "Interrupt without driver, masked",
+            Unhandled::WrongDirection => "Interrupt for the other direction, masked",
+            Unhandled::NoSlave => "WS edge without slave, masked",
+        }
+    }
+}
+
+/// Destination of received frames
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum RxSink {
+    /// Frame by frame, through the `(time, (l, r))` queues
+    Queue,
+    /// By blocks of `BLOCK_LEN` frames, through the block pool
+    Block,
+}
+
+/// Origin of transmitted frames
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum TxSource {
+    /// Frame by frame, through the `(l, r)` queues
+    Queue,
+    /// By blocks of `BLOCK_LEN` frames, through the block pool
+    Block,
+}
+
+/// Something transmit handlers can pop frames from
+pub trait FrameSource<T> {
+    /// Get the next frame to transmit, if any.
+    fn pop(&mut self) -> Option<(T, T)>;
+}
+
+impl<T, const N: usize> FrameSource<T> for Consumer<'static, (T, T), N> {
+    fn pop(&mut self) -> Option<(T, T)> {
+        self.dequeue()
+    }
+}
+
+// Channel mode of a driver and the sample filling idle or muted channels
+#[derive(Copy, Clone)]
+struct Channels {
+    mode: ChannelMode,
+    fill: i32,
+}
+
+impl Channels {
+    fn tx<'a, S>(
+        self,
+        frames: &'a mut S,
+        mono_c: &'a mut Consumer<'static, i32, 8>,
+    ) -> TxFrames<'a, S> {
+        TxFrames {
+            frames,
+            mono_c,
+            channels: self,
+        }
+    }
+
+    fn rx<'a, S>(
+        self,
+        frames: &'a mut S,
+        mono_p: &'a mut Producer<'static, (u32, i32), 8>,
+    ) -> RxFrames<'a, S> {
+        RxFrames {
+            frames,
+            mono_p,
+            channels: self,
+        }
+    }
+}
+
+// frames to transmit, built from mono samples or masked stereo frames
+struct TxFrames<'a, S> {
+    frames: &'a mut S,
+    mono_c: &'a mut Consumer<'static, i32, 8>,
+    channels: Channels,
+}
+
+impl<'a, T: Sample, S: FrameSource<T>> FrameSource<T> for TxFrames<'a, S> {
+    fn pop(&mut self) -> Option<(T, T)> {
+        let Channels { mode, fill } = self.channels;
+        let fill = T::from_aligned(fill);
+        if mode.is_mono() {
+            let sample = T::from_aligned(self.mono_c.dequeue()?);
+            Some(mode.frame_of(sample, fill))
+        } else {
+            Some(mode.mask(self.frames.pop()?, fill))
+        }
+    }
+}
+
+// received frames, stored as mono samples or masked stereo frames
+struct RxFrames<'a, S> {
+    frames: &'a mut S,
+    mono_p: &'a mut Producer<'static, (u32, i32), 8>,
+    channels: Channels,
+}
+
+impl<'a, T: Sample, S: FrameSink<T>> FrameSink<T> for RxFrames<'a, S> {
+    fn push(&mut self, time: u32, frame: (T, T)) -> bool {
+        let Channels { mode, fill } = self.channels;
+        if mode.is_mono() {
+            let sample = mode.sample_of(frame).to_aligned();
+            self.mono_p.enqueue((time, sample)).is_ok()
+        } else {
+            self.frames
+                .push(time, mode.mask(frame, T::from_aligned(fill)))
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        if self.channels.mode.is_mono() {
+            !self.mono_p.ready()
+        } else {
+            self.frames.is_full()
+        }
+    }
+}
+
+/// Something receive handlers can push frames to
+pub trait FrameSink<T> {
+    /// Store a received frame, return `false` if it was dropped.
+    fn push(&mut self, time: u32, frame: (T, T)) -> bool;
+    /// `true` when no more frame can be stored.
+    fn is_full(&self) -> bool;
+}
+
+impl<T, const N: usize> FrameSink<T> for Producer<'static, (u32, (T, T)), N> {
+    fn push(&mut self, time: u32, frame: (T, T)) -> bool {
+        self.enqueue((time, frame)).is_ok()
+    }
+
+    fn is_full(&self) -> bool {
+        !self.ready()
+    }
+}
+
+/// Frames to transmit, consumed by whichever instance transmits
+pub struct TxData {
+    pub data_16_c: Consumer<'static, (i16, i16), 8>,
+    pub data_32_c: Consumer<'static, (i32, i32), 8>,
+    pub blocks_d: BlockDrainer<'static, BLOCK_LEN>,
+    pub tdm_c: Consumer<'static, SlotFrame, 8>,
+    pub mono_c: Consumer<'static, i32, 8>,
+}
+
+/// Received frames, produced by whichever instance receives
+pub struct RxData {
+    pub data_16_p: Producer<'static, (u32, (i16, i16)), 8>,
+    pub data_32_p: Producer<'static, (u32, (i32, i32)), 8>,
+    pub blocks_f: BlockFiller<'static, BLOCK_LEN>,
+    pub tdm_p: Producer<'static, (u32, SlotFrame), 8>,
+    pub mono_p: Producer<'static, (u32, i32), 8>,
+}
+
+/// I2S instances `DriverWrap` and the scenarios can drive, with a WS pin able to trigger EXTI
+/// for slave synchronisation
+pub trait I2sInstance: I2sPeripheral {
+    const NAME: &'static str;
+    /// Interrupt of the SPI peripheral
+    const INTERRUPT: Interrupt;
+    /// Timer input the WS line is wired to, if any
+    const WS_CAPTURE: Option<WsLine>;
+    /// Reset the SPI peripheral through RCC.
+    fn reset_peripheral();
+    /// SPI/I2S registers as programmed now
+    fn registers() -> SpiRegs;
+    fn ws_is_high(&mut self) -> bool;
+    /// Clear the WS EXTI pending bit, return `true` if it was set. WS pins of several instances
+    /// can share an EXTI interrupt.
+    fn ws_take_interrupt(&mut self) -> bool;
+    fn ws_enable_interrupt(&mut self, exti: &mut EXTI);
+    fn ws_disable_interrupt(&mut self, exti: &mut EXTI);
+    /// Unmask or mask the WS EXTI line whoever owns EXTI, for the handlers.
+    fn ws_listen(enable: bool);
+    /// Mask the SPI interrupts through the registers, whoever owns the peripheral.
+    fn mask_interrupts();
+    /// Mask the WS EXTI line and clear its pending bit, whoever owns the pin. Return `true` if
+    /// it was pending.
+    fn ws_mask() -> bool;
+    /// WS pin level, whoever owns the pin.
+    fn ws_level() -> bool;
+
+    /// Wait for WS to go through both levels, the master of the bus is then clocking. A blocking
+    /// slave transfer started before that never returns if the master is dead.
+    fn wait_ws_clocking(deadline: &Deadline) -> Result<(), TimedOut> {
+        deadline.wait(Self::ws_level)?;
+        deadline.wait(|| !Self::ws_level())
+    }
+}
+
+// IMR is bit-banded, so changing a line never clobbers the others and the handlers need no lock
+// on EXTI to mask or unmask their own line
+fn listen_line(exti: &exti::RegisterBlock, line: u8, enable: bool) {
+    unsafe { bb::write(&exti.imr, line, enable) }
+}
+
+macro_rules! i2s_instance {
+    ($I2s:ty, $SPI:ident, $name:literal, $ws:expr, $capture:expr) => {
+        impl I2sInstance for $I2s {
+            const NAME: &'static str = $name;
+            const INTERRUPT: Interrupt = Interrupt::$SPI;
+            const WS_CAPTURE: Option<WsLine> = $capture;
+
+            fn reset_peripheral() {
+                unsafe {
+                    let rcc = &(*RCC::ptr());
+                    <$SPI>::reset(rcc);
+                }
+            }
+
+            fn registers() -> SpiRegs {
+                let spi = unsafe { &(*<$SPI>::ptr()) };
+                SpiRegs {
+                    cr1: spi.cr1.read().bits(),
+                    cr2: spi.cr2.read().bits(),
+                    sr: spi.sr.read().bits(),
+                    i2scfgr: spi.i2scfgr.read().bits(),
+                    i2spr: spi.i2spr.read().bits(),
+                }
+            }
+
+            fn ws_is_high(&mut self) -> bool {
+                self.ws_pin_mut().is_high()
+            }
+
+            fn ws_take_interrupt(&mut self) -> bool {
+                let ws_pin = self.ws_pin_mut();
+                let pending = ws_pin.check_interrupt();
+                ws_pin.clear_interrupt_pending_bit();
+                pending
+            }
+
+            fn ws_enable_interrupt(&mut self, exti: &mut EXTI) {
+                listen_line(exti, $ws.pin, true);
+            }
+
+            fn ws_disable_interrupt(&mut self, exti: &mut EXTI) {
+                listen_line(exti, $ws.pin, false);
+            }
+
+            fn ws_listen(enable: bool) {
+                listen_line(unsafe { &(*EXTI::ptr()) }, $ws.pin, enable);
+            }
+
+            fn mask_interrupts() {
+                let spi = unsafe { &(*<$SPI>::ptr()) };
+                spi.cr2.modify(|_, w| {
+                    w.txeie()
+                        .clear_bit()
+                        .rxneie()
+                        .clear_bit()
+                        .errie()
+                        .clear_bit()
+                });
+            }
+
+            fn ws_mask() -> bool {
+                let exti = unsafe { &(*EXTI::ptr()) };
+                let line = 1 << $ws.pin;
+                listen_line(exti, $ws.pin, false);
+                let pending = exti.pr.read().bits() & line != 0;
+                // cleared by writing 1
+                exti.pr.write(|w| unsafe { w.bits(line) });
+                pending
+            }
+
+            fn ws_level() -> bool {
+                $ws.is_high()
+            }
+        }
+    };
+}
+
+i2s_instance!(I2s2, SPI2, "i2s2", board::I2S2_LINES[0], Some(WsLine::I2s2));
+i2s_instance!(I2s3, SPI3, "i2s3", board::I2S3_LINES[0], Some(WsLine::I2s3));
+#[cfg(has_i2s1)]
+i2s_instance!(I2s1, SPI1, "i2s1", board::I2S1_LINES[0], None);
+#[cfg(has_i2s5)]
+i2s_instance!(I2s5, SPI5, "i2s5", board::I2S5_LINES[0], None);
+
+/// Registers of instances `A` and `B` and of the clock tree, as programmed now
+pub fn snapshot<A: I2sInstance, B: I2sInstance>() -> Snapshot {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    Snapshot {
+        instances: [(A::NAME, A::registers()), (B::NAME, B::registers())],
+        rcc: RccRegs {
+            hse_hz: board::HSE_HZ,
+            cr: rcc.cr.read().bits(),
+            pllcfgr: rcc.pllcfgr.read().bits(),
+            cfgr: rcc.cfgr.read().bits(),
+            plli2scfgr: rcc.plli2scfgr.read().bits(),
+        },
+    }
+}
+
+/// Errors seen by the interrupt handlers
+#[derive(Copy, Clone, Default, Debug)]
+pub struct ErrorCounters {
+    /// Underruns
+    pub udr: u32,
+    /// Overruns
+    pub ovr: u32,
+    /// Frame errors
+    pub fre: u32,
+    /// Data on the unexpected channel
+    pub channel: u32,
+    /// Master stops that timed out, the peripheral being disabled anyway
+    pub stop: u32,
+}
+
+// Bookkeeping of the interrupt handlers
+struct HandlerState {
     frame_state: FrameState,
     frame: (u32, u32),
+    errors: ErrorCounters,
+    sync: SlaveSync,
+    recovery: Recovery,
+    slots: SlotState,
 }
 
-fn _slave_transmit_16bits_interrupt(
-    driver: &mut I2sDriver<I2s3, Slave, Transmit, I2sStd>,
-    exti: &mut impl Mutex<T = EXTI>,
-    frame_state: &mut FrameState,
-    frame: &mut (u32, u32),
-    data_16_c: &mut Consumer<'static, (i16, i16), 8>,
+pub struct DriverWrap<I> {
+    drv: Option<DriverMode<I>>,
+    state: HandlerState,
+    rx_sink: RxSink,
+    tx_source: TxSource,
+    channels: Channels,
+    // block pool still holds data from a previous use
+    blocks_stale: bool,
+    // interrupts masked for lack of a driver to serve them
+    unhandled: u32,
+}
+
+fn _slave_transmit_16bits_interrupt<I: I2sInstance>(
+    driver: &mut I2sDriver<I, Slave, Transmit, I2sStd>,
+    state: &mut HandlerState,
+    data_16_c: &mut impl FrameSource<i16>,
 ) {
     let status = driver.status();
+    let mut restart = false;
     // it's better to write data first to avoid to trigger udr flag
     if status.txe() {
         let data;
-        match (*frame_state, status.chside()) {
+        match (state.frame_state, status.chside()) {
             (LeftMsb, Channel::Left) => {
-                let (l, r) = data_16_c.dequeue().unwrap_or_default();
-                *frame = (l as u32, r as u32);
-                data = (frame.0 & 0xFFFF) as u16;
-                *frame_state = RightMsb;
+                let (l, r) = data_16_c.pop().unwrap_or_default();
+                state.frame = (l as u32, r as u32);
+                data = (state.frame.0 & 0xFFFF) as u16;
+                state.frame_state = RightMsb;
             }
             (RightMsb, Channel::Right) => {
-                data = (frame.1 & 0xFFFF) as u16;
-                *frame_state = LeftMsb;
+                data = (state.frame.1 & 0xFFFF) as u16;
+                state.frame_state = LeftMsb;
+                state.recovery.on_frame();
             }
             // in case of udr this resynchronize tracked and actual channel
             _ => {
-                *frame_state = LeftMsb;
+                state.errors.channel += 1;
+                restart |= state.recovery.on_desync() == Recover::Restart;
+                state.frame_state = LeftMsb;
                 data = 0; //garbage data to avoid additional underrrun
             }
         }
         driver.write_data_register(data);
     }
     if status.fre() {
-        log::spawn(DWT::cycle_count(), "i2s3 Frame error").ok();
+        state.errors.fre += 1;
+        log::spawn(DWT::cycle_count(), "Slave Frame error").ok();
+        restart |= state.recovery.on_frame_error() == Recover::Restart;
+    }
+    // wait the next WS edge to enable again
+    if restart {
         driver.disable();
-        exti.lock(|exti| {
-            driver
-                .i2s_peripheral_mut()
-                .ws_pin_mut()
-                .enable_interrupt(exti)
-        })
+        state.frame_state = LeftMsb;
+        state.sync.arm(DWT::cycle_count());
+        if state.sync.strategy() == SyncStrategy::Exti {
+            I::ws_listen(true);
+        }
     }
     if status.udr() {
-        log::spawn(DWT::cycle_count(), "i2s3 udr").ok();
+        state.errors.udr += 1;
+        log::spawn(DWT::cycle_count(), "Slave Transmit Underrun").ok();
         driver.status();
         driver.write_data_register(0);
     }
 }
 
-fn _slave_transmit_32bits_interrupt(
-    driver: &mut I2sDriver<I2s3, Slave, Transmit, I2sStd>,
-    exti: &mut impl Mutex<T = EXTI>,
-    frame_state: &mut FrameState,
-    frame: &mut (u32, u32),
-    data_32_c: &mut Consumer<'static, (i32, i32), 8>,
+fn _slave_transmit_32bits_interrupt<I: I2sInstance>(
+    driver: &mut I2sDriver<I, Slave, Transmit, I2sStd>,
+    state: &mut HandlerState,
+    data_32_c: &mut impl FrameSource<i32>,
 ) {
     let status = driver.status();
+    let mut restart = false;
     // it's better to write data first to avoid to trigger udr flag
     if status.txe() {
         let data;
-        match (*frame_state, status.chside()) {
+        match (state.frame_state, status.chside()) {
             (LeftMsb, Channel::Left) => {
-                let (l, r) = data_32_c.dequeue().unwrap_or_default();
-                *frame = (l as u32, r as u32);
-                data = (frame.0 >> 16) as u16;
-                *frame_state = LeftLsb;
+                let (l, r) = data_32_c.pop().unwrap_or_default();
+                state.frame = (l as u32, r as u32);
+                data = (state.frame.0 >> 16) as u16;
+                state.frame_state = LeftLsb;
             }
             (LeftLsb, Channel::Left) => {
-                data = (frame.0 & 0xFFFF) as u16;
-                *frame_state = RightMsb;
+                data = (state.frame.0 & 0xFFFF) as u16;
+                state.frame_state = RightMsb;
             }
             (RightMsb, Channel::Right) => {
-                data = (frame.1 >> 16) as u16;
-                *frame_state = RightLsb;
+                data = (state.frame.1 >> 16) as u16;
+                state.frame_state = RightLsb;
             }
             (RightLsb, Channel::Right) => {
-                data = (frame.1 & 0xFFFF) as u16;
-                *frame_state = LeftMsb;
+                data = (state.frame.1 & 0xFFFF) as u16;
+                state.frame_state = LeftMsb;
+                state.recovery.on_frame();
             }
             // in case of udr this resynchronize tracked and actual channel
             _ => {
-                *frame_state = LeftMsb;
+                state.errors.channel += 1;
+                restart |= state.recovery.on_desync() == Recover::Restart;
+                state.frame_state = LeftMsb;
                 data = 0; //garbage data to avoid additional underrrun
             }
         }
         driver.write_data_register(data);
     }
     if status.fre() {
-        log::spawn(DWT::cycle_count(), "i2s3 Frame error").ok();
+        state.errors.fre += 1;
+        log::spawn(DWT::cycle_count(), "Slave Frame error").ok();
+        restart |= state.recovery.on_frame_error() == Recover::Restart;
+    }
+    // wait the next WS edge to enable again
+    if restart {
         driver.disable();
-        exti.lock(|exti| {
-            driver
-                .i2s_peripheral_mut()
-                .ws_pin_mut()
-                .enable_interrupt(exti)
-        })
+        state.frame_state = LeftMsb;
+        state.sync.arm(DWT::cycle_count());
+        if state.sync.strategy() == SyncStrategy::Exti {
+            I::ws_listen(true);
+        }
     }
     if status.udr() {
-        log::spawn(DWT::cycle_count(), "i2s3 udr").ok();
+        state.errors.udr += 1;
+        log::spawn(DWT::cycle_count(), "Slave Transmit Underrun").ok();
         driver.status();
         driver.write_data_register(0);
     }
 }
 
+/// Frames a master transmitter may take to shift its last data out
+const STOP_FRAMES: u32 = 4;
+
+// RM disable procedure of a master transmitter, the last data must be shifted out before
+// clearing I2SE. Return `false` if it didn't within `STOP_FRAMES`, I2SE is cleared anyway.
+fn stop_master_transmit<I: I2sPeripheral>(
+    driver: &mut I2sDriver<I, Master, Transmit, I2sStd>,
+) -> bool {
+    let timeout = STOP_FRAMES * (SYSCLK_HZ / driver.sample_rate());
+    let start = DWT::cycle_count();
+    let stopped = loop {
+        let status = driver.status();
+        if status.txe() && !status.bsy() {
+            break true;
+        }
+        if DWT::cycle_count().wrapping_sub(start) > timeout {
+            break false;
+        }
+    };
+    driver.disable();
+    stopped
+}
+
+// RM disable procedure of a master receiver with 32 bits channels, right after an RXNE: clear
+// I2SE `clocks` I2S clock cycles later, 17 for 16 bits data and 1 for 32 bits data
+fn stop_master_receive<I: I2sPeripheral>(
+    driver: &mut I2sDriver<I, Master, Receive, I2sStd>,
+    clocks: u32,
+) {
+    let clock_cycles = SYSCLK_HZ / (driver.sample_rate() * 64) + 1;
+    let start = DWT::cycle_count();
+    while DWT::cycle_count().wrapping_sub(start) < clocks * clock_cycles {}
+    driver.disable();
+}
+
 fn _master_transmit_16bits_interrupt<I: I2sPeripheral>(
     driver: &mut I2sDriver<I, Master, Transmit, I2sStd>,
-    frame_state: &mut FrameState,
-    frame: &mut (u32, u32),
-    data_16_c: &mut Consumer<'static, (i16, i16), 8>,
+    state: &mut HandlerState,
+    data_16_c: &mut impl FrameSource<i16>,
 ) {
     let status = driver.status();
+    let mut restart = false;
     // it's better to write data first to avoid to trigger udr flag
     if status.txe() {
         let data;
-        match (*frame_state, status.chside()) {
+        match (state.frame_state, status.chside()) {
             (LeftMsb, Channel::Left) => {
-                let (l, r) = data_16_c.dequeue().unwrap_or_default();
-                *frame = (l as u32, r as u32);
-                data = (frame.0 & 0xFFFF) as u16;
-                *frame_state = RightMsb;
+                let (l, r) = data_16_c.pop().unwrap_or_default();
+                state.frame = (l as u32, r as u32);
+                data = (state.frame.0 & 0xFFFF) as u16;
+                state.frame_state = RightMsb;
             }
             (RightMsb, Channel::Right) => {
-                data = (frame.1 & 0xFFFF) as u16;
-                *frame_state = LeftMsb;
+                data = (state.frame.1 & 0xFFFF) as u16;
+                state.frame_state = LeftMsb;
+                state.recovery.on_frame();
             }
             // in case of udr this resynchronize tracked and actual channel
             _ => {
-                *frame_state = LeftMsb;
+                state.errors.channel += 1;
+                restart |= state.recovery.on_desync() == Recover::Restart;
+                state.frame_state = LeftMsb;
                 data = 0; //garbage data to avoid additional underrrun
             }
         }
         driver.write_data_register(data);
     }
+    // masters can't see frame errors, restart on persistent desynchronisation
+    if restart {
+        if !stop_master_transmit(driver) {
+            state.errors.stop += 1;
+            log::spawn(DWT::cycle_count(), "Master Transmit Stop timeout").ok();
+        }
+        state.frame_state = LeftMsb;
+        driver.enable();
+    }
 }
 
 fn _master_transmit_32bits_interrupt<I: I2sPeripheral>(
     driver: &mut I2sDriver<I, Master, Transmit, I2sStd>,
-    frame_state: &mut FrameState,
-    frame: &mut (u32, u32),
-    data_32_c: &mut Consumer<'static, (i32, i32), 8>,
+    state: &mut HandlerState,
+    data_32_c: &mut impl FrameSource<i32>,
 ) {
     let status = driver.status();
+    let mut restart = false;
     // it's better to write data first to avoid to trigger udr flag
     if status.txe() {
         let data;
-        match (*frame_state, status.chside()) {
+        match (state.frame_state, status.chside()) {
             (LeftMsb, Channel::Left) => {
-                let (l, r) = data_32_c.dequeue().unwrap_or_default();
-                *frame = (l as u32, r as u32);
-                data = (frame.0 >> 16) as u16;
-                *frame_state = LeftLsb;
+                let (l, r) = data_32_c.pop().unwrap_or_default();
+                state.frame = (l as u32, r as u32);
+                data = (state.frame.0 >> 16) as u16;
+                state.frame_state = LeftLsb;
             }
             (LeftLsb, Channel::Left) => {
-                data = (frame.0 & 0xFFFF) as u16;
-                *frame_state = RightMsb;
+                data = (state.frame.0 & 0xFFFF) as u16;
+                state.frame_state = RightMsb;
             }
             (RightMsb, Channel::Right) => {
-                data = (frame.1 >> 16) as u16;
-                *frame_state = RightLsb;
+                data = (state.frame.1 >> 16) as u16;
+                state.frame_state = RightLsb;
             }
             (RightLsb, Channel::Right) => {
-                data = (frame.1 & 0xFFFF) as u16;
-                *frame_state = LeftMsb;
+                data = (state.frame.1 & 0xFFFF) as u16;
+                state.frame_state = LeftMsb;
+                state.recovery.on_frame();
             }
             // in case of udr this resynchronize tracked and actual channel
             _ => {
-                *frame_state = LeftMsb;
+                state.errors.channel += 1;
+                restart |= state.recovery.on_desync() == Recover::Restart;
+                state.frame_state = LeftMsb;
                 data = 0; //garbage data to avoid additional underrrun
             }
         }
         driver.write_data_register(data);
     }
+    // masters can't see frame errors, restart on persistent desynchronisation
+    if restart {
+        if !stop_master_transmit(driver) {
+            state.errors.stop += 1;
+            log::spawn(DWT::cycle_count(), "Master Transmit Stop timeout").ok();
+        }
+        state.frame_state = LeftMsb;
+        driver.enable();
+    }
 }
 
-fn _slave_receive_16bits_interrupt(
-    driver: &mut I2sDriver<I2s2, Slave, Receive, I2sStd>,
-    exti: &mut impl Mutex<T = EXTI>,
-    frame_state: &mut FrameState,
-    frame: &mut (u32, u32),
-    data_16_p: &mut Producer<'static, (u32, (i16, i16)), 8>,
+fn _slave_receive_16bits_interrupt<I: I2sInstance>(
+    driver: &mut I2sDriver<I, Slave, Receive, I2sStd>,
+    state: &mut HandlerState,
+    data_16_p: &mut impl FrameSink<i16>,
 ) {
     let status = driver.status();
+    let mut restart = false;
     // It's better to read first to avoid triggering ovr flag
     if status.rxne() {
         let data = driver.read_data_register();
-        match (*frame_state, status.chside()) {
+        match (state.frame_state, status.chside()) {
             (LeftMsb, Channel::Left) => {
-                frame.0 = data as u32;
-                *frame_state = RightMsb;
+                state.frame.0 = data as u32;
+                state.frame_state = RightMsb;
             }
             (RightMsb, Channel::Right) => {
-                frame.1 = data as u32;
+                state.frame.1 = data as u32;
                 // defer sample processing to another task
-                let (l, r) = *frame;
-                data_16_p
-                    .enqueue((DWT::cycle_count(), (l as i16, r as i16)))
-                    .ok();
-                if !data_16_p.ready() {
+                let (l, r) = state.frame;
+                data_16_p.push(DWT::cycle_count(), (l as i16, r as i16));
+                if data_16_p.is_full() {
                     driver.disable();
                 }
-                *frame_state = LeftMsb;
+                state.frame_state = LeftMsb;
+                state.recovery.on_frame();
             }
             // in case of ovr this resynchronize at start of new frame
             _ => {
+                state.errors.channel += 1;
+                restart |= state.recovery.on_desync() == Recover::Restart;
                 log::spawn(DWT::cycle_count(), "Slave Receive Channel Err").ok();
-                *frame_state = LeftMsb;
+                state.frame_state = LeftMsb;
             }
         }
     }
     if status.fre() {
-        log::spawn(DWT::cycle_count(), "i2s3 Frame error").ok();
+        state.errors.fre += 1;
+        log::spawn(DWT::cycle_count(), "Slave Frame error").ok();
+        restart |= state.recovery.on_frame_error() == Recover::Restart;
+    }
+    // wait the next WS edge to enable again
+    if restart {
         driver.disable();
-        exti.lock(|exti| {
-            driver
-                .i2s_peripheral_mut()
-                .ws_pin_mut()
-                .enable_interrupt(exti)
-        })
+        state.frame_state = LeftMsb;
+        state.sync.arm(DWT::cycle_count());
+        if state.sync.strategy() == SyncStrategy::Exti {
+            I::ws_listen(true);
+        }
     }
     if status.ovr() {
-        log::spawn(DWT::cycle_count(), "i2s2 Overrun").ok();
+        state.errors.ovr += 1;
+        log::spawn(DWT::cycle_count(), "Slave Receive Overrun").ok();
         // sequence to delete ovr flag
         driver.read_data_register();
         driver.status();
     }
 }
 
-fn _slave_receive_32bits_interrupt(
-    driver: &mut I2sDriver<I2s2, Slave, Receive, I2sStd>,
-    exti: &mut impl Mutex<T = EXTI>,
-    frame_state: &mut FrameState,
-    frame: &mut (u32, u32),
-    data_32_p: &mut Producer<'static, (u32, (i32, i32)), 8>,
+fn _slave_receive_32bits_interrupt<I: I2sInstance>(
+    driver: &mut I2sDriver<I, Slave, Receive, I2sStd>,
+    state: &mut HandlerState,
+    data_32_p: &mut impl FrameSink<i32>,
 ) {
     let status = driver.status();
+    let mut restart = false;
     // It's better to read first to avoid triggering ovr flag
     if status.rxne() {
         let data = driver.read_data_register();
-        match (*frame_state, status.chside()) {
+        match (state.frame_state, status.chside()) {
             (LeftMsb, Channel::Left) => {
-                frame.0 = (data as u32) << 16;
-                *frame_state = LeftLsb;
+                state.frame.0 = (data as u32) << 16;
+                state.frame_state = LeftLsb;
             }
             (LeftLsb, Channel::Left) => {
-                frame.0 |= data as u32;
-                *frame_state = RightMsb;
+                state.frame.0 |= data as u32;
+                state.frame_state = RightMsb;
             }
             (RightMsb, Channel::Right) => {
-                frame.1 = (data as u32) << 16;
-                *frame_state = RightLsb;
+                state.frame.1 = (data as u32) << 16;
+                state.frame_state = RightLsb;
             }
             (RightLsb, Channel::Right) => {
-                frame.1 |= data as u32;
+                state.frame.1 |= data as u32;
                 // defer sample processing to another task
-                let (l, r) = *frame;
-                data_32_p
-                    .enqueue((DWT::cycle_count(), (l as i32, r as i32)))
-                    .ok();
-                if !data_32_p.ready() {
+                let (l, r) = state.frame;
+                data_32_p.push(DWT::cycle_count(), (l as i32, r as i32));
+                if data_32_p.is_full() {
                     driver.disable();
                 }
-                *frame_state = LeftMsb;
+                state.frame_state = LeftMsb;
+                state.recovery.on_frame();
             }
             // in case of ovr this resynchronize at start of new frame
             _ => {
+                state.errors.channel += 1;
+                restart |= state.recovery.on_desync() == Recover::Restart;
                 log::spawn(DWT::cycle_count(), "Slave Receive Channel Err").ok();
-                *frame_state = LeftMsb;
+                state.frame_state = LeftMsb;
             }
         }
     }
     if status.fre() {
-        log::spawn(DWT::cycle_count(), "i2s3 Frame error").ok();
+        state.errors.fre += 1;
+        log::spawn(DWT::cycle_count(), "Slave Frame error").ok();
+        restart |= state.recovery.on_frame_error() == Recover::Restart;
+    }
+    // wait the next WS edge to enable again
+    if restart {
         driver.disable();
-        exti.lock(|exti| {
-            driver
-                .i2s_peripheral_mut()
-                .ws_pin_mut()
-                .enable_interrupt(exti)
-        })
+        state.frame_state = LeftMsb;
+        state.sync.arm(DWT::cycle_count());
+        if state.sync.strategy() == SyncStrategy::Exti {
+            I::ws_listen(true);
+        }
     }
     if status.ovr() {
-        log::spawn(DWT::cycle_count(), "i2s2 Overrun").ok();
+        state.errors.ovr += 1;
+        log::spawn(DWT::cycle_count(), "Slave Receive Overrun").ok();
         // sequence to delete ovr flag
         driver.read_data_register();
         driver.status();
@@ -326,39 +905,47 @@ fn _slave_receive_32bits_interrupt(
 
 fn _master_receive_16bits_interrupt<I: I2sPeripheral>(
     driver: &mut I2sDriver<I, Master, Receive, I2sStd>,
-    frame_state: &mut FrameState,
-    frame: &mut (u32, u32),
-    data_16_p: &mut Producer<'static, (u32, (i16, i16)), 8>,
+    state: &mut HandlerState,
+    data_16_p: &mut impl FrameSink<i16>,
 ) {
     let status = driver.status();
+    let mut restart = false;
     // It's better to read first to avoid triggering ovr flag
     if status.rxne() {
         let data = driver.read_data_register();
-        match (*frame_state, status.chside()) {
+        match (state.frame_state, status.chside()) {
             (LeftMsb, Channel::Left) => {
-                frame.0 = data as u32;
-                *frame_state = RightMsb;
+                state.frame.0 = data as u32;
+                state.frame_state = RightMsb;
             }
             (RightMsb, Channel::Right) => {
-                frame.1 = data as u32;
+                state.frame.1 = data as u32;
                 // defer sample processing to another task
-                let (l, r) = *frame;
-                data_16_p
-                    .enqueue((DWT::cycle_count(), (l as i16, r as i16)))
-                    .ok();
-                //if !data_16_p.ready() {
+                let (l, r) = state.frame;
+                data_16_p.push(DWT::cycle_count(), (l as i16, r as i16));
+                //if data_16_p.is_full() {
                 //    driver.disable();
                 //}
-                *frame_state = LeftMsb;
+                state.frame_state = LeftMsb;
+                state.recovery.on_frame();
             }
             // in case of ovr this resynchronize at start of new frame
             _ => {
+                state.errors.channel += 1;
+                restart |= state.recovery.on_desync() == Recover::Restart;
                 log::spawn(DWT::cycle_count(), "Master Receive Channel Err").ok();
-                *frame_state = LeftMsb;
+                state.frame_state = LeftMsb;
             }
         }
     }
+    // masters can't see frame errors, restart on persistent desynchronisation
+    if restart {
+        stop_master_receive(driver, 17);
+        state.frame_state = LeftMsb;
+        driver.enable();
+    }
     if status.ovr() {
+        state.errors.ovr += 1;
         log::spawn(DWT::cycle_count(), "Master Receive Overrun").ok();
         // sequence to delete ovr flag
         driver.read_data_register();
@@ -368,47 +955,149 @@ fn _master_receive_16bits_interrupt<I: I2sPeripheral>(
 
 fn _master_receive_32bits_interrupt<I: I2sPeripheral>(
     driver: &mut I2sDriver<I, Master, Receive, I2sStd>,
-    frame_state: &mut FrameState,
-    frame: &mut (u32, u32),
-    data_32_p: &mut Producer<'static, (u32, (i32, i32)), 8>,
+    state: &mut HandlerState,
+    data_32_p: &mut impl FrameSink<i32>,
 ) {
     let status = driver.status();
+    let mut restart = false;
     // It's better to read first to avoid triggering ovr flag
     if status.rxne() {
         let data = driver.read_data_register();
-        match (*frame_state, status.chside()) {
+        match (state.frame_state, status.chside()) {
             (LeftMsb, Channel::Left) => {
-                frame.0 = (data as u32) << 16;
-                *frame_state = LeftLsb;
+                state.frame.0 = (data as u32) << 16;
+                state.frame_state = LeftLsb;
             }
             (LeftLsb, Channel::Left) => {
-                frame.0 |= data as u32;
-                *frame_state = RightMsb;
+                state.frame.0 |= data as u32;
+                state.frame_state = RightMsb;
             }
             (RightMsb, Channel::Right) => {
-                frame.1 = (data as u32) << 16;
-                *frame_state = RightLsb;
+                state.frame.1 = (data as u32) << 16;
+                state.frame_state = RightLsb;
             }
             (RightLsb, Channel::Right) => {
-                frame.1 |= data as u32;
+                state.frame.1 |= data as u32;
                 // defer sample processing to another task
-                let (l, r) = *frame;
-                data_32_p
-                    .enqueue((DWT::cycle_count(), (l as i32, r as i32)))
-                    .ok();
-                if !data_32_p.ready() {
+                let (l, r) = state.frame;
+                data_32_p.push(DWT::cycle_count(), (l as i32, r as i32));
+                if data_32_p.is_full() {
                     driver.disable();
                 }
-                *frame_state = LeftMsb;
+                state.frame_state = LeftMsb;
+                state.recovery.on_frame();
             }
             // in case of ovr this resynchronize at start of new frame
             _ => {
+                state.errors.channel += 1;
+                restart |= state.recovery.on_desync() == Recover::Restart;
                 log::spawn(DWT::cycle_count(), "Master Receive Channel Err").ok();
-                *frame_state = LeftMsb;
+                state.frame_state = LeftMsb;
             }
         }
     }
+    // masters can't see frame errors, restart on persistent desynchronisation
+    if restart {
+        stop_master_receive(driver, 1);
+        state.frame_state = LeftMsb;
+        driver.enable();
+    }
+    if status.ovr() {
+        state.errors.ovr += 1;
+        log::spawn(DWT::cycle_count(), "Master Receive Overrun").ok();
+        // sequence to delete ovr flag
+        driver.read_data_register();
+        driver.status();
+    }
+}
+
+// TDM slaves can't realign on slots, words lost on errors shift the following slots
+fn _slave_transmit_tdm_interrupt<I: I2sInstance, STD>(
+    driver: &mut I2sDriver<I, Slave, Transmit, STD>,
+    state: &mut HandlerState,
+    tdm_c: &mut Consumer<'static, SlotFrame, 8>,
+) {
+    let status = driver.status();
+    // it's better to write data first to avoid to trigger udr flag
+    if status.txe() {
+        let data = state.slots.next_word(tdm_c);
+        driver.write_data_register(data);
+        if state.slots.pos == 0 {
+            state.recovery.on_frame();
+        }
+    }
+    if status.fre() {
+        state.errors.fre += 1;
+        log::spawn(DWT::cycle_count(), "Slave Frame error").ok();
+    }
+    if status.udr() {
+        state.errors.udr += 1;
+        log::spawn(DWT::cycle_count(), "Slave Transmit Underrun").ok();
+        driver.status();
+        driver.write_data_register(0);
+    }
+}
+
+fn _master_transmit_tdm_interrupt<I: I2sPeripheral, STD>(
+    driver: &mut I2sDriver<I, Master, Transmit, STD>,
+    state: &mut HandlerState,
+    tdm_c: &mut Consumer<'static, SlotFrame, 8>,
+) {
+    let status = driver.status();
+    if status.txe() {
+        let data = state.slots.next_word(tdm_c);
+        driver.write_data_register(data);
+        if state.slots.pos == 0 {
+            state.recovery.on_frame();
+        }
+    }
+}
+
+fn _slave_receive_tdm_interrupt<I: I2sInstance, STD>(
+    driver: &mut I2sDriver<I, Slave, Receive, STD>,
+    state: &mut HandlerState,
+    tdm_p: &mut Producer<'static, (u32, SlotFrame), 8>,
+) {
+    let status = driver.status();
+    // It's better to read first to avoid triggering ovr flag
+    if status.rxne() {
+        let data = driver.read_data_register();
+        if let Some(frame) = state.slots.store_word(data) {
+            // defer sample processing to another task
+            tdm_p.enqueue((DWT::cycle_count(), frame)).ok();
+            state.recovery.on_frame();
+        }
+    }
+    if status.fre() {
+        state.errors.fre += 1;
+        log::spawn(DWT::cycle_count(), "Slave Frame error").ok();
+    }
+    if status.ovr() {
+        state.errors.ovr += 1;
+        log::spawn(DWT::cycle_count(), "Slave Receive Overrun").ok();
+        // sequence to delete ovr flag
+        driver.read_data_register();
+        driver.status();
+    }
+}
+
+fn _master_receive_tdm_interrupt<I: I2sPeripheral, STD>(
+    driver: &mut I2sDriver<I, Master, Receive, STD>,
+    state: &mut HandlerState,
+    tdm_p: &mut Producer<'static, (u32, SlotFrame), 8>,
+) {
+    let status = driver.status();
+    // It's better to read first to avoid triggering ovr flag
+    if status.rxne() {
+        let data = driver.read_data_register();
+        if let Some(frame) = state.slots.store_word(data) {
+            // defer sample processing to another task
+            tdm_p.enqueue((DWT::cycle_count(), frame)).ok();
+            state.recovery.on_frame();
+        }
+    }
     if status.ovr() {
+        state.errors.ovr += 1;
         log::spawn(DWT::cycle_count(), "Master Receive Overrun").ok();
         // sequence to delete ovr flag
         driver.read_data_register();
@@ -420,150 +1109,567 @@ impl<I: I2sPeripheral> DriverWrap<I> {
     pub fn new(drv: Option<DriverMode<I>>) -> Self {
         Self {
             drv,
-            frame_state: LeftMsb,
-            frame: (0, 0),
+            state: HandlerState {
+                frame_state: LeftMsb,
+                frame: (0, 0),
+                errors: ErrorCounters::default(),
+                sync: SlaveSync::new(SyncStrategy::Exti),
+                recovery: Recovery::new(RecoveryPolicy::Lenient),
+                slots: SlotState::new(2, 1),
+            },
+            rx_sink: RxSink::Queue,
+            tx_source: TxSource::Queue,
+            channels: Channels {
+                mode: ChannelMode::Stereo,
+                fill: 0,
+            },
+            blocks_stale: true,
+            unhandled: 0,
+        }
+    }
+
+    //reset frame tracking
+    pub fn reset_frame(&mut self) {
+        self.state.frame_state = LeftMsb;
+        self.state.frame = (0, 0);
+        self.state.slots.reset();
+    }
+
+    /// Move frame tracking to the other channel, as missed interrupts would, to inject faults.
+    pub fn skew_frame(&mut self) {
+        self.state.frame_state = match self.state.frame_state {
+            LeftMsb | LeftLsb => RightMsb,
+            RightMsb | RightLsb => LeftMsb,
+        };
+    }
+
+    /// Set the slots of a TDM frame and their width, 16 or 32 bits, back to 2 slots of 16 bits
+    /// on `take()`. Must be called before `replace()`.
+    pub fn set_slots(&mut self, slots: usize, slot_bits: u8) {
+        assert!((1..=MAX_SLOTS).contains(&slots));
+        assert!(slot_bits == 16 || slot_bits == 32);
+        self.state.slots = SlotState::new(slots, slot_bits as usize / 16);
+    }
+
+    /// Errors seen since the driver was set up, cleared on `take()`.
+    pub fn errors(&self) -> ErrorCounters {
+        self.state.errors
+    }
+
+    /// Select how a slave synchronise on WS, back to `SyncStrategy::Exti` on `take()`. Must be
+    /// called before `replace()`.
+    pub fn set_sync(&mut self, strategy: SyncStrategy) {
+        self.state.sync.set_strategy(strategy);
+    }
+
+    /// Synchronisation statistics since the driver was set up, cleared on `take()`.
+    pub fn sync_stats(&self) -> SyncStats {
+        self.state.sync.stats
+    }
+
+    /// Select how handlers recover from desynchronisation, back to `RecoveryPolicy::Lenient`
+    /// on `take()`.
+    pub fn set_recovery(&mut self, policy: RecoveryPolicy) {
+        self.state.recovery.set_policy(policy);
+    }
+
+    /// Recovery statistics since the driver was set up, cleared on `take()`.
+    pub fn recovery_stats(&self) -> RecoveryStats {
+        self.state.recovery.stats
+    }
+
+    /// Interrupts masked for lack of a driver to serve them, not cleared on `take()`
+    pub fn unhandled(&self) -> u32 {
+        self.unhandled
+    }
+
+    /// `true` while a slave waits for WS.
+    pub fn sync_armed(&self) -> bool {
+        self.state.sync.is_armed()
+    }
+
+    /// Enable the driver whatever its mode, frame tracking restarts from the first channel.
+    pub fn enable(&mut self) {
+        // handlers may have run on a disabled driver since the last frame
+        self.reset_frame();
+        match self.drv {
+            Some(Tx(SlaveTransmit16bits(ref mut drv)))
+            | Some(Tx(SlaveTransmit32bits(ref mut drv))) => drv.enable(),
+            Some(Tx(MasterTransmit16bits(ref mut drv)))
+            | Some(Tx(MasterTransmit32bits(ref mut drv))) => drv.enable(),
+            Some(Rx(SlaveReceive16bits(ref mut drv)))
+            | Some(Rx(SlaveReceive32bits(ref mut drv))) => drv.enable(),
+            Some(Rx(MasterReceive16bits(ref mut drv)))
+            | Some(Rx(MasterReceive32bits(ref mut drv))) => drv.enable(),
+            Some(Tx(SlaveTransmitTdmShort(ref mut drv))) => drv.enable(),
+            Some(Tx(MasterTransmitTdmShort(ref mut drv))) => drv.enable(),
+            Some(Rx(SlaveReceiveTdmShort(ref mut drv))) => drv.enable(),
+            Some(Rx(MasterReceiveTdmShort(ref mut drv))) => drv.enable(),
+            Some(Tx(SlaveTransmitTdmLong(ref mut drv))) => drv.enable(),
+            Some(Tx(MasterTransmitTdmLong(ref mut drv))) => drv.enable(),
+            Some(Rx(SlaveReceiveTdmLong(ref mut drv))) => drv.enable(),
+            Some(Rx(MasterReceiveTdmLong(ref mut drv))) => drv.enable(),
+            None => {}
+        }
+    }
+
+    /// Disable the driver whatever its mode.
+    pub fn disable(&mut self) {
+        match self.drv {
+            Some(Tx(SlaveTransmit16bits(ref mut drv)))
+            | Some(Tx(SlaveTransmit32bits(ref mut drv))) => drv.disable(),
+            Some(Tx(MasterTransmit16bits(ref mut drv)))
+            | Some(Tx(MasterTransmit32bits(ref mut drv))) => drv.disable(),
+            Some(Rx(SlaveReceive16bits(ref mut drv)))
+            | Some(Rx(SlaveReceive32bits(ref mut drv))) => drv.disable(),
+            Some(Rx(MasterReceive16bits(ref mut drv)))
+            | Some(Rx(MasterReceive32bits(ref mut drv))) => drv.disable(),
+            Some(Tx(SlaveTransmitTdmShort(ref mut drv))) => drv.disable(),
+            Some(Tx(MasterTransmitTdmShort(ref mut drv))) => drv.disable(),
+            Some(Rx(SlaveReceiveTdmShort(ref mut drv))) => drv.disable(),
+            Some(Rx(MasterReceiveTdmShort(ref mut drv))) => drv.disable(),
+            Some(Tx(SlaveTransmitTdmLong(ref mut drv))) => drv.disable(),
+            Some(Tx(MasterTransmitTdmLong(ref mut drv))) => drv.disable(),
+            Some(Rx(SlaveReceiveTdmLong(ref mut drv))) => drv.disable(),
+            Some(Rx(MasterReceiveTdmLong(ref mut drv))) => drv.disable(),
+            None => {}
         }
     }
 
+    /// Select where received frames go, back to `RxSink::Queue` on `take()`.
+    pub fn set_rx_sink(&mut self, rx_sink: RxSink) {
+        self.rx_sink = rx_sink;
+        self.blocks_stale = true;
+    }
+
+    /// Select where transmitted frames come from, back to `TxSource::Queue` on `take()`.
+    pub fn set_tx_source(&mut self, tx_source: TxSource) {
+        self.tx_source = tx_source;
+        self.blocks_stale = true;
+    }
+
+    /// Select the channel mode, back to `ChannelMode::Stereo` on `take()`. Mono samples go
+    /// through the mono queues, `fill` is the left aligned sample of idle and muted channels.
+    /// Doesn't apply to TDM.
+    pub fn set_channel_mode(&mut self, mode: ChannelMode, fill: i32) {
+        self.channels = Channels { mode, fill };
+    }
+}
+
+impl<I: I2sInstance> DriverWrap<I> {
     pub fn take(&mut self) -> Option<DriverMode<I>> {
-        self.frame_state = LeftMsb;
-        self.frame = (0, 0);
+        self.state.frame_state = LeftMsb;
+        self.state.frame = (0, 0);
+        self.state.errors = ErrorCounters::default();
+        self.state.sync.set_strategy(SyncStrategy::Exti);
+        self.state.recovery.set_policy(RecoveryPolicy::Lenient);
+        self.state.slots = SlotState::new(2, 1);
+        self.rx_sink = RxSink::Queue;
+        self.tx_source = TxSource::Queue;
+        self.channels.mode = ChannelMode::Stereo;
+        crash::set_driver_mode(I::NAME, None);
         self.drv.take()
     }
 
-    pub fn replace(&mut self, drv: DriverMode<I>) -> Option<DriverMode<I>> {
+    /// Set the driver, a slave waits for WS using the selected synchronisation strategy. TDM
+    /// slaves aren't synchronised, they must be enabled before their master to count slots
+    /// from the first one. Frame tracking restarts from the first channel.
+    pub fn replace(&mut self, drv: impl Into<DriverMode<I>>) -> Option<DriverMode<I>> {
+        let drv = drv.into();
+        self.reset_frame();
+        match drv {
+            Tx(SlaveTransmit16bits(_))
+            | Tx(SlaveTransmit32bits(_))
+            | Rx(SlaveReceive16bits(_))
+            | Rx(SlaveReceive32bits(_)) => self.state.sync.arm(DWT::cycle_count()),
+            _ => self.state.sync.disarm(),
+        }
+        crash::set_driver_mode(I::NAME, Some(drv.index()));
         self.drv.replace(drv)
     }
 
-    //reset frame tracking
-    pub fn reset_frame(&mut self) {
-        self.frame_state = LeftMsb;
-        self.frame = (0, 0);
+    /// Disable the driver and start it again from the first channel. A 16 or 32 bits slave
+    /// waits for the next WS edge like after `replace()`, other drivers are enabled right away.
+    pub fn restart(&mut self) {
+        self.disable();
+        match self.drv {
+            Some(Tx(SlaveTransmit16bits(_)))
+            | Some(Tx(SlaveTransmit32bits(_)))
+            | Some(Rx(SlaveReceive16bits(_)))
+            | Some(Rx(SlaveReceive32bits(_))) => {
+                self.reset_frame();
+                self.state.sync.arm(DWT::cycle_count());
+                if self.state.sync.strategy() == SyncStrategy::Exti {
+                    I::ws_listen(true);
+                }
+            }
+            _ => self.enable(),
+        }
+    }
+
+    /// Take the driver whatever its mode, disable it and its WS interrupt, release it and reset
+    /// the peripheral. `None` without driver, the peripheral is reset anyway.
+    pub fn teardown(&mut self, exti: &mut EXTI) -> Option<I> {
+        macro_rules! release {
+            ($drv:ident) => {{
+                $drv.disable();
+                $drv.i2s_peripheral_mut().ws_disable_interrupt(exti);
+                $drv.release()
+            }};
+        }
+        let i2s = match self.take() {
+            Some(Tx(SlaveTransmit16bits(mut drv))) | Some(Tx(SlaveTransmit32bits(mut drv))) => {
+                Some(release!(drv))
+            }
+            Some(Tx(MasterTransmit16bits(mut drv))) | Some(Tx(MasterTransmit32bits(mut drv))) => {
+                Some(release!(drv))
+            }
+            Some(Rx(SlaveReceive16bits(mut drv))) | Some(Rx(SlaveReceive32bits(mut drv))) => {
+                Some(release!(drv))
+            }
+            Some(Rx(MasterReceive16bits(mut drv))) | Some(Rx(MasterReceive32bits(mut drv))) => {
+                Some(release!(drv))
+            }
+            Some(Tx(SlaveTransmitTdmShort(mut drv))) => Some(release!(drv)),
+            Some(Tx(MasterTransmitTdmShort(mut drv))) => Some(release!(drv)),
+            Some(Rx(SlaveReceiveTdmShort(mut drv))) => Some(release!(drv)),
+            Some(Rx(MasterReceiveTdmShort(mut drv))) => Some(release!(drv)),
+            Some(Tx(SlaveTransmitTdmLong(mut drv))) => Some(release!(drv)),
+            Some(Tx(MasterTransmitTdmLong(mut drv))) => Some(release!(drv)),
+            Some(Rx(SlaveReceiveTdmLong(mut drv))) => Some(release!(drv)),
+            Some(Rx(MasterReceiveTdmLong(mut drv))) => Some(release!(drv)),
+            None => None,
+        };
+        I::reset_peripheral();
+        i2s
     }
-}
 
-impl DriverWrap<I2s3> {
-    pub fn transmit_interrupt_handler(
+    // count an interrupt the handlers of direction `transmit` can't serve
+    fn count_unhandled(&mut self, transmit: bool) -> Unhandled {
+        self.unhandled += 1;
+        match self.drv {
+            None => Unhandled::Empty,
+            Some(Tx(_)) if !transmit => Unhandled::WrongDirection,
+            Some(Rx(_)) if transmit => Unhandled::WrongDirection,
+            Some(_) => Unhandled::NoSlave,
+        }
+    }
+
+    // mask the SPI interrupts no driver of direction `transmit` serves
+    fn mask_interrupts(&mut self, transmit: bool) -> Unhandled {
+        I::mask_interrupts();
+        self.count_unhandled(transmit)
+    }
+
+    // mask the WS interrupt no slave waits for, unless it came from another pin
+    fn mask_ws(&mut self, transmit: bool) -> Result<(), Unhandled> {
+        if !I::ws_mask() {
+            return Ok(());
+        }
+        Err(self.count_unhandled(transmit))
+    }
+
+    /// Serve the SPI interrupt in the direction of the driver. Without driver the SPI interrupts
+    /// are masked and counted.
+    pub fn interrupt_handler(
         &mut self,
-        exti: &mut impl Mutex<T = EXTI>,
-        data_16_c: &mut Consumer<'static, (i16, i16), 8>,
-        data_32_c: &mut Consumer<'static, (i32, i32), 8>,
-    ) {
+        tx_data: &mut TxData,
+        rx_data: &mut RxData,
+    ) -> Result<(), Unhandled> {
         match self.drv {
-            Some(SlaveTransmit16bits(ref mut drv)) => _slave_transmit_16bits_interrupt(
-                drv,
-                exti,
-                &mut self.frame_state,
-                &mut self.frame,
-                data_16_c,
-            ),
-            Some(SlaveTransmit32bits(ref mut drv)) => _slave_transmit_32bits_interrupt(
-                drv,
-                exti,
-                &mut self.frame_state,
-                &mut self.frame,
-                data_32_c,
-            ),
-            Some(MasterTransmit16bits(ref mut drv)) => _master_transmit_16bits_interrupt(
-                drv,
-                &mut self.frame_state,
-                &mut self.frame,
-                data_16_c,
-            ),
-            Some(MasterTransmit32bits(ref mut drv)) => _master_transmit_32bits_interrupt(
-                drv,
-                &mut self.frame_state,
-                &mut self.frame,
-                data_32_c,
-            ),
-            _ => unimplemented!(),
-        }
-    }
-
-    pub fn transmit_exti_handler(&mut self, exti: &mut EXTI) {
+            Some(Rx(_)) => self.receive_interrupt_handler(rx_data),
+            _ => self.transmit_interrupt_handler(tx_data),
+        }
+    }
+
+    /// Serve the SPI interrupt of a transmitter. Without a transmitter the SPI interrupts are
+    /// masked and counted.
+    pub fn transmit_interrupt_handler(&mut self, tx_data: &mut TxData) -> Result<(), Unhandled> {
+        let TxData {
+            data_16_c,
+            data_32_c,
+            blocks_d: blocks,
+            tdm_c,
+            mono_c,
+        } = tx_data;
+        if self.tx_source == TxSource::Block && self.blocks_stale {
+            blocks.reset();
+            self.blocks_stale = false;
+        }
+        let Some(Tx(tx)) = &mut self.drv else {
+            return Err(self.mask_interrupts(true));
+        };
+        let state = &mut self.state;
+        let channels = self.channels;
+        match (tx, self.tx_source) {
+            (SlaveTransmit16bits(drv), TxSource::Queue) => {
+                _slave_transmit_16bits_interrupt(drv, state, &mut channels.tx(data_16_c, mono_c))
+            }
+            (SlaveTransmit16bits(drv), TxSource::Block) => {
+                _slave_transmit_16bits_interrupt(drv, state, &mut channels.tx(blocks, mono_c))
+            }
+            (SlaveTransmit32bits(drv), TxSource::Queue) => {
+                _slave_transmit_32bits_interrupt(drv, state, &mut channels.tx(data_32_c, mono_c))
+            }
+            (SlaveTransmit32bits(drv), TxSource::Block) => {
+                _slave_transmit_32bits_interrupt(drv, state, &mut channels.tx(blocks, mono_c))
+            }
+            (MasterTransmit16bits(drv), TxSource::Queue) => {
+                _master_transmit_16bits_interrupt(drv, state, &mut channels.tx(data_16_c, mono_c))
+            }
+            (MasterTransmit16bits(drv), TxSource::Block) => {
+                _master_transmit_16bits_interrupt(drv, state, &mut channels.tx(blocks, mono_c))
+            }
+            (MasterTransmit32bits(drv), TxSource::Queue) => {
+                _master_transmit_32bits_interrupt(drv, state, &mut channels.tx(data_32_c, mono_c))
+            }
+            (MasterTransmit32bits(drv), TxSource::Block) => {
+                _master_transmit_32bits_interrupt(drv, state, &mut channels.tx(blocks, mono_c))
+            }
+            // TDM frames only come from the queue
+            (SlaveTransmitTdmShort(drv), _) => _slave_transmit_tdm_interrupt(drv, state, tdm_c),
+            (SlaveTransmitTdmLong(drv), _) => _slave_transmit_tdm_interrupt(drv, state, tdm_c),
+            (MasterTransmitTdmShort(drv), _) => _master_transmit_tdm_interrupt(drv, state, tdm_c),
+            (MasterTransmitTdmLong(drv), _) => _master_transmit_tdm_interrupt(drv, state, tdm_c),
+        }
+        Ok(())
+    }
+
+    /// With `SyncStrategy::Polling`, sample WS once and enable an armed slave on a rising edge.
+    /// `window` is the largest time between samples, in cycles, to catch an edge in time.
+    /// Return `true` if the slave was enabled.
+    pub fn transmit_sync_poll(&mut self, window: u32) -> bool {
+        if self.state.sync.strategy() != SyncStrategy::Polling || !self.state.sync.is_armed() {
+            return false;
+        }
         match self.drv {
-            Some(SlaveTransmit16bits(ref mut i2s3_driver)) => {
-                let ws_pin = i2s3_driver.i2s_peripheral_mut().ws_pin_mut();
-                ws_pin.clear_interrupt_pending_bit();
-                if ws_pin.is_high() {
-                    ws_pin.disable_interrupt(exti);
-                    i2s3_driver.write_data_register(0);
-                    i2s3_driver.enable();
+            Some(Tx(SlaveTransmit16bits(ref mut drv)))
+            | Some(Tx(SlaveTransmit32bits(ref mut drv))) => {
+                let high = drv.i2s_peripheral_mut().ws_is_high();
+                let enable = self.state.sync.on_sample(DWT::cycle_count(), high, window);
+                if enable {
+                    drv.write_data_register(0);
+                    drv.enable();
                 }
+                enable
             }
-            Some(SlaveTransmit32bits(ref mut i2s3_driver)) => {
-                let ws_pin = i2s3_driver.i2s_peripheral_mut().ws_pin_mut();
-                ws_pin.clear_interrupt_pending_bit();
-                if ws_pin.is_high() {
-                    ws_pin.disable_interrupt(exti);
-                    i2s3_driver.write_data_register(0);
-                    i2s3_driver.enable();
+            _ => false,
+        }
+    }
+
+    /// With `SyncStrategy::TimerCapture`, handle a WS edge captured by the timer.
+    pub fn transmit_capture_handler(&mut self, in_time: bool) {
+        if self.state.sync.strategy() != SyncStrategy::TimerCapture {
+            return;
+        }
+        match self.drv {
+            Some(Tx(SlaveTransmit16bits(ref mut drv)))
+            | Some(Tx(SlaveTransmit32bits(ref mut drv))) => {
+                if self.state.sync.on_edge(DWT::cycle_count(), in_time) {
+                    drv.write_data_register(0);
+                    drv.enable();
                 }
             }
-            _ => unimplemented!(),
+            _ => {}
         }
     }
-}
 
-impl DriverWrap<I2s2> {
-    pub fn receive_interrupt_handler(
-        &mut self,
-        exti: &mut impl Mutex<T = EXTI>,
-        data_16_p: &mut Producer<'static, (u32, (i16, i16)), 8>,
-        data_32_p: &mut Producer<'static, (u32, (i32, i32)), 8>,
-    ) {
+    /// Serve a WS edge of a slave waiting for it. Without such a slave the WS interrupt is
+    /// masked and counted.
+    pub fn transmit_exti_handler(&mut self) -> Result<(), Unhandled> {
+        // the slave synchronises another way, the edge isn't for it
+        if self.state.sync.strategy() != SyncStrategy::Exti {
+            return self.mask_ws(true);
+        }
         match self.drv {
-            Some(SlaveReceive16bits(ref mut drv)) => _slave_receive_16bits_interrupt(
-                drv,
-                exti,
-                &mut self.frame_state,
-                &mut self.frame,
-                data_16_p,
-            ),
-            Some(SlaveReceive32bits(ref mut drv)) => _slave_receive_32bits_interrupt(
-                drv,
-                exti,
-                &mut self.frame_state,
-                &mut self.frame,
-                data_32_p,
-            ),
-            Some(MasterReceive16bits(ref mut drv)) => _master_receive_16bits_interrupt(
-                drv,
-                &mut self.frame_state,
-                &mut self.frame,
-                data_16_p,
-            ),
-            Some(MasterReceive32bits(ref mut drv)) => _master_receive_32bits_interrupt(
-                drv,
-                &mut self.frame_state,
-                &mut self.frame,
-                data_32_p,
-            ),
-            _ => unimplemented!(),
-        }
-    }
-
-    pub fn receive_exti_handler(&mut self, exti: &mut EXTI) {
+            Some(Tx(SlaveTransmit16bits(ref mut drv)))
+            | Some(Tx(SlaveTransmit32bits(ref mut drv))) => {
+                let i2s = drv.i2s_peripheral_mut();
+                // the edge may come from another pin sharing the EXTI interrupt
+                if !i2s.ws_take_interrupt() {
+                    return Ok(());
+                }
+                if self
+                    .state
+                    .sync
+                    .on_edge(DWT::cycle_count(), i2s.ws_is_high())
+                {
+                    I::ws_listen(false);
+                    drv.write_data_register(0);
+                    drv.enable();
+                }
+                Ok(())
+            }
+            _ => self.mask_ws(true),
+        }
+    }
+
+    /// Serve the SPI interrupt of a receiver. Without a receiver the SPI interrupts are masked
+    /// and counted.
+    pub fn receive_interrupt_handler(&mut self, rx_data: &mut RxData) -> Result<(), Unhandled> {
+        let RxData {
+            data_16_p,
+            data_32_p,
+            blocks_f: blocks,
+            tdm_p,
+            mono_p,
+        } = rx_data;
+        if self.rx_sink == RxSink::Block && self.blocks_stale {
+            blocks.reset();
+            self.blocks_stale = false;
+        }
+        let Some(Rx(rx)) = &mut self.drv else {
+            return Err(self.mask_interrupts(false));
+        };
+        let state = &mut self.state;
+        let channels = self.channels;
+        match (rx, self.rx_sink) {
+            (SlaveReceive16bits(drv), RxSink::Queue) => {
+                _slave_receive_16bits_interrupt(drv, state, &mut channels.rx(data_16_p, mono_p))
+            }
+            (SlaveReceive16bits(drv), RxSink::Block) => {
+                _slave_receive_16bits_interrupt(drv, state, &mut channels.rx(blocks, mono_p))
+            }
+            (SlaveReceive32bits(drv), RxSink::Queue) => {
+                _slave_receive_32bits_interrupt(drv, state, &mut channels.rx(data_32_p, mono_p))
+            }
+            (SlaveReceive32bits(drv), RxSink::Block) => {
+                _slave_receive_32bits_interrupt(drv, state, &mut channels.rx(blocks, mono_p))
+            }
+            (MasterReceive16bits(drv), RxSink::Queue) => {
+                _master_receive_16bits_interrupt(drv, state, &mut channels.rx(data_16_p, mono_p))
+            }
+            (MasterReceive16bits(drv), RxSink::Block) => {
+                _master_receive_16bits_interrupt(drv, state, &mut channels.rx(blocks, mono_p))
+            }
+            (MasterReceive32bits(drv), RxSink::Queue) => {
+                _master_receive_32bits_interrupt(drv, state, &mut channels.rx(data_32_p, mono_p))
+            }
+            (MasterReceive32bits(drv), RxSink::Block) => {
+                _master_receive_32bits_interrupt(drv, state, &mut channels.rx(blocks, mono_p))
+            }
+            // TDM frames only go to the queue
+            (SlaveReceiveTdmShort(drv), _) => _slave_receive_tdm_interrupt(drv, state, tdm_p),
+            (SlaveReceiveTdmLong(drv), _) => _slave_receive_tdm_interrupt(drv, state, tdm_p),
+            (MasterReceiveTdmShort(drv), _) => _master_receive_tdm_interrupt(drv, state, tdm_p),
+            (MasterReceiveTdmLong(drv), _) => _master_receive_tdm_interrupt(drv, state, tdm_p),
+        }
+        Ok(())
+    }
+
+    /// Level of the WS line, `None` without driver or with a TDM one.
+    pub fn ws_is_high(&mut self) -> Option<bool> {
         match self.drv {
-            Some(SlaveReceive16bits(ref mut i2s2_driver)) => {
-                let ws_pin = i2s2_driver.i2s_peripheral_mut().ws_pin_mut();
-                ws_pin.clear_interrupt_pending_bit();
-                if ws_pin.is_high() {
-                    ws_pin.disable_interrupt(exti);
-                    //i2s2_driver.write_data_register(0);
-                    i2s2_driver.enable();
+            Some(Rx(SlaveReceive16bits(ref mut drv)))
+            | Some(Rx(SlaveReceive32bits(ref mut drv))) => {
+                Some(drv.i2s_peripheral_mut().ws_is_high())
+            }
+            Some(Rx(MasterReceive16bits(ref mut drv)))
+            | Some(Rx(MasterReceive32bits(ref mut drv))) => {
+                Some(drv.i2s_peripheral_mut().ws_is_high())
+            }
+            Some(Tx(SlaveTransmit16bits(ref mut drv)))
+            | Some(Tx(SlaveTransmit32bits(ref mut drv))) => {
+                Some(drv.i2s_peripheral_mut().ws_is_high())
+            }
+            Some(Tx(MasterTransmit16bits(ref mut drv)))
+            | Some(Tx(MasterTransmit32bits(ref mut drv))) => {
+                Some(drv.i2s_peripheral_mut().ws_is_high())
+            }
+            _ => None,
+        }
+    }
+
+    /// With `SyncStrategy::Polling`, sample WS once and enable an armed slave on a rising edge.
+    /// `window` is the largest time between samples, in cycles, to catch an edge in time.
+    /// Return `true` if the slave was enabled.
+    pub fn receive_sync_poll(&mut self, window: u32) -> bool {
+        if self.state.sync.strategy() != SyncStrategy::Polling || !self.state.sync.is_armed() {
+            return false;
+        }
+        match self.drv {
+            Some(Rx(SlaveReceive16bits(ref mut drv)))
+            | Some(Rx(SlaveReceive32bits(ref mut drv))) => {
+                let high = drv.i2s_peripheral_mut().ws_is_high();
+                let enable = self.state.sync.on_sample(DWT::cycle_count(), high, window);
+                if enable {
+                    drv.enable();
                 }
+                enable
             }
-            Some(SlaveReceive32bits(ref mut i2s2_driver)) => {
-                let ws_pin = i2s2_driver.i2s_peripheral_mut().ws_pin_mut();
-                ws_pin.clear_interrupt_pending_bit();
-                if ws_pin.is_high() {
-                    ws_pin.disable_interrupt(exti);
-                    //i2s2_driver.write_data_register(0);
-                    i2s2_driver.enable();
+            _ => false,
+        }
+    }
+
+    /// With `SyncStrategy::TimerCapture`, handle a WS edge captured by the timer.
+    pub fn receive_capture_handler(&mut self, in_time: bool) {
+        if self.state.sync.strategy() != SyncStrategy::TimerCapture {
+            return;
+        }
+        match self.drv {
+            Some(Rx(SlaveReceive16bits(ref mut drv)))
+            | Some(Rx(SlaveReceive32bits(ref mut drv))) => {
+                if self.state.sync.on_edge(DWT::cycle_count(), in_time) {
+                    drv.enable();
                 }
             }
-            _ => unimplemented!(),
+            _ => {}
+        }
+    }
+
+    /// Serve a WS edge of a slave waiting for it. Without such a slave the WS interrupt is
+    /// masked and counted.
+    pub fn receive_exti_handler(&mut self) -> Result<(), Unhandled> {
+        // the slave synchronises another way, the edge isn't for it
+        if self.state.sync.strategy() != SyncStrategy::Exti {
+            return self.mask_ws(false);
+        }
+        match self.drv {
+            Some(Rx(SlaveReceive16bits(ref mut drv)))
+            | Some(Rx(SlaveReceive32bits(ref mut drv))) => {
+                let i2s = drv.i2s_peripheral_mut();
+                // the edge may come from another pin sharing the EXTI interrupt
+                if !i2s.ws_take_interrupt() {
+                    return Ok(());
+                }
+                if self
+                    .state
+                    .sync
+                    .on_edge(DWT::cycle_count(), i2s.ws_is_high())
+                {
+                    I::ws_listen(false);
+                    //drv.write_data_register(0);
+                    drv.enable();
+                }
+                Ok(())
+            }
+            _ => self.mask_ws(false),
+        }
+    }
+
+    /// With `SyncStrategy::Polling`, sample WS for the slave of either direction, see
+    /// `transmit_sync_poll()`.
+    pub fn sync_poll(&mut self, window: u32) -> bool {
+        match self.drv {
+            Some(Rx(_)) => self.receive_sync_poll(window),
+            _ => self.transmit_sync_poll(window),
+        }
+    }
+
+    /// With `SyncStrategy::TimerCapture`, handle a WS edge for the slave of either direction.
+    pub fn capture_handler(&mut self, in_time: bool) {
+        match self.drv {
+            Some(Rx(_)) => self.receive_capture_handler(in_time),
+            _ => self.transmit_capture_handler(in_time),
+        }
+    }
+
+    /// Serve a WS edge for the slave of either direction. Without such a slave the WS interrupt
+    /// is masked and counted.
+    pub fn exti_handler(&mut self) -> Result<(), Unhandled> {
+        match self.drv {
+            Some(Rx(_)) => self.receive_exti_handler(),
+            _ => self.transmit_exti_handler(),
         }
     }
 }
@@ -0,0 +1,233 @@
+//! Background load competing with the I2S tasks
+//!
+//! Load tasks run from the update interrupt of their own timer, at a priority above or below the
+//! I2S tasks, and busy-wait for a fixed or random part of each timer period. Streaming under
+//! increasing load shows where underruns and overruns begin.
+
+use core::fmt;
+
+/// Loads tried by the scenarios, in percent of the CPU
+pub const PERCENTS: [u32; 6] = [5, 10, 20, 30, 50, 70];
+
+/// Priority of a load task relative to the I2S tasks
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Level {
+    /// Preempts the I2S tasks
+    Above,
+    /// Preempted by the I2S tasks
+    Below,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Above => "above",
+            Level::Below => "below",
+        }
+    }
+}
+
+/// Busy time of a load task in each period
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Busy {
+    /// The load of each period
+    Fixed,
+    /// Uniform between none and twice the load, averaging the load
+    Random,
+}
+
+/// All busy times, for scenarios comparing them
+pub const BUSY: [Busy; 2] = [Busy::Fixed, Busy::Random];
+
+impl Busy {
+    pub fn name(self) -> &'static str {
+        match self {
+            Busy::Fixed => "fixed",
+            Busy::Random => "random",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Profile {
+    /// Average load, in percent of each period
+    pub percent: u32,
+    pub busy: Busy,
+}
+
+// xorshift32, enough to spread busy times
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
+
+/// Busy times of a load task, periods are counted in cycles
+pub struct Load {
+    level: Level,
+    period: u32,
+    profile: Option<Profile>,
+    rng: Rng,
+    // since the profile was set
+    periods: u32,
+    busy_cycles: u64,
+}
+
+impl Load {
+    pub const fn new(level: Level, period: u32) -> Self {
+        Self {
+            level,
+            period,
+            profile: None,
+            rng: Rng(0x1234_5678),
+            periods: 0,
+            busy_cycles: 0,
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile
+    }
+
+    /// Set the profile, `None` for no load, and clear the measured load.
+    pub fn set(&mut self, profile: Option<Profile>) {
+        self.profile = profile;
+        self.periods = 0;
+        self.busy_cycles = 0;
+    }
+
+    /// Cycles to busy-wait in the coming period, never the whole period.
+    pub fn next_busy(&mut self) -> u32 {
+        let Some(profile) = self.profile else {
+            return 0;
+        };
+        let mean = (self.period as u64 * profile.percent.min(100) as u64 / 100) as u32;
+        let busy = match profile.busy {
+            Busy::Fixed => mean,
+            Busy::Random => self.rng.next() % (2 * mean + 1),
+        };
+        // leave time to leave the interrupt
+        let busy = busy.min(self.period - self.period / 16);
+        self.periods += 1;
+        self.busy_cycles += busy as u64;
+        busy
+    }
+
+    /// Load actually applied since the profile was set, in percent
+    pub fn measured_percent(&self) -> u32 {
+        if self.periods == 0 {
+            return 0;
+        }
+        (self.busy_cycles * 100 / (self.periods as u64 * self.period as u64)) as u32
+    }
+}
+
+/// Lowest loads where stream errors showed up
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Onset {
+    pub underrun: Option<u32>,
+    pub overrun: Option<u32>,
+    /// Highest load tried
+    pub max: u32,
+}
+
+impl Onset {
+    /// Record the errors seen at `percent`.
+    pub fn record(&mut self, percent: u32, underruns: u32, overruns: u32) {
+        let lowest = |onset: Option<u32>| Some(onset.map_or(percent, |p| p.min(percent)));
+        if underruns > 0 {
+            self.underrun = lowest(self.underrun);
+        }
+        if overruns > 0 {
+            self.overrun = lowest(self.overrun);
+        }
+        self.max = self.max.max(percent);
+    }
+}
+
+impl fmt::Display for Onset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.underrun {
+            Some(percent) => write!(f, "underruns from {}%", percent)?,
+            None => write!(f, "no underrun up to {}%", self.max)?,
+        }
+        match self.overrun {
+            Some(percent) => write!(f, ", overruns from {}%", percent),
+            None => write!(f, ", no overrun up to {}%", self.max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::format;
+
+    #[test]
+    fn busy_times() {
+        let mut load = Load::new(Level::Above, 1000);
+        assert_eq!(load.next_busy(), 0);
+        load.set(Some(Profile {
+            percent: 30,
+            busy: Busy::Fixed,
+        }));
+        assert_eq!(load.next_busy(), 300);
+        assert_eq!(load.measured_percent(), 30);
+        load.set(Some(Profile {
+            percent: 100,
+            busy: Busy::Fixed,
+        }));
+        assert_eq!(load.next_busy(), 1000 - 1000 / 16);
+    }
+
+    #[test]
+    fn random_busy_times_average_the_load() {
+        let mut load = Load::new(Level::Below, 1000);
+        load.set(Some(Profile {
+            percent: 20,
+            busy: Busy::Random,
+        }));
+        let mut max = 0;
+        for _ in 0..10_000 {
+            max = max.max(load.next_busy());
+        }
+        assert!(max <= 400 && max > 350);
+        assert!((19..=21).contains(&load.measured_percent()));
+        load.set(None);
+        assert_eq!(load.measured_percent(), 0);
+    }
+
+    #[test]
+    fn onset() {
+        let mut onset = Onset::default();
+        onset.record(5, 0, 0);
+        onset.record(20, 3, 0);
+        onset.record(10, 1, 0);
+        onset.record(30, 8, 2);
+        assert_eq!(onset.underrun, Some(10));
+        assert_eq!(onset.overrun, Some(30));
+        assert_eq!(
+            format!("{}", onset),
+            "underruns from 10%, overruns from 30%"
+        );
+        let mut clean = Onset::default();
+        clean.record(70, 0, 0);
+        assert_eq!(
+            format!("{}", clean),
+            "no underrun up to 70%, no overrun up to 70%"
+        );
+    }
+}
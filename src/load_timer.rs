@@ -0,0 +1,69 @@
+//! Timers pacing the [`load`](crate::load) tasks
+//!
+//! TIM3 and TIM4 interrupt at `LOAD_HZ`, the tasks bound to them busy-wait from the update
+//! interrupt. The timers only run while a load profile is set.
+
+use crate::hal;
+use crate::load::{Level, Load, Profile};
+
+use hal::pac::{tim3, DWT, RCC, TIM3, TIM4};
+use hal::rcc::{Enable, Reset};
+
+/// Rate of the load interrupts, short busy waits at a high rate hit the I2S interrupts more
+/// often than long ones
+pub const LOAD_HZ: u32 = 20_000;
+
+/// A load task, its timer and busy times
+pub struct LoadTask {
+    // TIM4 has the TIM3 registers
+    tim: &'static tim3::RegisterBlock,
+    pub load: Load,
+}
+
+impl LoadTask {
+    /// Load task `level` paced by TIM3, `timclk` is the TIM3 clock frequency.
+    pub fn tim3(_tim: TIM3, timclk: u32, level: Level) -> Self {
+        unsafe {
+            let rcc = &(*RCC::ptr());
+            TIM3::enable(rcc);
+            TIM3::reset(rcc);
+            Self::new(&*TIM3::ptr(), timclk, level)
+        }
+    }
+
+    /// Load task `level` paced by TIM4, `timclk` is the TIM4 clock frequency.
+    pub fn tim4(_tim: TIM4, timclk: u32, level: Level) -> Self {
+        unsafe {
+            let rcc = &(*RCC::ptr());
+            TIM4::enable(rcc);
+            TIM4::reset(rcc);
+            Self::new(&*TIM4::ptr(), timclk, level)
+        }
+    }
+
+    fn new(tim: &'static tim3::RegisterBlock, timclk: u32, level: Level) -> Self {
+        tim.psc.write(|w| unsafe { w.bits(0) });
+        tim.arr.write(|w| unsafe { w.bits(timclk / LOAD_HZ - 1) });
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.dier.write(|w| w.uie().set_bit());
+        Self {
+            tim,
+            load: Load::new(level, crate::SYSCLK_HZ / LOAD_HZ),
+        }
+    }
+
+    /// Set the load profile, the timer stops without one.
+    pub fn set(&mut self, profile: Option<Profile>) {
+        self.load.set(profile);
+        self.tim.cr1.modify(|_, w| w.cen().bit(profile.is_some()));
+    }
+
+    /// Serve the update interrupt, busy-waiting for the load of this period.
+    pub fn run(&mut self) {
+        self.tim.sr.write(|w| unsafe { w.bits(0) });
+        let start = DWT::cycle_count();
+        let busy = self.load.next_busy();
+        while DWT::cycle_count().wrapping_sub(start) < busy {}
+    }
+}
@@ -4,24 +4,94 @@
 //!
 //! # Hardware Wiring
 //!
-//! This use several SPI/I2S peripheral of the chip connected together.
+//! This use several SPI/I2S peripheral of the chip connected together, see [`board`] for the
+//! pins of each supported board.
 
 #![no_std]
 #![no_main]
 
+use core::fmt::Write;
+use core::mem::MaybeUninit;
 use core::panic::PanicInfo;
+use cortex_m_rt::{exception, ExceptionFrame};
 use rtt_target::rprintln;
 
 use stm32f4xx_hal as hal;
 
+pub mod analysis;
+pub mod block;
+pub mod board;
+pub mod channel;
+pub mod chip;
+pub mod codec;
+pub mod crash;
+pub mod deadline;
 pub mod driver_wrap;
+pub mod load;
+pub mod load_timer;
+pub mod pipeline;
+pub mod priority;
+pub mod progress;
+pub mod recovery;
+pub mod signal;
+pub mod snapshot;
+pub mod sync;
+pub mod teardown;
 pub mod test;
 pub mod tests_16bits;
+pub mod tests_channel;
+pub mod tests_codec;
+pub mod tests_cycle;
+pub mod tests_fault;
+pub mod tests_instances;
+pub mod tests_load;
+pub mod tests_loopback;
+pub mod tests_signal;
+pub mod tests_sync;
+pub mod tests_tdm;
+pub mod wiring;
+pub mod ws_capture;
+
+/// System clock frequency, 96 MHz or the chip limit if lower
+pub const SYSCLK_HZ: u32 = if chip::SYSCLK_MAX_HZ < 96_000_000 {
+    chip::SYSCLK_MAX_HZ
+} else {
+    96_000_000
+};
+
+/// Deadline `cycles` from now on the cycle counter
+pub fn deadline(cycles: u32) -> deadline::Deadline {
+    deadline::Deadline::new(hal::pac::DWT::cycle_count, cycles)
+}
+
+/// Deadline `ms` milliseconds from now on the cycle counter
+pub fn deadline_ms(ms: u32) -> deadline::Deadline {
+    deadline(ms * (SYSCLK_HZ / 1000))
+}
+
+/// Watchdog timeout, longer than any scenario with its waits timing out
+const WATCHDOG_MS: u32 = 8000;
+
+// left alone by the startup code, so it survives watchdog resets
+#[link_section = ".uninit.PROGRESS"]
+static mut PROGRESS: MaybeUninit<progress::Progress> = MaybeUninit::uninit();
+
+// written by a panic or a HardFault, reported and cleared on the next boot
+#[link_section = ".uninit.CRASH"]
+static mut CRASH: MaybeUninit<[u8; crash::RECORD_LEN]> = MaybeUninit::uninit();
+
+// WS interrupt tasks are bound to these lines
+const _: () = assert!(matches!(board::I2S2_WS_EXTI, hal::pac::Interrupt::EXTI15_10));
+const _: () = assert!(matches!(board::I2S3_WS_EXTI, hal::pac::Interrupt::EXTI4));
+#[cfg(has_i2s1)]
+const _: () = assert!(matches!(board::I2S1_WS_EXTI, hal::pac::Interrupt::EXTI15_10));
+#[cfg(has_i2s5)]
+const _: () = assert!(matches!(board::I2S5_WS_EXTI, hal::pac::Interrupt::EXTI1));
 
 #[rtic::app(
     device = stm32f4xx_hal::pac,
     peripherals = true,
-    dispatchers = [EXTI0, EXTI1, EXTI2]
+    dispatchers = [EXTI0, EXTI2, EXTI3]
 )]
 mod app {
     use super::*;
@@ -29,19 +99,36 @@ mod app {
     use core::fmt::Write;
 
     use hal::gpio::Edge;
-    use hal::gpio::{NoPin, Pin};
+    #[cfg(feature = "disco-f407")]
+    use hal::gpio::PinState;
+    use hal::i2c::I2c;
     use hal::i2s::stm32_i2s_v12x::driver::*;
     use hal::i2s::I2s;
-    #[allow(unused)]
     use hal::pac::DWT;
-    use hal::pac::{EXTI, SPI2, SPI3};
+    use hal::pac::{EXTI, I2C1, SPI2, SPI3};
+    use hal::watchdog::IndependentWatchdog;
+    #[cfg(has_i2s1)]
+    use hal::pac::SPI1;
+    #[cfg(has_i2s5)]
+    use hal::pac::SPI5;
     use hal::prelude::*;
 
+    use block::*;
     use driver_wrap::*;
+    use load::*;
+    use load_timer::*;
+    use pipeline::*;
+    use priority::*;
+    use progress::*;
+    use sync::*;
+    use wiring::*;
+    use ws_capture::*;
 
     use heapless::spsc::*;
 
-    use rtt_target::{rprintln, rtt_init, set_print_channel};
+    use rtic::Mutex;
+
+    use rtt_target::{rprint, rprintln, rtt_init, set_print_channel};
 
     pub const FRM_32: &[(i32, i32)] = &[
         (0x11113333u32 as _, 0x7777EEEEu32 as _),
@@ -64,16 +151,50 @@ mod app {
         Slave(I2sDriver<I, Slave, Receive, STD>),
     }
 
-    pub type I2s2 = I2s<
-        SPI2,
-        (
-            Pin<'B', 12_u8>,
-            Pin<'B', 13_u8>,
-            Pin<'C', 6_u8>,
-            Pin<'B', 15_u8>,
-        ),
-    >;
-    pub type I2s3 = I2s<SPI3, (Pin<'A', 4_u8>, Pin<'C', 10_u8>, NoPin, Pin<'C', 12_u8>)>;
+    pub type I2s2 = I2s<SPI2, board::I2s2Pins>;
+    pub type I2s3 = I2s<SPI3, board::I2s3Pins>;
+    pub type BoardCodec = board::CodecDriver<I2c<I2C1, board::CodecI2cPins>>;
+    #[cfg(has_i2s1)]
+    pub type I2s1 = I2s<SPI1, board::I2s1Pins>;
+    #[cfg(has_i2s5)]
+    pub type I2s5 = I2s<SPI5, board::I2s5Pins>;
+
+    /// Instances the chip may not have, transmitting to i2s2
+    pub struct ExtraI2s {
+        #[cfg(has_i2s1)]
+        pub i2s1: I2s1,
+        #[cfg(has_i2s5)]
+        pub i2s5: I2s5,
+    }
+
+    /// Drivers of the `ExtraI2s` instances
+    pub struct ExtraDrivers {
+        #[cfg(has_i2s1)]
+        pub i2s1: DriverWrap<I2s1>,
+        #[cfg(has_i2s5)]
+        pub i2s5: DriverWrap<I2s5>,
+    }
+
+    /// One of the `ExtraDrivers`, lockable like a shared resource
+    pub struct ExtraDriver<'a, M, I> {
+        extra: &'a mut M,
+        select: fn(&mut ExtraDrivers) -> &mut DriverWrap<I>,
+    }
+
+    impl<'a, M: Mutex<T = ExtraDrivers>, I> ExtraDriver<'a, M, I> {
+        pub fn new(extra: &'a mut M, select: fn(&mut ExtraDrivers) -> &mut DriverWrap<I>) -> Self {
+            Self { extra, select }
+        }
+    }
+
+    impl<'a, M: Mutex<T = ExtraDrivers>, I> Mutex for ExtraDriver<'a, M, I> {
+        type T = DriverWrap<I>;
+
+        fn lock<R>(&mut self, f: impl FnOnce(&mut DriverWrap<I>) -> R) -> R {
+            let select = self.select;
+            self.extra.lock(|extra| f(select(extra)))
+        }
+    }
 
     #[derive(Copy, Clone)]
     pub enum I2sCtl {
@@ -85,7 +206,15 @@ mod app {
     struct Shared {
         i2s2_driver: DriverWrap<I2s2>,
         i2s3_driver: DriverWrap<I2s3>,
+        extra_drivers: ExtraDrivers,
+        tx_data: TxData,
+        rx_data: RxData,
         exti: EXTI,
+        rx_blocks: BlockDrainer<'static, BLOCK_LEN>,
+        pipeline: Pipeline<Passthrough>,
+        ws_capture: WsCapture,
+        load_above: LoadTask,
+        load_below: LoadTask,
     }
     pub use crate::app::shared_resources::exti_that_needs_to_be_locked;
     pub use crate::app::shared_resources::i2s2_driver_that_needs_to_be_locked;
@@ -96,30 +225,49 @@ mod app {
         logs_chan: rtt_target::UpChannel,
         i2s2: Option<I2s2>,
         i2s3: Option<I2s3>,
-        i2s2_data_16_p: Producer<'static, (u32, (i16, i16)), 8>,
-        i2s2_data_16_c: Consumer<'static, (u32, (i16, i16)), 8>,
-        i2s3_data_16_p: Producer<'static, (i16, i16), 8>,
-        i2s3_data_16_c: Consumer<'static, (i16, i16), 8>,
-        i2s2_data_32_p: Producer<'static, (u32, (i32, i32)), 8>,
-        i2s2_data_32_c: Consumer<'static, (u32, (i32, i32)), 8>,
-        i2s3_data_32_p: Producer<'static, (i32, i32), 8>,
-        i2s3_data_32_c: Consumer<'static, (i32, i32), 8>,
+        extra_i2s: Option<ExtraI2s>,
+        codec: Option<BoardCodec>,
+        progress: Option<&'static mut Progress>,
+        watchdog: Option<IndependentWatchdog>,
+        rx_data_16_c: Consumer<'static, (u32, (i16, i16)), 8>,
+        tx_data_16_p: Producer<'static, (i16, i16), 8>,
+        rx_data_32_c: Consumer<'static, (u32, (i32, i32)), 8>,
+        tx_data_32_p: Producer<'static, (i32, i32), 8>,
+        rx_tdm_c: Consumer<'static, (u32, SlotFrame), 8>,
+        tx_tdm_p: Producer<'static, SlotFrame, 8>,
+        rx_mono_c: Consumer<'static, (u32, i32), 8>,
+        tx_mono_p: Producer<'static, i32, 8>,
+        tx_blocks_f: BlockFiller<'static, BLOCK_LEN>,
     }
 
     #[init(
         local = [
-            i2s2_data_16_q: Queue<(u32, (i16,i16)), 8> = Queue::new(),
-            i2s3_data_16_q: Queue<(i16,i16), 8> = Queue::new(),
-            i2s2_data_32_q: Queue<(u32, (i32,i32)), 8> = Queue::new(),
-            i2s3_data_32_q: Queue<(i32,i32), 8> = Queue::new(),
+            rx_data_16_q: Queue<(u32, (i16,i16)), 8> = Queue::new(),
+            tx_data_16_q: Queue<(i16,i16), 8> = Queue::new(),
+            rx_data_32_q: Queue<(u32, (i32,i32)), 8> = Queue::new(),
+            tx_data_32_q: Queue<(i32,i32), 8> = Queue::new(),
+            rx_tdm_q: Queue<(u32, SlotFrame), 8> = Queue::new(),
+            tx_tdm_q: Queue<SlotFrame, 8> = Queue::new(),
+            rx_mono_q: Queue<(u32, i32), 8> = Queue::new(),
+            tx_mono_q: Queue<i32, 8> = Queue::new(),
+            rx_blocks: [Block<BLOCK_LEN>; BLOCK_COUNT] = [Block::EMPTY; BLOCK_COUNT],
+            rx_blocks_free_q: BlockQueue<'static, BLOCK_LEN> = Queue::new(),
+            rx_blocks_filled_q: BlockQueue<'static, BLOCK_LEN> = Queue::new(),
+            tx_blocks: [Block<BLOCK_LEN>; BLOCK_COUNT] = [Block::EMPTY; BLOCK_COUNT],
+            tx_blocks_free_q: BlockQueue<'static, BLOCK_LEN> = Queue::new(),
+            tx_blocks_filled_q: BlockQueue<'static, BLOCK_LEN> = Queue::new(),
             i2s2_ctl_q: Queue<I2sCtl, 2> = Queue::new(),
             i2s3_ctl_q: Queue<I2sCtl, 2> = Queue::new()]
         )]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
-        let i2s2_data_16_q = cx.local.i2s2_data_16_q;
-        let i2s3_data_16_q = cx.local.i2s3_data_16_q;
-        let i2s2_data_32_q = cx.local.i2s2_data_32_q;
-        let i2s3_data_32_q = cx.local.i2s3_data_32_q;
+        let rx_data_16_q = cx.local.rx_data_16_q;
+        let tx_data_16_q = cx.local.tx_data_16_q;
+        let rx_data_32_q = cx.local.rx_data_32_q;
+        let tx_data_32_q = cx.local.tx_data_32_q;
+        let rx_tdm_q = cx.local.rx_tdm_q;
+        let tx_tdm_q = cx.local.tx_tdm_q;
+        let rx_mono_q = cx.local.rx_mono_q;
+        let tx_mono_q = cx.local.tx_mono_q;
         let channels = rtt_init! {
             up: {
                 0: {
@@ -136,51 +284,129 @@ mod app {
         let logs_chan = channels.up.0;
         let panics_chan = channels.up.1;
         set_print_channel(panics_chan);
-        let (i2s2_data_16_p, i2s2_data_16_c) = i2s2_data_16_q.split();
-        let (i2s3_data_16_p, i2s3_data_16_c) = i2s3_data_16_q.split();
-        let (i2s2_data_32_p, i2s2_data_32_c) = i2s2_data_32_q.split();
-        let (i2s3_data_32_p, i2s3_data_32_c) = i2s3_data_32_q.split();
+        rprintln!("Board {} {}", board::NAME, chip::NAME);
+        let (rx_data_16_p, rx_data_16_c) = rx_data_16_q.split();
+        let (tx_data_16_p, tx_data_16_c) = tx_data_16_q.split();
+        let (rx_data_32_p, rx_data_32_c) = rx_data_32_q.split();
+        let (tx_data_32_p, tx_data_32_c) = tx_data_32_q.split();
+        let (rx_tdm_p, rx_tdm_c) = rx_tdm_q.split();
+        let (tx_tdm_p, tx_tdm_c) = tx_tdm_q.split();
+        let (rx_mono_p, rx_mono_c) = rx_mono_q.split();
+        let (tx_mono_p, tx_mono_c) = tx_mono_q.split();
+        let (rx_blocks_f, rx_blocks) = block::split(
+            cx.local.rx_blocks,
+            cx.local.rx_blocks_free_q,
+            cx.local.rx_blocks_filled_q,
+        );
+        let (tx_blocks_f, tx_blocks_d) = block::split(
+            cx.local.tx_blocks,
+            cx.local.tx_blocks_free_q,
+            cx.local.tx_blocks_filled_q,
+        );
         let mut core = cx.core;
         core.DCB.enable_trace();
         core.DWT.set_cycle_count(0);
         core.DWT.enable_cycle_counter();
         let device = cx.device;
+
+        // resume the suite after a watchdog reset
+        let watchdog_reset = device.RCC.csr.read().wdgrstf().bit_is_set();
+        device.RCC.csr.modify(|_, w| w.rmvf().set_bit());
+        // any content is a valid `Progress`, checked by `start`
+        let progress = unsafe { (*core::ptr::addr_of_mut!(PROGRESS)).assume_init_mut() };
+        if let Some(hung) = progress.start(watchdog_reset) {
+            rprintln!("Watchdog reset, scenario #{} hung and failed, resuming", hung);
+        } else if progress.is_resumed() {
+            rprintln!("Watchdog reset, resuming");
+        }
+        // any content is a valid record buffer, checked by `decode`
+        let crash_record = unsafe { (*core::ptr::addr_of_mut!(CRASH)).assume_init_mut() };
+        if let Ok(record) = crash::CrashRecord::decode(crash_record) {
+            rprintln!("Previous run crashed: {}", record);
+        }
+        *crash_record = [0; crash::RECORD_LEN];
+        let mut watchdog = IndependentWatchdog::new(device.IWDG);
+        watchdog.start(WATCHDOG_MS.millis());
+
         let mut syscfg = device.SYSCFG.constrain();
         let mut exti = device.EXTI;
 
-        let gpioa = device.GPIOA.split();
-        let gpiob = device.GPIOB.split();
-        let gpioc = device.GPIOC.split();
+        let pins = board::Pins::new(
+            device.GPIOA.split(),
+            device.GPIOB.split(),
+            device.GPIOC.split(),
+            device.GPIOD.split(),
+        );
         let rcc = device.RCC.constrain();
-        let clocks = rcc
+        let cfgr = rcc
             .cfgr
-            .use_hse(8u32.MHz())
-            .sysclk(96.MHz())
-            .hclk(96.MHz())
-            .pclk1(50.MHz())
-            .pclk2(100.MHz())
-            .i2s_clk(61440.kHz())
-            .freeze();
+            .use_hse(board::HSE_HZ.Hz())
+            .sysclk(SYSCLK_HZ.Hz())
+            .hclk(SYSCLK_HZ.Hz())
+            .pclk1(chip::PCLK1_MAX_HZ.Hz())
+            .pclk2(chip::PCLK2_MAX_HZ.Hz());
+        let clocks = chip::i2s_clocks(cfgr).freeze();
 
         // I2S pins: (WS, CK, MCLK, SD) for I2S2
-        let mut i2s2_pins = (
-            gpiob.pb12, //WS
-            gpiob.pb13, //CK
-            gpioc.pc6,  //MCK
-            gpiob.pb15, //SD
-        );
+        let mut i2s2_pins = pins.i2s2;
         // set up an interrupt on WS pin
         i2s2_pins.0.make_interrupt_source(&mut syscfg);
         i2s2_pins.0.trigger_on_edge(&mut exti, Edge::Rising);
         let i2s2 = Some(I2s::new(device.SPI2, i2s2_pins, &clocks));
 
         // I2S3 pins: (WS, CK, NoPin, SD) for I2S3
-        let mut i2s3_pins = (gpioa.pa4, gpioc.pc10, NoPin, gpioc.pc12);
+        let mut i2s3_pins = pins.i2s3;
         // set up an interrupt on WS pin
         i2s3_pins.0.make_interrupt_source(&mut syscfg);
         i2s3_pins.0.trigger_on_edge(&mut exti, Edge::Rising);
         let i2s3 = Some(I2s::new(device.SPI3, i2s3_pins, &clocks));
 
+        #[cfg(has_i2s1)]
+        let i2s1 = {
+            let mut i2s1_pins = pins.i2s1;
+            i2s1_pins.0.make_interrupt_source(&mut syscfg);
+            i2s1_pins.0.trigger_on_edge(&mut exti, Edge::Rising);
+            I2s::new(device.SPI1, i2s1_pins, &clocks)
+        };
+        #[cfg(has_i2s5)]
+        let i2s5 = {
+            let mut i2s5_pins = pins.i2s5;
+            i2s5_pins.0.make_interrupt_source(&mut syscfg);
+            i2s5_pins.0.trigger_on_edge(&mut exti, Edge::Rising);
+            I2s::new(device.SPI5, i2s5_pins, &clocks)
+        };
+        let extra_i2s = Some(ExtraI2s {
+            #[cfg(has_i2s1)]
+            i2s1,
+            #[cfg(has_i2s5)]
+            i2s5,
+        });
+        let extra_drivers = ExtraDrivers {
+            #[cfg(has_i2s1)]
+            i2s1: DriverWrap::new(None),
+            #[cfg(has_i2s5)]
+            i2s5: DriverWrap::new(None),
+        };
+
+        // TIM2 inputs for WS timer capture, wired to PB12 and PA4
+        let _ = pins.ws_capture.0.into_alternate::<1>();
+        let _ = pins.ws_capture.1.into_alternate::<1>();
+        let ws_capture = WsCapture::new(device.TIM2, clocks.timclk1().raw());
+
+        // background load, stopped until a scenario sets a profile
+        let load_above = LoadTask::tim3(device.TIM3, clocks.timclk1().raw(), Level::Above);
+        let load_below = LoadTask::tim4(device.TIM4, clocks.timclk1().raw(), Level::Below);
+
+        // I2S2ext SD for the loopback test
+        #[cfg(has_i2sext)]
+        let _ = pins.i2s2ext_sd.into_alternate::<6>();
+
+        // codec control
+        #[cfg(feature = "disco-f407")]
+        let _ = pins.codec_reset.into_push_pull_output_in_state(PinState::High);
+        let codec_i2c = I2c::new(device.I2C1, pins.codec_i2c, 100.kHz(), &clocks);
+        let codec = Some(BoardCodec::new(codec_i2c));
+
         //i2s2_driver.enable();
         let i2s2_driver = DriverWrap::new(None); //Some(ReceiveDriver::Master(i2s2_driver));
         let i2s3_driver = DriverWrap::new(None); //Some(TransmitDriver::Slave(i2s3_driver));
@@ -189,182 +415,646 @@ mod app {
             Shared {
                 i2s2_driver,
                 i2s3_driver,
+                extra_drivers,
+                tx_data: TxData {
+                    data_16_c: tx_data_16_c,
+                    data_32_c: tx_data_32_c,
+                    blocks_d: tx_blocks_d,
+                    tdm_c: tx_tdm_c,
+                    mono_c: tx_mono_c,
+                },
+                rx_data: RxData {
+                    data_16_p: rx_data_16_p,
+                    data_32_p: rx_data_32_p,
+                    blocks_f: rx_blocks_f,
+                    tdm_p: rx_tdm_p,
+                    mono_p: rx_mono_p,
+                },
                 exti,
+                rx_blocks,
+                pipeline: Pipeline::bypass(),
+                ws_capture,
+                load_above,
+                load_below,
             },
             Local {
                 logs_chan,
                 i2s2,
                 i2s3,
-                i2s2_data_16_p,
-                i2s2_data_16_c,
-                i2s3_data_16_p,
-                i2s3_data_16_c,
-                i2s2_data_32_p,
-                i2s2_data_32_c,
-                i2s3_data_32_p,
-                i2s3_data_32_c,
+                extra_i2s,
+                codec,
+                progress: Some(progress),
+                watchdog: Some(watchdog),
+                rx_data_16_c,
+                tx_data_16_p,
+                rx_data_32_c,
+                tx_data_32_p,
+                rx_tdm_c,
+                tx_tdm_p,
+                rx_mono_c,
+                tx_mono_p,
+                tx_blocks_f,
             },
             init::Monotonics(),
         )
     }
 
     #[idle(
-        shared = [i2s2_driver, i2s3_driver,exti],
-        local = [i2s2, i2s3, i2s2_data_16_c, i2s3_data_16_p, i2s2_data_32_c, i2s3_data_32_p]
+        shared = [
+            i2s2_driver,
+            i2s3_driver,
+            extra_drivers,
+            exti,
+            tx_data,
+            rx_blocks,
+            pipeline,
+            ws_capture,
+            load_above,
+            load_below,
+        ],
+        local = [
+            i2s2,
+            i2s3,
+            extra_i2s,
+            codec,
+            progress,
+            watchdog,
+            rx_data_16_c,
+            tx_data_16_p,
+            rx_data_32_c,
+            tx_data_32_p,
+            rx_tdm_c,
+            tx_tdm_p,
+            rx_mono_c,
+            tx_mono_p,
+        ]
     )]
     fn idle(cx: idle::Context) -> ! {
         let i2s2 = cx.local.i2s2.take().unwrap();
         let i2s3 = cx.local.i2s3.take().unwrap();
-        let i2s2_data_16_c = cx.local.i2s2_data_16_c;
-        let i2s3_data_16_p = cx.local.i2s3_data_16_p;
-        let i2s2_data_32_c = cx.local.i2s2_data_32_c;
-        let i2s3_data_32_p = cx.local.i2s3_data_32_p;
+        #[allow(unused_variables, unused_mut)]
+        let mut extra_i2s = cx.local.extra_i2s.take().unwrap();
+        let mut codec = cx.local.codec.take().unwrap();
+        let mut runner = Runner::new(
+            cx.local.progress.take().unwrap(),
+            cx.local.watchdog.take().unwrap(),
+            |index| {
+                crash::set_scenario(index);
+                rprint!("#{} ", index)
+            },
+        );
+        let rx_data_16_c = cx.local.rx_data_16_c;
+        let tx_data_16_p = cx.local.tx_data_16_p;
+        let rx_data_32_c = cx.local.rx_data_32_c;
+        let tx_data_32_p = cx.local.tx_data_32_p;
+        let rx_tdm_c = cx.local.rx_tdm_c;
+        let tx_tdm_p = cx.local.tx_tdm_p;
+        let rx_mono_c = cx.local.rx_mono_c;
+        let tx_mono_p = cx.local.tx_mono_p;
         //let i2s2_ctl_p = cx.local.i2s2_ctl_p;
         //let i2s3_ctl_p = cx.local.i2s3_ctl_p;
         let mut shared_i2s2_driver = cx.shared.i2s2_driver;
         let mut shared_i2s3_driver = cx.shared.i2s3_driver;
+        #[allow(unused_variables, unused_mut)]
+        let mut shared_extra_drivers = cx.shared.extra_drivers;
         let mut shared_exti = cx.shared.exti;
+        let mut shared_tx_data = cx.shared.tx_data;
+        let mut shared_rx_blocks = cx.shared.rx_blocks;
+        let mut shared_pipeline = cx.shared.pipeline;
+        let mut shared_ws_capture = cx.shared.ws_capture;
+        let mut shared_load_above = cx.shared.load_above;
+        let mut shared_load_below = cx.shared.load_below;
 
-        let (i2s2, i2s3) = test::master_receive_slave_transmit_driver_interrupt(
-            &mut shared_exti,
-            &mut shared_i2s2_driver,
-            &mut shared_i2s3_driver,
-            i2s2_data_32_c,
-            i2s3_data_32_p,
-            i2s2,
-            i2s3,
-        );
+        // pre-flight, jumpers decide which tests can run
+        let wiring = Wiring::detect();
+        wiring.report();
+        #[allow(unused_mut)]
+        let mut i2s2 = runner.run(i2s2, |i2s2| tests_loopback::loopback(wiring, i2s2));
 
-        let (i2s2, i2s3) = test::slave_receive_master_transmit_driver_interrupt(
-            &mut shared_exti,
-            &mut shared_i2s2_driver,
-            &mut shared_i2s3_driver,
-            i2s2_data_32_c,
-            i2s3_data_32_p,
-            i2s2,
-            i2s3,
-        );
+        #[cfg(has_i2s1)]
+        for tx_master in [true, false] {
+            if !wiring.i2s1_ok() {
+                rprintln!("i2s1 wiring incomplete, i2s1 tests skipped");
+                break;
+            }
+            let mut shared_i2s1_driver =
+                ExtraDriver::new(&mut shared_extra_drivers, |extra| &mut extra.i2s1);
+            (extra_i2s.i2s1, i2s2) = runner.run((extra_i2s.i2s1, i2s2), |(i2s1, i2s2)| {
+                tests_instances::transmit_to_i2s2(
+                    &mut shared_exti,
+                    &mut shared_i2s1_driver,
+                    &mut shared_i2s2_driver,
+                    &mut shared_rx_blocks,
+                    tx_data_32_p,
+                    tx_master,
+                    i2s1,
+                    i2s2,
+                )
+            });
+        }
+        #[cfg(has_i2s5)]
+        for tx_master in [true, false] {
+            if !wiring.i2s5_ok() {
+                rprintln!("i2s5 wiring incomplete, i2s5 tests skipped");
+                break;
+            }
+            let mut shared_i2s5_driver =
+                ExtraDriver::new(&mut shared_extra_drivers, |extra| &mut extra.i2s5);
+            (extra_i2s.i2s5, i2s2) = runner.run((extra_i2s.i2s5, i2s2), |(i2s5, i2s2)| {
+                tests_instances::transmit_to_i2s2(
+                    &mut shared_exti,
+                    &mut shared_i2s5_driver,
+                    &mut shared_i2s2_driver,
+                    &mut shared_rx_blocks,
+                    tx_data_32_p,
+                    tx_master,
+                    i2s5,
+                    i2s2,
+                )
+            });
+        }
 
-        let (i2s2, i2s3) = test::master_transmit_transfer_block(
-            &mut shared_exti,
-            &mut shared_i2s2_driver,
-            i2s2_data_32_c,
-            i2s2,
-            i2s3,
-        );
+        let i2s3 = runner.run(i2s3, |i2s3| {
+            tests_codec::stream_to_codec(
+                &mut shared_exti,
+                &mut shared_i2s3_driver,
+                tx_data_32_p,
+                &mut codec,
+                i2s3,
+            )
+        });
 
-        let (i2s2, i2s3) = test::master_transmit_transfer_nb(
-            &mut shared_exti,
-            &mut shared_i2s2_driver,
-            i2s2_data_32_c,
-            i2s2,
-            i2s3,
-        );
+        if !wiring.i2s3_ok() {
+            rprintln!("i2s2 - i2s3 wiring incomplete, paired tests skipped");
+            end_of_tests(&mut runner);
+        }
 
-        let (i2s2, i2s3) = test::slave_transmit_transfer_block(
-            &mut shared_i2s2_driver,
-            i2s2_data_32_c,
-            i2s2,
-            i2s3,
-        );
+        // the paired matrix, receiving on one instance of the pair and transmitting from the other
+        macro_rules! paired_tests {
+            ($shared_rx_driver:ident, $shared_tx_driver:ident, $rx:expr, $tx:expr) => {{
+                let (rx, tx) = runner.run(($rx, $tx), |(rx, tx)| {
+                    test::master_receive_slave_transmit_driver_interrupt(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        &mut $shared_tx_driver,
+                        rx_data_32_c,
+                        tx_data_32_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) =
-            test::slave_transmit_transfer_nb(&mut shared_i2s2_driver, i2s2_data_32_c, i2s2, i2s3);
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::slave_receive_master_transmit_driver_interrupt(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        &mut $shared_tx_driver,
+                        rx_data_32_c,
+                        tx_data_32_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = test::master_receive_transfer_block(
-            &mut shared_exti,
-            &mut shared_i2s3_driver,
-            i2s3_data_32_p,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::master_receive_slave_transmit_blocks(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        &mut $shared_tx_driver,
+                        &mut shared_rx_blocks,
+                        tx_data_32_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = test::master_receive_transfer_nb(
-            &mut shared_exti,
-            &mut shared_i2s3_driver,
-            i2s3_data_32_p,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::slave_receive_master_transmit_passthrough(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        &mut $shared_tx_driver,
+                        &mut shared_pipeline,
+                        &mut shared_tx_data,
+                        tx_data_32_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) =
-            test::slave_receive_transfer_block(&mut shared_i2s3_driver, i2s3_data_32_p, i2s2, i2s3);
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::master_transmit_transfer_block(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        rx_data_32_c,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) =
-            test::slave_receive_transfer_nb(&mut shared_i2s3_driver, i2s3_data_32_p, i2s2, i2s3);
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::master_transmit_transfer_nb(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        rx_data_32_c,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = tests_16bits::master_receive_slave_transmit_driver_interrupt(
-            &mut shared_exti,
-            &mut shared_i2s2_driver,
-            &mut shared_i2s3_driver,
-            i2s2_data_16_c,
-            i2s3_data_16_p,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::slave_transmit_transfer_block(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        rx_data_32_c,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = tests_16bits::slave_receive_master_transmit_driver_interrupt(
-            &mut shared_exti,
-            &mut shared_i2s2_driver,
-            &mut shared_i2s3_driver,
-            i2s2_data_16_c,
-            i2s3_data_16_p,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::slave_transmit_transfer_nb(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        rx_data_32_c,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = tests_16bits::master_transmit_transfer_block(
-            &mut shared_exti,
-            &mut shared_i2s2_driver,
-            i2s2_data_16_c,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::master_receive_transfer_block(
+                        &mut shared_exti,
+                        &mut $shared_tx_driver,
+                        tx_data_32_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = tests_16bits::master_transmit_transfer_nb(
-            &mut shared_exti,
-            &mut shared_i2s2_driver,
-            i2s2_data_16_c,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::master_receive_transfer_nb(
+                        &mut shared_exti,
+                        &mut $shared_tx_driver,
+                        tx_data_32_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = tests_16bits::slave_transmit_transfer_block(
-            &mut shared_i2s2_driver,
-            i2s2_data_16_c,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::slave_receive_transfer_block(
+                        &mut shared_exti,
+                        &mut $shared_tx_driver,
+                        tx_data_32_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) =
-            tests_16bits::slave_transmit_transfer_nb(&mut shared_i2s2_driver, i2s2_data_16_c, i2s2, i2s3);
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    test::slave_receive_transfer_nb(
+                        &mut shared_exti,
+                        &mut $shared_tx_driver,
+                        tx_data_32_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = tests_16bits::master_receive_transfer_block(
-            &mut shared_exti,
-            &mut shared_i2s3_driver,
-            i2s3_data_16_p,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::master_receive_slave_transmit_driver_interrupt(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        &mut $shared_tx_driver,
+                        rx_data_16_c,
+                        tx_data_16_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) = tests_16bits::master_receive_transfer_nb(
-            &mut shared_exti,
-            &mut shared_i2s3_driver,
-            i2s3_data_16_p,
-            i2s2,
-            i2s3,
-        );
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::slave_receive_master_transmit_driver_interrupt(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        &mut $shared_tx_driver,
+                        rx_data_16_c,
+                        tx_data_16_p,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) =
-            tests_16bits::slave_receive_transfer_block(&mut shared_i2s3_driver, i2s3_data_16_p, i2s2, i2s3);
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::master_transmit_transfer_block(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        rx_data_16_c,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let (i2s2, i2s3) =
-            tests_16bits::slave_receive_transfer_nb(&mut shared_i2s3_driver, i2s3_data_16_p, i2s2, i2s3);
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::master_transmit_transfer_nb(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        rx_data_16_c,
+                        rx,
+                        tx,
+                    )
+                });
 
-        let _ = (i2s2, i2s3);
-        rprintln!("--- End of Tests");
-        #[allow(clippy::empty_loop)]
-        loop {}
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::slave_transmit_transfer_block(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        rx_data_16_c,
+                        rx,
+                        tx,
+                    )
+                });
+
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::slave_transmit_transfer_nb(
+                        &mut shared_exti,
+                        &mut $shared_rx_driver,
+                        rx_data_16_c,
+                        rx,
+                        tx,
+                    )
+                });
+
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::master_receive_transfer_block(
+                        &mut shared_exti,
+                        &mut $shared_tx_driver,
+                        tx_data_16_p,
+                        rx,
+                        tx,
+                    )
+                });
+
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::master_receive_transfer_nb(
+                        &mut shared_exti,
+                        &mut $shared_tx_driver,
+                        tx_data_16_p,
+                        rx,
+                        tx,
+                    )
+                });
+
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::slave_receive_transfer_block(
+                        &mut shared_exti,
+                        &mut $shared_tx_driver,
+                        tx_data_16_p,
+                        rx,
+                        tx,
+                    )
+                });
+
+                let (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                    tests_16bits::slave_receive_transfer_nb(
+                        &mut shared_exti,
+                        &mut $shared_tx_driver,
+                        tx_data_16_p,
+                        rx,
+                        tx,
+                    )
+                });
+
+                let (mut rx, mut tx) = (rx, tx);
+                for policy in recovery::POLICIES {
+                    for (name, fault) in tests_fault::FAULTS.iter() {
+                        (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                            tests_fault::fault_injection(
+                                &mut shared_exti,
+                                &mut $shared_rx_driver,
+                                &mut $shared_tx_driver,
+                                &mut shared_rx_blocks,
+                                tx_data_32_p,
+                                name,
+                                *fault,
+                                policy,
+                                rx,
+                                tx,
+                            )
+                        });
+                    }
+                }
+
+                for cycle in tests_cycle::CYCLE_KINDS {
+                    for bits in [16, 32] {
+                        for tx_master in [true, false] {
+                            (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                                tests_cycle::start_stop_cycling(
+                                    &mut shared_exti,
+                                    &mut $shared_rx_driver,
+                                    &mut $shared_tx_driver,
+                                    &mut shared_rx_blocks,
+                                    tx_data_16_p,
+                                    tx_data_32_p,
+                                    cycle,
+                                    bits,
+                                    tx_master,
+                                    rx,
+                                    tx,
+                                )
+                            });
+                        }
+                    }
+                }
+
+                for strategy in STRATEGIES {
+                    if strategy == SyncStrategy::TimerCapture && !wiring.ws_capture_ok() {
+                        rprintln!("{} sync tests skipped, WS capture wiring incomplete", strategy.name());
+                        continue;
+                    }
+                    for sample_rate in tests_sync::SAMPLE_RATES {
+                        (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                            tests_sync::slave_receive_sync(
+                                &mut shared_exti,
+                                &mut $shared_rx_driver,
+                                &mut $shared_tx_driver,
+                                &mut shared_rx_blocks,
+                                &mut shared_ws_capture,
+                                tx_data_32_p,
+                                strategy,
+                                sample_rate,
+                                rx,
+                                tx,
+                            )
+                        });
+                    }
+                }
+
+                for depth in [tests_signal::Depth::Bits16, tests_signal::Depth::Bits32] {
+                    for (name, signal) in tests_signal::SIGNALS.iter() {
+                        (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                            tests_signal::master_transmit_slave_receive_signal(
+                                &mut shared_exti,
+                                &mut $shared_rx_driver,
+                                &mut $shared_tx_driver,
+                                &mut shared_rx_blocks,
+                                tx_data_16_p,
+                                tx_data_32_p,
+                                name,
+                                *signal,
+                                depth,
+                                rx,
+                                tx,
+                            )
+                        });
+                    }
+                }
+
+                for frame_sync in tests_tdm::FRAME_SYNCS {
+                    for (slots, slot_bits) in [(4, 16), (8, 16), (4, 32), (8, 32)] {
+                        for tx_master in [true, false] {
+                            (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                                tests_tdm::transmit_tdm(
+                                    &mut shared_exti,
+                                    &mut $shared_rx_driver,
+                                    &mut $shared_tx_driver,
+                                    rx_tdm_c,
+                                    tx_tdm_p,
+                                    frame_sync,
+                                    slots,
+                                    slot_bits,
+                                    tx_master,
+                                    rx,
+                                    tx,
+                                )
+                            });
+                        }
+                    }
+                }
+
+                for mode in channel::CHANNEL_MODES {
+                    for on_transmit in [true, false] {
+                        (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                            tests_channel::stream_channel_mode(
+                                &mut shared_exti,
+                                &mut $shared_rx_driver,
+                                &mut $shared_tx_driver,
+                                rx_data_32_c,
+                                tx_data_32_p,
+                                rx_mono_c,
+                                tx_mono_p,
+                                mode,
+                                on_transmit,
+                                rx,
+                                tx,
+                            )
+                        });
+                    }
+                }
+
+                (rx, tx)
+            }};
+        }
+
+        let (i2s2, i2s3) = paired_tests!(shared_i2s2_driver, shared_i2s3_driver, i2s2, i2s3);
+
+        // same matrix with the roles of SPI2 and SPI3 swapped
+        rprintln!("--- Roles swapped, {} receives from {}", I2s3::NAME, I2s2::NAME);
+        let (i2s3, i2s2) = paired_tests!(shared_i2s3_driver, shared_i2s2_driver, i2s3, i2s2);
+
+        // stream under increasing load, from a task preempting the I2S tasks then from one they
+        // preempt
+        rprintln!("--- Load, {} receives from {}", I2s2::NAME, I2s3::NAME);
+        // set_layout lifts the ceilings of the drivers and tx_data, so the I2S tasks lock them
+        // even at priority 4, the onsets include that overhead
+        rprintln!("I2S drivers and tx_data locked at priority {}", LAYOUT_CEILING);
+        let (mut rx, mut tx) = (i2s2, i2s3);
+        macro_rules! load_sweep {
+            ($shared_load:ident) => {
+                for busy in BUSY {
+                    let mut onset = Onset::default();
+                    for percent in PERCENTS {
+                        for tx_master in [true, false] {
+                            (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                                tests_load::stream_under_load(
+                                    &mut shared_exti,
+                                    &mut shared_i2s2_driver,
+                                    &mut shared_i2s3_driver,
+                                    &mut shared_rx_blocks,
+                                    &mut $shared_load,
+                                    tx_data_32_p,
+                                    Profile { percent, busy },
+                                    tx_master,
+                                    &mut onset,
+                                    rx,
+                                    tx,
+                                )
+                            });
+                        }
+                    }
+                    let level = $shared_load.lock(|load_task| load_task.load.level());
+                    rprintln!("Load {} {}: {}", level.name(), busy.name(), onset);
+                }
+            };
+        }
+        load_sweep!(shared_load_above);
+        load_sweep!(shared_load_below);
+
+        // slave synchronisation with each priority layout of the SPI and WS tasks
+        let mut layout_results = LAYOUTS.map(|layout| LayoutResult {
+            layout,
+            passed: 0,
+            failed: 0,
+        });
+        for result in layout_results.iter_mut() {
+            rprintln!("--- Layout {}", result.layout.name());
+            set_layout::spawn(result.layout).ok();
+            let (passed, failed) = (runner.progress().passed, runner.progress().failed);
+            for strategy in STRATEGIES {
+                if strategy == SyncStrategy::TimerCapture && !wiring.ws_capture_ok() {
+                    continue;
+                }
+                for sample_rate in tests_sync::SAMPLE_RATES {
+                    (rx, tx) = runner.run((rx, tx), |(rx, tx)| {
+                        tests_sync::slave_receive_sync(
+                            &mut shared_exti,
+                            &mut shared_i2s2_driver,
+                            &mut shared_i2s3_driver,
+                            &mut shared_rx_blocks,
+                            &mut shared_ws_capture,
+                            tx_data_32_p,
+                            strategy,
+                            sample_rate,
+                            rx,
+                            tx,
+                        )
+                    });
+                }
+            }
+            result.passed = runner.progress().passed - passed;
+            result.failed = runner.progress().failed - failed;
+        }
+        set_layout::spawn(Layout::Equal).ok();
+        rprintln!("--- Priority layouts");
+        for result in layout_results {
+            rprintln!("{}", result);
+        }
+
+        let _ = (rx, tx);
+        end_of_tests(&mut runner);
+    }
+
+    fn end_of_tests(runner: &mut Runner<IndependentWatchdog>) -> ! {
+        let progress = runner.progress();
+        rprintln!(
+            "--- End of Tests, {} passed, {} failed, {} skipped",
+            progress.passed,
+            progress.failed,
+            progress.skipped
+        );
+        loop {
+            runner.feed();
+        }
     }
 
     // Printing message directly in a i2s interrupt can cause timing issues.
@@ -373,68 +1063,213 @@ mod app {
         writeln!(cx.local.logs_chan, "{} {}", time, msg).unwrap();
     }
 
-    #[task(
-        priority = 4,
-        binds = SPI2,
-        local = [
-            i2s2_data_16_p,
-            i2s2_data_32_p,
-        ],
-        shared = [i2s2_driver,exti]
-    )]
-    fn i2s2(cx: i2s2::Context) {
-        let i2s2_data_16_p = cx.local.i2s2_data_16_p;
-        let i2s2_data_32_p = cx.local.i2s2_data_32_p;
-        let mut i2s2_driver = cx.shared.i2s2_driver;
-        let mut exti = cx.shared.exti;
-        i2s2_driver.lock(|i2s2_driver| {
-            i2s2_driver.receive_interrupt_handler(&mut exti, i2s2_data_16_p, i2s2_data_32_p);
+    // Run received blocks through the processing pipeline and hand them to the
+    // transmitting instance.
+    #[task(priority = 2, shared = [rx_blocks, pipeline], local = [tx_blocks_f])]
+    fn process(cx: process::Context) {
+        let tx_blocks_f = cx.local.tx_blocks_f;
+        let rx_blocks = cx.shared.rx_blocks;
+        let pipeline = cx.shared.pipeline;
+        (rx_blocks, pipeline).lock(|rx_blocks, pipeline| {
+            if !pipeline.is_enabled() {
+                return;
+            }
+            while let Some(block) = rx_blocks.dequeue() {
+                pipeline.process(&mut block.frames);
+                if let Some(out) = tx_blocks_f.acquire() {
+                    out.frames = block.frames;
+                    tx_blocks_f.commit(out, DWT::cycle_count());
+                } else {
+                    pipeline.dropped += 1;
+                }
+                rx_blocks.release(block);
+            }
         });
     }
 
+    // Apply a priority layout to the SPI and WS tasks. `apply` touches no resource, the list
+    // only lifts to 5 the ceilings of the resources a raised task shares with a task left below
+    // it: the drivers between the groups and with idle, tx_data and ws_capture with idle.
+    // rx_data stays at 4, only the SPI tasks use it and they are always raised together.
     #[task(
-        priority = 4,
-        binds = SPI3,
-        local = [
-            i2s3_data_16_c,
-            i2s3_data_32_c,
-        ],
-        shared = [i2s3_driver,exti]
+        priority = 5,
+        shared = [i2s2_driver, i2s3_driver, extra_drivers, tx_data, ws_capture]
     )]
+    fn set_layout(_cx: set_layout::Context, layout: Layout) {
+        // sound, see `Layout::apply`
+        unsafe { layout.apply() };
+    }
+
+    // Serve the SPI interrupt of `driver` in either direction, received blocks go to `process`.
+    fn serve<I: I2sInstance>(
+        driver: &mut DriverWrap<I>,
+        tx_data: &mut TxData,
+        rx_data: &mut RxData,
+    ) {
+        if let Err(e) = driver.interrupt_handler(tx_data, rx_data) {
+            log::spawn(DWT::cycle_count(), e.as_str()).ok();
+        }
+        if rx_data.blocks_f.take_completed() {
+            process::spawn().ok();
+        }
+    }
+
+    #[task(priority = 4, binds = SPI2, shared = [i2s2_driver, tx_data, rx_data])]
+    fn i2s2(cx: i2s2::Context) {
+        let i2s2_driver = cx.shared.i2s2_driver;
+        let tx_data = cx.shared.tx_data;
+        let rx_data = cx.shared.rx_data;
+        (i2s2_driver, tx_data, rx_data).lock(|i2s2_driver, tx_data, rx_data| {
+            serve(i2s2_driver, tx_data, rx_data)
+        })
+    }
+
+    #[task(priority = 4, binds = SPI3, shared = [i2s3_driver, tx_data, rx_data])]
     fn i2s3(cx: i2s3::Context) {
-        let i2s3_data_16_c = cx.local.i2s3_data_16_c;
-        let i2s3_data_32_c = cx.local.i2s3_data_32_c;
-        let mut i2s3_driver = cx.shared.i2s3_driver;
-        let mut exti = cx.shared.exti;
-        i2s3_driver.lock(|i2s3_driver| {
-            i2s3_driver.transmit_interrupt_handler(&mut exti, i2s3_data_16_c, i2s3_data_32_c);
+        let i2s3_driver = cx.shared.i2s3_driver;
+        let tx_data = cx.shared.tx_data;
+        let rx_data = cx.shared.rx_data;
+        (i2s3_driver, tx_data, rx_data).lock(|i2s3_driver, tx_data, rx_data| {
+            serve(i2s3_driver, tx_data, rx_data)
+        })
+    }
+
+    #[cfg(has_i2s1)]
+    #[task(priority = 4, binds = SPI1, shared = [extra_drivers, tx_data, rx_data])]
+    fn i2s1(cx: i2s1::Context) {
+        let extra_drivers = cx.shared.extra_drivers;
+        let tx_data = cx.shared.tx_data;
+        let rx_data = cx.shared.rx_data;
+        (extra_drivers, tx_data, rx_data).lock(|extra_drivers, tx_data, rx_data| {
+            serve(&mut extra_drivers.i2s1, tx_data, rx_data)
+        })
+    }
+
+    #[cfg(has_i2s5)]
+    #[task(priority = 4, binds = SPI5, shared = [extra_drivers, tx_data, rx_data])]
+    fn i2s5(cx: i2s5::Context) {
+        let extra_drivers = cx.shared.extra_drivers;
+        let tx_data = cx.shared.tx_data;
+        let rx_data = cx.shared.rx_data;
+        (extra_drivers, tx_data, rx_data).lock(|extra_drivers, tx_data, rx_data| {
+            serve(&mut extra_drivers.i2s5, tx_data, rx_data)
         })
     }
 
+    // WS edges captured by the timer, for slave (re) synchronisation
+    #[task(priority = 4, binds = TIM2, shared = [i2s2_driver, i2s3_driver, ws_capture])]
+    fn tim2(cx: tim2::Context) {
+        let i2s2_driver = cx.shared.i2s2_driver;
+        let i2s3_driver = cx.shared.i2s3_driver;
+        let ws_capture = cx.shared.ws_capture;
+        (ws_capture, i2s2_driver, i2s3_driver).lock(|ws_capture, i2s2_driver, i2s3_driver| {
+            let (i2s2_edge, i2s3_edge) = ws_capture.take_edges();
+            if let Some(in_time) = i2s2_edge {
+                i2s2_driver.capture_handler(in_time);
+            }
+            if let Some(in_time) = i2s3_edge {
+                i2s3_driver.capture_handler(in_time);
+            }
+        });
+    }
+
+    // Background load preempting the I2S tasks, whatever their layout
+    #[task(priority = 6, binds = TIM3, shared = [load_above])]
+    fn tim3(cx: tim3::Context) {
+        let mut load_above = cx.shared.load_above;
+        load_above.lock(|load_task| load_task.run());
+    }
+
+    // Background load preempted by the I2S tasks
+    #[task(priority = 3, binds = TIM4, shared = [load_below])]
+    fn tim4(cx: tim4::Context) {
+        let mut load_below = cx.shared.load_below;
+        load_below.lock(|load_task| load_task.run());
+    }
+
     // Look i2s3 WS line for slave (re) synchronisation
-    #[task(priority = 4, binds = EXTI4, shared = [i2s3_driver,exti])]
+    #[task(priority = 4, binds = EXTI4, shared = [i2s3_driver])]
     fn exti4(cx: exti4::Context) {
-        let i2s3_driver = cx.shared.i2s3_driver;
-        let exti = cx.shared.exti;
-        (exti, i2s3_driver).lock(|exti, i2s3_driver| {
-            i2s3_driver.transmit_exti_handler(exti);
+        let mut i2s3_driver = cx.shared.i2s3_driver;
+        i2s3_driver.lock(|i2s3_driver| {
+            if let Err(e) = i2s3_driver.exti_handler() {
+                log::spawn(DWT::cycle_count(), e.as_str()).ok();
+            }
         });
     }
 
-    // Look i2s2 WS line for slave (re) synchronisation
-    #[task(priority = 4, binds = EXTI15_10, shared = [i2s2_driver,exti])]
+    // Look i2s2 WS line for slave (re) synchronisation, and i2s1 WS line sharing the interrupt
+    #[task(priority = 4, binds = EXTI15_10, shared = [i2s2_driver, extra_drivers])]
     fn exti15_10(cx: exti15_10::Context) {
         let i2s2_driver = cx.shared.i2s2_driver;
-        let exti = cx.shared.exti;
-        (exti, i2s2_driver).lock(|exti, i2s2_driver| {
-            i2s2_driver.receive_exti_handler(exti);
+        let extra_drivers = cx.shared.extra_drivers;
+        (i2s2_driver, extra_drivers).lock(|i2s2_driver, _extra_drivers| {
+            if let Err(e) = i2s2_driver.exti_handler() {
+                log::spawn(DWT::cycle_count(), e.as_str()).ok();
+            }
+            #[cfg(has_i2s1)]
+            if let Err(e) = _extra_drivers.i2s1.exti_handler() {
+                log::spawn(DWT::cycle_count(), e.as_str()).ok();
+            }
+        });
+    }
+
+    // Look i2s5 WS line for slave (re) synchronisation
+    #[cfg(has_i2s5)]
+    #[task(priority = 4, binds = EXTI1, shared = [extra_drivers])]
+    fn exti1(cx: exti1::Context) {
+        let mut extra_drivers = cx.shared.extra_drivers;
+        extra_drivers.lock(|extra_drivers| {
+            if let Err(e) = extra_drivers.i2s5.exti_handler() {
+                log::spawn(DWT::cycle_count(), e.as_str()).ok();
+            }
         });
     }
 }
 
+/// Fault status registers of the SCB
+fn fault_status() -> crash::FaultStatus {
+    let scb = unsafe { &*hal::pac::SCB::PTR };
+    crash::FaultStatus {
+        cfsr: scb.cfsr.read(),
+        hfsr: scb.hfsr.read(),
+        mmfar: scb.mmfar.read(),
+        bfar: scb.bfar.read(),
+        pc: 0,
+        lr: 0,
+    }
+}
+
+/// Keep `record` for the next boot.
+fn store_crash(record: &crash::CrashRecord) {
+    let mut buf = [0; crash::RECORD_LEN];
+    record.encode(&mut buf);
+    unsafe { (*core::ptr::addr_of_mut!(CRASH)).write(buf) };
+}
+
 #[inline(never)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    let mut record = crash::CrashRecord::new(crash::Kind::Panic);
+    write!(record.message, "{}", info.message()).ok();
+    if let Some(location) = info.location() {
+        record.set_location(location.file(), location.line(), location.column());
+    }
+    record.fault = fault_status();
+    store_crash(&record);
     rprintln!("{}", info);
     loop {} // You might need a compiler fence in here.
 }
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    let mut record = crash::CrashRecord::new(crash::Kind::HardFault);
+    record.fault = crash::FaultStatus {
+        pc: frame.pc(),
+        lr: frame.lr(),
+        ..fault_status()
+    };
+    store_crash(&record);
+    rprintln!("{}", record);
+    loop {}
+}
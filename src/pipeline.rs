@@ -0,0 +1,368 @@
+//! Audio processing applied to received blocks
+//!
+//! Stages implement [`Stage`] and are chained by putting them in a tuple, each stage processing
+//! the block in place after the previous one. Coefficients are fixed point Q2.30 numbers.
+
+use libm::{cosf, sinf};
+
+/// Fixed point representation of 1.0 for coefficients.
+pub const ONE: i32 = 1 << 30;
+
+/// Convert a float coefficient to Q2.30.
+pub fn q30(x: f32) -> i32 {
+    (x * ONE as f32) as i32
+}
+
+fn mul(x: i32, coef: i32) -> i64 {
+    (x as i64 * coef as i64) >> 30
+}
+
+fn saturate(x: i64) -> i32 {
+    x.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// A processing step applied to a block of `(left, right)` frames.
+pub trait Stage {
+    fn process(&mut self, frames: &mut [(i32, i32)]);
+}
+
+/// A bypassed stage
+impl<S: Stage> Stage for Option<S> {
+    fn process(&mut self, frames: &mut [(i32, i32)]) {
+        if let Some(stage) = self {
+            stage.process(frames);
+        }
+    }
+}
+
+impl<A: Stage, B: Stage> Stage for (A, B) {
+    fn process(&mut self, frames: &mut [(i32, i32)]) {
+        self.0.process(frames);
+        self.1.process(frames);
+    }
+}
+
+impl<A: Stage, B: Stage, C: Stage> Stage for (A, B, C) {
+    fn process(&mut self, frames: &mut [(i32, i32)]) {
+        self.0.process(frames);
+        self.1.process(frames);
+        self.2.process(frames);
+    }
+}
+
+impl<A: Stage, B: Stage, C: Stage, D: Stage> Stage for (A, B, C, D) {
+    fn process(&mut self, frames: &mut [(i32, i32)]) {
+        self.0.process(frames);
+        self.1.process(frames);
+        self.2.process(frames);
+        self.3.process(frames);
+    }
+}
+
+/// Per channel gain
+pub struct Gain {
+    pub left: i32,
+    pub right: i32,
+}
+
+impl Gain {
+    pub fn new(left: f32, right: f32) -> Self {
+        Self {
+            left: q30(left),
+            right: q30(right),
+        }
+    }
+
+    pub fn unity() -> Self {
+        Self {
+            left: ONE,
+            right: ONE,
+        }
+    }
+}
+
+impl Stage for Gain {
+    fn process(&mut self, frames: &mut [(i32, i32)]) {
+        for (l, r) in frames.iter_mut() {
+            *l = saturate(mul(*l, self.left));
+            *r = saturate(mul(*r, self.right));
+        }
+    }
+}
+
+/// Channel mixing matrix: `l' = ll * l + lr * r` and `r' = rl * l + rr * r`
+pub struct Mix {
+    pub ll: i32,
+    pub lr: i32,
+    pub rl: i32,
+    pub rr: i32,
+}
+
+impl Mix {
+    pub fn identity() -> Self {
+        Self {
+            ll: ONE,
+            lr: 0,
+            rl: 0,
+            rr: ONE,
+        }
+    }
+
+    pub fn swap() -> Self {
+        Self {
+            ll: 0,
+            lr: ONE,
+            rl: ONE,
+            rr: 0,
+        }
+    }
+
+    /// Both channels get the average of left and right.
+    pub fn mono() -> Self {
+        Self {
+            ll: ONE / 2,
+            lr: ONE / 2,
+            rl: ONE / 2,
+            rr: ONE / 2,
+        }
+    }
+}
+
+impl Stage for Mix {
+    fn process(&mut self, frames: &mut [(i32, i32)]) {
+        for (l, r) in frames.iter_mut() {
+            let (x, y) = (*l, *r);
+            *l = saturate(mul(x, self.ll) + mul(y, self.lr));
+            *r = saturate(mul(x, self.rl) + mul(y, self.rr));
+        }
+    }
+}
+
+/// DC removal, a one pole high-pass filter `y[n] = x[n] - x[n-1] + pole * y[n-1]`
+pub struct DcBlock {
+    pole: i32,
+    // (x[n-1], y[n-1]) for each channel
+    state: [(i32, i64); 2],
+}
+
+impl DcBlock {
+    /// `pole` close to 1.0 gives a low cutoff frequency, 0.995 is a usual value.
+    pub fn new(pole: f32) -> Self {
+        Self {
+            pole: q30(pole),
+            state: [(0, 0); 2],
+        }
+    }
+
+    fn step(pole: i32, state: &mut (i32, i64), x: i32) -> i32 {
+        let (x1, y1) = *state;
+        let y = x as i64 - x1 as i64 + ((y1 * pole as i64) >> 30);
+        *state = (x, y);
+        saturate(y)
+    }
+}
+
+impl Stage for DcBlock {
+    fn process(&mut self, frames: &mut [(i32, i32)]) {
+        let [left, right] = &mut self.state;
+        for (l, r) in frames.iter_mut() {
+            *l = Self::step(self.pole, left, *l);
+            *r = Self::step(self.pole, right, *r);
+        }
+    }
+}
+
+/// Second order IIR filter, direct form I with `a0` normalized to 1.
+pub struct Biquad {
+    b0: i32,
+    b1: i32,
+    b2: i32,
+    a1: i32,
+    a2: i32,
+    // (x[n-1], x[n-2], y[n-1], y[n-2]) for each channel
+    state: [(i32, i32, i32, i32); 2],
+}
+
+impl Biquad {
+    /// Coefficients in Q2.30, normalized so `a0` is 1.
+    pub fn from_coefficients(b0: i32, b1: i32, b2: i32, a1: i32, a2: i32) -> Self {
+        Self {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            state: [(0, 0, 0, 0); 2],
+        }
+    }
+
+    pub fn identity() -> Self {
+        Self::from_coefficients(ONE, 0, 0, 0, 0)
+    }
+
+    fn from_float(b: [f32; 3], a: [f32; 3]) -> Self {
+        Self::from_coefficients(
+            q30(b[0] / a[0]),
+            q30(b[1] / a[0]),
+            q30(b[2] / a[0]),
+            q30(a[1] / a[0]),
+            q30(a[2] / a[0]),
+        )
+    }
+
+    /// Low-pass with cutoff `freq` for sample rate `fs` (Audio EQ Cookbook formulas)
+    pub fn lowpass(freq: f32, fs: f32, q: f32) -> Self {
+        let w0 = 2.0 * core::f32::consts::PI * freq / fs;
+        let (cos, alpha) = (cosf(w0), sinf(w0) / (2.0 * q));
+        Self::from_float(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// High-pass with cutoff `freq` for sample rate `fs` (Audio EQ Cookbook formulas)
+    pub fn highpass(freq: f32, fs: f32, q: f32) -> Self {
+        let w0 = 2.0 * core::f32::consts::PI * freq / fs;
+        let (cos, alpha) = (cosf(w0), sinf(w0) / (2.0 * q));
+        Self::from_float(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    fn step(&self, state: &mut (i32, i32, i32, i32), x: i32) -> i32 {
+        let (x1, x2, y1, y2) = *state;
+        let acc = [
+            mul(x, self.b0),
+            mul(x1, self.b1),
+            mul(x2, self.b2),
+            -mul(y1, self.a1),
+            -mul(y2, self.a2),
+        ]
+        .iter()
+        .fold(0i64, |acc, v| acc.saturating_add(*v));
+        let y = saturate(acc);
+        *state = (x, x1, y, y1);
+        y
+    }
+}
+
+impl Stage for Biquad {
+    fn process(&mut self, frames: &mut [(i32, i32)]) {
+        let mut state = self.state;
+        for (l, r) in frames.iter_mut() {
+            *l = self.step(&mut state[0], *l);
+            *r = self.step(&mut state[1], *r);
+        }
+        self.state = state;
+    }
+}
+
+/// Stages of the reference passthrough, in processing order.
+pub type Passthrough = (Gain, Mix, Option<DcBlock>, Option<Biquad>);
+
+/// A processing chain and its statistics
+pub struct Pipeline<S> {
+    pub stages: S,
+    enabled: bool,
+    /// Blocks processed.
+    pub processed: u32,
+    /// Processed blocks dropped because the transmitter had no free block.
+    pub dropped: u32,
+}
+
+impl<S: Stage> Pipeline<S> {
+    pub fn new(stages: S) -> Self {
+        Self {
+            stages,
+            enabled: false,
+            processed: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Start processing received blocks, clearing statistics.
+    pub fn enable(&mut self) {
+        self.enabled = true;
+        self.processed = 0;
+        self.dropped = 0;
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn process(&mut self, frames: &mut [(i32, i32)]) {
+        self.stages.process(frames);
+        self.processed = self.processed.wrapping_add(1);
+    }
+}
+
+impl Pipeline<Passthrough> {
+    /// Passthrough leaving the signal untouched.
+    pub fn bypass() -> Self {
+        Self::new((Gain::unity(), Mix::identity(), None, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain() {
+        let mut frames = [(1000, -1000), (i32::MAX, i32::MIN)];
+        Gain::new(0.5, 2.0).process(&mut frames);
+        assert_eq!(frames[0], (500, -2000));
+        assert_eq!(frames[1], (i32::MAX / 2, i32::MIN));
+    }
+
+    #[test]
+    fn mix_swap_then_mono() {
+        let mut frames = [(1000, 3000)];
+        let mut stages = (Mix::swap(), Mix::mono());
+        stages.0.process(&mut frames);
+        assert_eq!(frames[0], (3000, 1000));
+        stages.1.process(&mut frames);
+        assert_eq!(frames[0], (2000, 2000));
+    }
+
+    #[test]
+    fn dc_block_removes_offset() {
+        let mut dc = DcBlock::new(0.995);
+        let mut frames = [(1 << 20, -(1 << 20)); 64];
+        for _ in 0..64 {
+            frames = [(1 << 20, -(1 << 20)); 64];
+            dc.process(&mut frames);
+        }
+        let (l, r) = frames[63];
+        assert!(l.abs() < 1 << 8 && r.abs() < 1 << 8);
+    }
+
+    #[test]
+    fn lowpass_dc_gain() {
+        let mut lp = Biquad::lowpass(1000.0, 48000.0, 0.707);
+        let mut frames = [(1 << 24, 0); 32];
+        for _ in 0..64 {
+            frames = [(1 << 24, 0); 32];
+            lp.process(&mut frames);
+        }
+        let (l, r) = frames[31];
+        assert!((l - (1 << 24)).abs() < 1 << 12);
+        assert_eq!(r, 0);
+    }
+
+    #[test]
+    fn bypass_is_transparent() {
+        let mut pipeline = Pipeline::bypass();
+        let mut frames = [(0x11113333, 0x7777EEEE), (-5, i32::MIN)];
+        let expected = frames;
+        pipeline.process(&mut frames);
+        assert_eq!(frames, expected);
+        assert_eq!(pipeline.processed, 1);
+    }
+}
@@ -0,0 +1,123 @@
+//! Priority layouts of the SPI and WS tasks
+//!
+//! The SPI tasks and the WS edge tasks (EXTI and TIM2 capture) are declared at priority 4, a
+//! layout raises one group at run time to see which order keeps slave synchronisation reliable.
+//! A raised task must not preempt a task holding a resource they share with an elided lock, so
+//! the `set_layout` task lifts the ceilings of those resources to `LAYOUT_CEILING`: the drivers,
+//! shared between the groups and with idle, and `tx_data` and `ws_capture`, shared with idle.
+//! `rx_data` is left at 4, only the SPI tasks use it and a layout raises them all together.
+
+use core::fmt;
+
+use crate::board;
+use crate::hal;
+
+use hal::pac::{Interrupt, NVIC_PRIO_BITS};
+
+/// Highest priority a layout gives, the ceiling of the resources of the SPI and WS tasks
+pub const LAYOUT_CEILING: u8 = 5;
+
+/// Priorities of the SPI and WS tasks
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Layout {
+    /// Both at 4, as declared
+    Equal,
+    /// WS tasks preempt the SPI tasks
+    ExtiAboveSpi,
+    /// SPI tasks preempt the WS tasks
+    SpiAboveExti,
+}
+
+/// All layouts, for scenarios comparing them
+pub const LAYOUTS: [Layout; 3] = [Layout::Equal, Layout::ExtiAboveSpi, Layout::SpiAboveExti];
+
+const SPI_INTERRUPTS: &[Interrupt] = &[
+    Interrupt::SPI2,
+    Interrupt::SPI3,
+    #[cfg(has_i2s1)]
+    Interrupt::SPI1,
+    #[cfg(has_i2s5)]
+    Interrupt::SPI5,
+];
+
+// the i2s1 WS pin shares the i2s2 one
+const WS_INTERRUPTS: &[Interrupt] = &[
+    board::I2S2_WS_EXTI,
+    board::I2S3_WS_EXTI,
+    #[cfg(has_i2s5)]
+    board::I2S5_WS_EXTI,
+    Interrupt::TIM2,
+];
+
+// NVIC value of an RTIC priority, higher priorities have lower values
+fn hw_priority(priority: u8) -> u8 {
+    ((1 << NVIC_PRIO_BITS) - priority) << (8 - NVIC_PRIO_BITS)
+}
+
+impl Layout {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Equal => "equal",
+            Layout::ExtiAboveSpi => "exti above spi",
+            Layout::SpiAboveExti => "spi above exti",
+        }
+    }
+
+    /// Priorities of the (SPI, WS) tasks
+    pub fn priorities(self) -> (u8, u8) {
+        match self {
+            Layout::Equal => (4, 4),
+            Layout::ExtiAboveSpi => (4, LAYOUT_CEILING),
+            Layout::SpiAboveExti => (LAYOUT_CEILING, 4),
+        }
+    }
+
+    /// Set the NVIC priorities of the layout.
+    ///
+    /// # Safety
+    ///
+    /// RTIC computes the resource ceilings from the declared priorities and elides the locks of
+    /// a task at the ceiling. Only call this from a task at `LAYOUT_CEILING` sharing every
+    /// resource a raised task shares with a task that may run below it, so no task runs above
+    /// the ceiling of a resource it locks or preempts an elided lock.
+    pub unsafe fn apply(self) {
+        let (spi, ws) = self.priorities();
+        let mut nvic = hal::pac::CorePeripherals::steal().NVIC;
+        for &interrupt in SPI_INTERRUPTS {
+            nvic.set_priority(interrupt, hw_priority(spi));
+        }
+        for &interrupt in WS_INTERRUPTS {
+            nvic.set_priority(interrupt, hw_priority(ws));
+        }
+    }
+}
+
+/// Sync scenarios passed and failed under a layout
+#[derive(Copy, Clone, Debug)]
+pub struct LayoutResult {
+    pub layout: Layout,
+    pub passed: u32,
+    pub failed: u32,
+}
+
+impl fmt::Display for LayoutResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.passed + self.failed;
+        match (total, self.failed) {
+            (0, _) => write!(f, "{}: not run", self.layout.name()),
+            (_, 0) => write!(
+                f,
+                "{}: reliable, {} sync scenarios passed",
+                self.layout.name(),
+                total
+            ),
+            (_, failed) => write!(
+                f,
+                "{}: unreliable, {}/{} sync scenarios failed",
+                self.layout.name(),
+                failed,
+                total
+            ),
+        }
+    }
+}
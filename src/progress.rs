@@ -0,0 +1,211 @@
+//! Progress of the suite across watchdog resets
+//!
+//! A scenario wedged in an interrupt storm must not silently stop the suite. The [`Runner`]
+//! feeds the independent watchdog between scenarios and keeps the running scenario and the
+//! verdicts so far in a [`Progress`] record left alone by the startup code. After a watchdog
+//! reset the scenario that hung is counted as failed and the suite resumes with the next one.
+//! Scenarios that can't run on the board, for lack of wiring or hardware, are counted apart.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_hal::watchdog::Watchdog;
+
+/// Marks a record written by a previous run
+const MAGIC: u32 = 0x5052_4F47;
+
+/// No scenario running
+const IDLE: u32 = u32::MAX;
+
+/// Set when the running scenario reports a failure
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Set when the running scenario can't run on this board
+static SKIPPED: AtomicBool = AtomicBool::new(false);
+
+/// Record the verdict of the running scenario, `ok` is given back to print it.
+pub fn verdict(ok: bool) -> bool {
+    if !ok {
+        FAILED.store(true, Ordering::Relaxed);
+    }
+    ok
+}
+
+/// Record that the running scenario can't run on this board, unless it also failed.
+pub fn skip() {
+    SKIPPED.store(true, Ordering::Relaxed);
+}
+
+/// Progress record, any content is valid as it's checked on start
+#[repr(C)]
+pub struct Progress {
+    magic: u32,
+    /// First scenario still to run
+    next: u32,
+    running: u32,
+    pub passed: u32,
+    pub failed: u32,
+    pub skipped: u32,
+}
+
+impl Progress {
+    pub const fn new() -> Self {
+        Self {
+            magic: MAGIC,
+            next: 0,
+            running: IDLE,
+            passed: 0,
+            failed: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Start a run, resuming the interrupted one after a watchdog reset. Returns the scenario
+    /// that hung, counted as failed.
+    pub fn start(&mut self, watchdog_reset: bool) -> Option<u32> {
+        if !watchdog_reset || self.magic != MAGIC {
+            *self = Self::new();
+            return None;
+        }
+        if self.running == IDLE {
+            return None;
+        }
+        let hung = self.running;
+        self.failed += 1;
+        self.next = hung + 1;
+        self.running = IDLE;
+        Some(hung)
+    }
+
+    /// `true` if scenarios were skipped, run before a watchdog reset
+    pub fn is_resumed(&self) -> bool {
+        self.next > 0
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs scenarios in order, feeding the watchdog in between
+pub struct Runner<'a, W> {
+    progress: &'a mut Progress,
+    watchdog: W,
+    // called with the index of each scenario before it runs
+    announce: fn(u32),
+    index: u32,
+}
+
+impl<'a, W: Watchdog> Runner<'a, W> {
+    pub fn new(progress: &'a mut Progress, watchdog: W, announce: fn(u32)) -> Self {
+        Self {
+            progress,
+            watchdog,
+            announce,
+            index: 0,
+        }
+    }
+
+    /// Run the next scenario on `peripherals`, unless it already ran before a watchdog reset.
+    pub fn run<T>(&mut self, peripherals: T, scenario: impl FnOnce(T) -> T) -> T {
+        let index = self.index;
+        self.index += 1;
+        if index < self.progress.next {
+            return peripherals;
+        }
+        self.watchdog.feed();
+        FAILED.store(false, Ordering::Relaxed);
+        SKIPPED.store(false, Ordering::Relaxed);
+        self.progress.running = index;
+        (self.announce)(index);
+        let peripherals = scenario(peripherals);
+        if FAILED.load(Ordering::Relaxed) {
+            self.progress.failed += 1;
+        } else if SKIPPED.load(Ordering::Relaxed) {
+            self.progress.skipped += 1;
+        } else {
+            self.progress.passed += 1;
+        }
+        self.progress.next = index + 1;
+        self.progress.running = IDLE;
+        self.watchdog.feed();
+        peripherals
+    }
+
+    pub fn progress(&self) -> &Progress {
+        self.progress
+    }
+
+    /// Keep the watchdog quiet once the suite is over.
+    pub fn feed(&mut self) {
+        self.watchdog.feed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter(u32);
+
+    impl Watchdog for Counter {
+        fn feed(&mut self) {
+            self.0 += 1;
+        }
+    }
+
+    // run the first `count` of 4 scenarios, the second one failing and the last one skipped
+    fn suite(runner: &mut Runner<Counter>, count: u32) -> u32 {
+        let mut ran = 0;
+        for index in 0..count {
+            ran = runner.run(ran, |ran| {
+                if index == 3 {
+                    skip();
+                } else {
+                    verdict(index != 1);
+                }
+                ran + 1
+            });
+        }
+        ran
+    }
+
+    // single test as verdicts are global
+    #[test]
+    fn resume_after_watchdog_reset() {
+        let mut progress = Progress {
+            magic: 0,
+            next: 3,
+            running: 2,
+            passed: 7,
+            failed: 7,
+            skipped: 7,
+        };
+        assert_eq!(progress.start(true), None);
+        let mut runner = Runner::new(&mut progress, Counter(0), |_| {});
+        assert_eq!(suite(&mut runner, 4), 4);
+        assert_eq!(runner.watchdog.0, 8);
+        assert_eq!(
+            (progress.passed, progress.failed, progress.skipped),
+            (2, 1, 1)
+        );
+
+        // not resumed after another kind of reset
+        assert_eq!(progress.start(false), None);
+        assert!(!progress.is_resumed());
+        let mut runner = Runner::new(&mut progress, Counter(0), |_| {});
+        assert_eq!(suite(&mut runner, 2), 2);
+        // the third scenario hangs
+        progress.running = 2;
+        assert_eq!(progress.start(true), Some(2));
+        assert!(progress.is_resumed());
+        let mut runner = Runner::new(&mut progress, Counter(0), |_| {});
+        // only the last one runs
+        assert_eq!(suite(&mut runner, 4), 1);
+        assert_eq!(
+            (progress.passed, progress.failed, progress.skipped),
+            (1, 2, 1)
+        );
+        assert_eq!(progress.start(true), None);
+    }
+}
@@ -0,0 +1,149 @@
+//! Recovery policy of the interrupt handlers
+//!
+//! Handlers report data seen on the unexpected channel (CHSIDE desynchronisation) and frame
+//! errors, [`Recovery`] tells if they resynchronise at the next left channel or restart the
+//! peripheral.
+
+/// Consecutive desynchronisations tolerated by the lenient policy before a restart
+pub const PERSISTENT_DESYNC: u32 = 4;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RecoveryPolicy {
+    /// Restart the peripheral on any desynchronisation
+    Strict,
+    /// Resynchronise at the next left channel, restart when desynchronisation persists
+    Lenient,
+}
+
+/// All policies, for scenarios comparing them
+pub const POLICIES: [RecoveryPolicy; 2] = [RecoveryPolicy::Strict, RecoveryPolicy::Lenient];
+
+impl RecoveryPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            RecoveryPolicy::Strict => "strict",
+            RecoveryPolicy::Lenient => "lenient",
+        }
+    }
+}
+
+/// What a handler must do to recover
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Recover {
+    /// Track the next left channel as the start of a frame
+    Resync,
+    /// Disable the peripheral and enable it again, at the next WS edge for a slave
+    Restart,
+}
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct RecoveryStats {
+    pub resyncs: u32,
+    pub restarts: u32,
+}
+
+pub struct Recovery {
+    policy: RecoveryPolicy,
+    // desynchronisations since the last complete frame
+    desync_run: u32,
+    pub stats: RecoveryStats,
+}
+
+impl Recovery {
+    pub const fn new(policy: RecoveryPolicy) -> Self {
+        Self {
+            policy,
+            desync_run: 0,
+            stats: RecoveryStats {
+                resyncs: 0,
+                restarts: 0,
+            },
+        }
+    }
+
+    pub fn policy(&self) -> RecoveryPolicy {
+        self.policy
+    }
+
+    /// Change policy, clearing statistics.
+    pub fn set_policy(&mut self, policy: RecoveryPolicy) {
+        *self = Self::new(policy);
+    }
+
+    /// A complete frame went through.
+    pub fn on_frame(&mut self) {
+        self.desync_run = 0;
+    }
+
+    /// Data was on the unexpected channel.
+    pub fn on_desync(&mut self) -> Recover {
+        self.desync_run += 1;
+        let persistent = match self.policy {
+            RecoveryPolicy::Strict => true,
+            RecoveryPolicy::Lenient => self.desync_run >= PERSISTENT_DESYNC,
+        };
+        if persistent {
+            self.restart()
+        } else {
+            self.stats.resyncs += 1;
+            Recover::Resync
+        }
+    }
+
+    /// A slave saw WS toggle at the wrong time, only a restart can recover.
+    pub fn on_frame_error(&mut self) -> Recover {
+        self.restart()
+    }
+
+    fn restart(&mut self) -> Recover {
+        self.desync_run = 0;
+        self.stats.restarts += 1;
+        Recover::Restart
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict_restarts_at_once() {
+        let mut recovery = Recovery::new(RecoveryPolicy::Strict);
+        assert_eq!(recovery.on_desync(), Recover::Restart);
+        assert_eq!(recovery.stats.restarts, 1);
+        assert_eq!(recovery.stats.resyncs, 0);
+    }
+
+    #[test]
+    fn lenient_restarts_when_persistent() {
+        let mut recovery = Recovery::new(RecoveryPolicy::Lenient);
+        for _ in 1..PERSISTENT_DESYNC {
+            assert_eq!(recovery.on_desync(), Recover::Resync);
+        }
+        assert_eq!(recovery.on_desync(), Recover::Restart);
+        // the count starts again after a restart
+        assert_eq!(recovery.on_desync(), Recover::Resync);
+        assert_eq!(recovery.stats.resyncs, PERSISTENT_DESYNC);
+        assert_eq!(recovery.stats.restarts, 1);
+    }
+
+    #[test]
+    fn lenient_forgives_after_a_frame() {
+        let mut recovery = Recovery::new(RecoveryPolicy::Lenient);
+        for _ in 0..3 * PERSISTENT_DESYNC {
+            assert_eq!(recovery.on_desync(), Recover::Resync);
+            recovery.on_frame();
+        }
+        assert_eq!(recovery.stats.restarts, 0);
+    }
+
+    #[test]
+    fn frame_error_always_restarts() {
+        for policy in [RecoveryPolicy::Strict, RecoveryPolicy::Lenient] {
+            let mut recovery = Recovery::new(policy);
+            assert_eq!(recovery.on_frame_error(), Recover::Restart);
+            recovery.set_policy(policy);
+            assert_eq!(recovery.stats.restarts, 0);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! Fixed point test signal generator
+//!
+//! Signals are produced from a 32 bits phase accumulator, a full turn being 2^32. Samples are
+//! full scale `i32`, 16 bits samples are their most significant half.
+
+use heapless::spsc::Producer;
+
+/// Maximum number of tones of a `Signal::MultiTone`
+pub const MAX_TONES: usize = 4;
+
+// Taylor series of sin(pi/2 * x) in Q2.30, odd terms from x to x^9
+const SIN_COEFS: [i64; 5] = [1686629713, -693598668, 85569306, -5026995, 172272];
+
+/// Sine of a 32 bits phase, in Q1.31
+pub fn sin_q31(phase: u32) -> i32 {
+    let quadrant = phase >> 30;
+    let mut x = (phase & 0x3FFF_FFFF) as i64;
+    if quadrant & 1 == 1 {
+        x = (1 << 30) - x;
+    }
+    let x2 = (x * x) >> 30;
+    let mut acc = 0;
+    for coef in SIN_COEFS.iter().rev() {
+        acc = coef + ((acc * x2) >> 30);
+    }
+    let y = ((acc * x) >> 29).min(i32::MAX as i64) as i32;
+    if quadrant & 2 == 2 {
+        -y
+    } else {
+        y
+    }
+}
+
+/// Description of a signal, frequencies are in Hz and amplitudes relative to full scale.
+#[derive(Copy, Clone)]
+pub enum Signal {
+    Sine {
+        freq: f32,
+        amplitude: f32,
+    },
+    Square {
+        freq: f32,
+        amplitude: f32,
+    },
+    /// Linear chirp from `start` to `end` in `duration` seconds, then start again
+    Sweep {
+        start: f32,
+        end: f32,
+        duration: f32,
+        amplitude: f32,
+    },
+    /// Sum of the `count` first sines of `freqs` with equal amplitude, `amplitude` being the
+    /// peak of the sum
+    MultiTone {
+        freqs: [f32; MAX_TONES],
+        count: usize,
+        amplitude: f32,
+    },
+    /// Uniform white noise
+    Noise {
+        seed: u32,
+        amplitude: f32,
+    },
+}
+
+impl Signal {
+    /// Peak amplitude relative to full scale
+    pub fn amplitude(&self) -> f32 {
+        match *self {
+            Signal::Sine { amplitude, .. }
+            | Signal::Square { amplitude, .. }
+            | Signal::Sweep { amplitude, .. }
+            | Signal::MultiTone { amplitude, .. }
+            | Signal::Noise { amplitude, .. } => amplitude,
+        }
+    }
+
+    /// Fundamental frequency of single frequency signals
+    pub fn frequency(&self) -> Option<f32> {
+        match *self {
+            Signal::Sine { freq, .. } | Signal::Square { freq, .. } => Some(freq),
+            _ => None,
+        }
+    }
+}
+
+enum Shape {
+    Sine {
+        phase: u32,
+        inc: u32,
+    },
+    Square {
+        phase: u32,
+        inc: u32,
+    },
+    Sweep {
+        phase: u32,
+        inc: u32,
+        start: u32,
+        step: i32,
+        len: u32,
+        n: u32,
+    },
+    MultiTone {
+        tones: [(u32, u32); MAX_TONES],
+        count: usize,
+    },
+    Noise {
+        state: u32,
+    },
+}
+
+pub struct Generator {
+    shape: Shape,
+    amplitude: i32,
+}
+
+fn phase_inc(freq: f32, sample_rate: u32) -> u32 {
+    (freq as f64 / sample_rate as f64 * 4294967296.0) as u32
+}
+
+fn q31(x: f32) -> i32 {
+    (x as f64 * 2147483648.0).clamp(0.0, i32::MAX as f64) as i32
+}
+
+fn scale(x: i32, amplitude: i32) -> i32 {
+    ((x as i64 * amplitude as i64) >> 31) as i32
+}
+
+impl Generator {
+    pub fn new(signal: Signal, sample_rate: u32) -> Self {
+        match signal {
+            Signal::Sine { freq, amplitude } => Self {
+                shape: Shape::Sine {
+                    phase: 0,
+                    inc: phase_inc(freq, sample_rate),
+                },
+                amplitude: q31(amplitude),
+            },
+            Signal::Square { freq, amplitude } => Self {
+                shape: Shape::Square {
+                    phase: 0,
+                    inc: phase_inc(freq, sample_rate),
+                },
+                amplitude: q31(amplitude),
+            },
+            Signal::Sweep {
+                start,
+                end,
+                duration,
+                amplitude,
+            } => {
+                let len = ((duration * sample_rate as f32) as u32).max(1);
+                let start = phase_inc(start, sample_rate);
+                let end = phase_inc(end, sample_rate);
+                Self {
+                    shape: Shape::Sweep {
+                        phase: 0,
+                        inc: start,
+                        start,
+                        step: ((end as i64 - start as i64) / len as i64) as i32,
+                        len,
+                        n: 0,
+                    },
+                    amplitude: q31(amplitude),
+                }
+            }
+            Signal::MultiTone {
+                freqs,
+                count,
+                amplitude,
+            } => {
+                let mut tones = [(0, 0); MAX_TONES];
+                let count = count.min(MAX_TONES);
+                for (tone, freq) in tones.iter_mut().zip(freqs[..count].iter()) {
+                    *tone = (0, phase_inc(*freq, sample_rate));
+                }
+                Self {
+                    shape: Shape::MultiTone { tones, count },
+                    amplitude: q31(amplitude / count.max(1) as f32),
+                }
+            }
+            Signal::Noise { seed, amplitude } => Self {
+                shape: Shape::Noise { state: seed.max(1) },
+                amplitude: q31(amplitude),
+            },
+        }
+    }
+
+    pub fn next_sample(&mut self) -> i32 {
+        let amplitude = self.amplitude;
+        match self.shape {
+            Shape::Sine {
+                ref mut phase,
+                inc,
+            } => {
+                let s = sin_q31(*phase);
+                *phase = phase.wrapping_add(inc);
+                scale(s, amplitude)
+            }
+            Shape::Square {
+                ref mut phase,
+                inc,
+            } => {
+                let s = if *phase < 1 << 31 {
+                    amplitude
+                } else {
+                    -amplitude
+                };
+                *phase = phase.wrapping_add(inc);
+                s
+            }
+            Shape::Sweep {
+                ref mut phase,
+                ref mut inc,
+                start,
+                step,
+                len,
+                ref mut n,
+            } => {
+                let s = sin_q31(*phase);
+                *phase = phase.wrapping_add(*inc);
+                *n += 1;
+                if *n == len {
+                    *n = 0;
+                    *inc = start;
+                } else {
+                    *inc = inc.wrapping_add(step as u32);
+                }
+                scale(s, amplitude)
+            }
+            Shape::MultiTone {
+                ref mut tones,
+                count,
+            } => {
+                let mut s = 0;
+                for (phase, inc) in tones[..count].iter_mut() {
+                    s += scale(sin_q31(*phase), amplitude);
+                    *phase = phase.wrapping_add(*inc);
+                }
+                s
+            }
+            Shape::Noise { ref mut state } => {
+                // xorshift32
+                *state ^= *state << 13;
+                *state ^= *state >> 17;
+                *state ^= *state << 5;
+                scale(*state as i32, amplitude)
+            }
+        }
+    }
+
+    /// Next frame, with the same sample on both channels.
+    pub fn next_frame(&mut self) -> (i32, i32) {
+        let s = self.next_sample();
+        (s, s)
+    }
+
+    /// Next frame for a 16 bits stream.
+    pub fn next_frame_16(&mut self) -> (i16, i16) {
+        let s = (self.next_sample() >> 16) as i16;
+        (s, s)
+    }
+
+    /// Top up a transmit queue.
+    pub fn fill<const N: usize>(&mut self, data_p: &mut Producer<'static, (i32, i32), N>) {
+        while data_p.ready() {
+            data_p.enqueue(self.next_frame()).ok();
+        }
+    }
+
+    /// Top up a 16 bits transmit queue.
+    pub fn fill_16<const N: usize>(&mut self, data_p: &mut Producer<'static, (i16, i16), N>) {
+        while data_p.ready() {
+            data_p.enqueue(self.next_frame_16()).ok();
+        }
+    }
+}
+
+/// Frequency of a periodic signal from its rising zero crossings, `None` if it has less than
+/// two of them.
+pub fn zero_crossing_frequency(samples: &[i32], sample_rate: u32) -> Option<f32> {
+    let mut first = None;
+    let mut last = 0.0;
+    let mut count = 0;
+    for (i, w) in samples.windows(2).enumerate() {
+        let (a, b) = (w[0] as f32, w[1] as f32);
+        if a < 0.0 && b >= 0.0 {
+            // interpolate the crossing position between the two samples
+            let t = i as f32 + a / (a - b);
+            first.get_or_insert(t);
+            last = t;
+            count += 1;
+        }
+    }
+    match first {
+        Some(first) if count >= 2 => Some((count - 1) as f32 * sample_rate as f32 / (last - first)),
+        _ => None,
+    }
+}
+
+/// Largest absolute sample value
+pub fn peak(samples: &[i32]) -> u32 {
+    samples.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: u32 = 48000;
+
+    fn render(signal: Signal) -> [i32; 4800] {
+        let mut generator = Generator::new(signal, FS);
+        let mut samples = [0; 4800];
+        for s in samples.iter_mut() {
+            *s = generator.next_sample();
+        }
+        samples
+    }
+
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            libm::fabsf(value - expected) <= expected * tolerance,
+            "{} is not within {}% of {}",
+            value,
+            tolerance * 100.0,
+            expected
+        );
+    }
+
+    #[test]
+    fn sin_accuracy() {
+        for i in 0..4096u32 {
+            let phase = i << 20;
+            let expected = libm::sin(phase as f64 / 4294967296.0 * 2.0 * core::f64::consts::PI);
+            let got = sin_q31(phase) as f64 / 2147483648.0;
+            assert!(libm::fabs(got - expected) < 1e-5, "phase {:#x}", phase);
+        }
+    }
+
+    #[test]
+    fn sine_frequency_and_amplitude() {
+        let samples = render(Signal::Sine {
+            freq: 1000.0,
+            amplitude: 0.5,
+        });
+        let freq = zero_crossing_frequency(&samples, FS).unwrap();
+        assert_close(freq, 1000.0, 0.001);
+        assert_close(peak(&samples) as f32, 0.5 * 2147483648.0, 0.001);
+    }
+
+    #[test]
+    fn square_frequency_and_amplitude() {
+        let samples = render(Signal::Square {
+            freq: 480.0,
+            amplitude: 0.25,
+        });
+        let freq = zero_crossing_frequency(&samples, FS).unwrap();
+        assert_close(freq, 480.0, 0.01);
+        assert_eq!(peak(&samples), (0.25 * 2147483648.0) as u32);
+    }
+
+    #[test]
+    fn multitone_stays_within_amplitude() {
+        let samples = render(Signal::MultiTone {
+            freqs: [440.0, 1000.0, 3000.0, 0.0],
+            count: 3,
+            amplitude: 0.9,
+        });
+        let peak = peak(&samples) as f32;
+        assert!(peak <= 0.9 * 2147483648.0);
+        assert!(peak > 0.5 * 2147483648.0);
+    }
+
+    #[test]
+    fn sweep_goes_up() {
+        let samples = render(Signal::Sweep {
+            start: 100.0,
+            end: 4000.0,
+            duration: 0.1,
+            amplitude: 0.5,
+        });
+        let low = zero_crossing_frequency(&samples[..1200], FS).unwrap();
+        let high = zero_crossing_frequency(&samples[3600..], FS).unwrap();
+        assert!(low < 1200.0 && high > 2800.0);
+    }
+
+    #[test]
+    fn noise_is_bounded_and_centered() {
+        let samples = render(Signal::Noise {
+            seed: 1,
+            amplitude: 0.5,
+        });
+        assert!(peak(&samples) as f32 <= 0.5 * 2147483648.0);
+        let mean = samples.iter().map(|s| *s as f64).sum::<f64>() / samples.len() as f64;
+        assert!(libm::fabs(mean) < 0.05 * 2147483648.0);
+    }
+}
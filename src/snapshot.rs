@@ -0,0 +1,404 @@
+//! Register snapshots
+//!
+//! A failed scenario dumps the SPI/I2S registers of its two instances and the clock tree
+//! registers as programmed at failure time, raw, one line each. `tools/decode` parses those lines
+//! from the log and decodes them field by field.
+
+use core::fmt;
+
+/// Clock of the internal oscillator, source of the PLLs when not using HSE
+const HSI_HZ: u32 = 16_000_000;
+
+/// SPI/I2S registers of an instance
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct SpiRegs {
+    pub cr1: u32,
+    pub cr2: u32,
+    pub sr: u32,
+    pub i2scfgr: u32,
+    pub i2spr: u32,
+}
+
+/// Clock tree registers, with the HSE clock of the board
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct RccRegs {
+    pub hse_hz: u32,
+    pub cr: u32,
+    pub pllcfgr: u32,
+    pub cfgr: u32,
+    pub plli2scfgr: u32,
+}
+
+/// Registers of the two instances of a scenario and of the clock tree
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Snapshot {
+    pub instances: [(&'static str, SpiRegs); 2],
+    pub rcc: RccRegs,
+}
+
+const SPI_NAMES: [&str; 5] = ["CR1", "CR2", "SR", "I2SCFGR", "I2SPR"];
+const RCC_NAMES: [&str; 5] = ["HSE", "CR", "PLLCFGR", "CFGR", "PLLI2SCFGR"];
+
+/// Raw dump, a `regs` line per instance and one for the clock tree
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, regs) in self.instances {
+            write!(f, "  regs {}", name)?;
+            let values = [regs.cr1, regs.cr2, regs.sr, regs.i2scfgr, regs.i2spr];
+            for (field, value) in SPI_NAMES.iter().zip(values) {
+                write!(f, " {} {:08x}", field, value)?;
+            }
+            writeln!(f)?;
+        }
+        let rcc = &self.rcc;
+        write!(f, "  regs rcc HSE {}", rcc.hse_hz)?;
+        let values = [rcc.cr, rcc.pllcfgr, rcc.cfgr, rcc.plli2scfgr];
+        for (field, value) in RCC_NAMES[1..].iter().zip(values) {
+            write!(f, " {} {:08x}", field, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed `regs` line
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Line<'a> {
+    Spi(&'a str, SpiRegs),
+    Rcc(RccRegs),
+}
+
+/// Parse a line of a raw dump, `None` if it isn't one.
+pub fn parse_line(line: &str) -> Option<Line<'_>> {
+    let mut tokens = line.split_whitespace().skip_while(|token| *token != "regs");
+    tokens.next()?;
+    let name = tokens.next()?;
+    let (names, radixes) = match name {
+        "rcc" => (RCC_NAMES, [10, 16, 16, 16, 16]),
+        _ => (SPI_NAMES, [16; 5]),
+    };
+    let mut values = [0; 5];
+    for ((field, radix), value) in names.iter().zip(radixes).zip(values.iter_mut()) {
+        if tokens.next()? != *field {
+            return None;
+        }
+        *value = u32::from_str_radix(tokens.next()?, radix).ok()?;
+    }
+    let [a, b, c, d, e] = values;
+    Some(match name {
+        "rcc" => Line::Rcc(RccRegs {
+            hse_hz: a,
+            cr: b,
+            pllcfgr: c,
+            cfgr: d,
+            plli2scfgr: e,
+        }),
+        _ => Line::Spi(
+            name,
+            SpiRegs {
+                cr1: a,
+                cr2: b,
+                sr: c,
+                i2scfgr: d,
+                i2spr: e,
+            },
+        ),
+    })
+}
+
+fn field(reg: u32, shift: u32, width: u32) -> u32 {
+    (reg >> shift) & ((1 << width) - 1)
+}
+
+fn bit(reg: u32, shift: u32) -> bool {
+    field(reg, shift, 1) == 1
+}
+
+// write the names of the set bits, `-` if none
+fn flags(f: &mut fmt::Formatter<'_>, reg: u32, names: &[(u32, &str)]) -> fmt::Result {
+    let mut none = true;
+    for (shift, name) in names {
+        if bit(reg, *shift) {
+            write!(f, " {}", name)?;
+            none = false;
+        }
+    }
+    if none {
+        write!(f, " -")?;
+    }
+    Ok(())
+}
+
+impl SpiRegs {
+    pub fn is_master(&self) -> bool {
+        bit(self.i2scfgr, 9)
+    }
+
+    /// Channel length, in bits
+    pub fn channel_bits(&self) -> u32 {
+        if bit(self.i2scfgr, 0) {
+            32
+        } else {
+            16
+        }
+    }
+
+    /// Sample rate of a master clocked by `i2s_clk_hz`, from I2SDIV, ODD, MCKOE and CHLEN
+    pub fn sample_rate(&self, i2s_clk_hz: u32) -> Option<f32> {
+        let div = 2 * field(self.i2spr, 0, 8) + field(self.i2spr, 8, 1);
+        if !self.is_master() || div < 2 {
+            return None;
+        }
+        let bit_clocks = if bit(self.i2spr, 9) {
+            256
+        } else {
+            2 * self.channel_bits()
+        };
+        Some(i2s_clk_hz as f32 / (bit_clocks * div) as f32)
+    }
+
+    /// Field by field decoding, with the sample rate when clocked by `i2s_clk_hz`
+    pub fn decode(&self, i2s_clk_hz: Option<u32>) -> DecodedSpi<'_> {
+        DecodedSpi {
+            regs: self,
+            i2s_clk_hz,
+        }
+    }
+}
+
+pub struct DecodedSpi<'a> {
+    regs: &'a SpiRegs,
+    i2s_clk_hz: Option<u32>,
+}
+
+impl<'a> fmt::Display for DecodedSpi<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let SpiRegs {
+            cr1,
+            cr2,
+            sr,
+            i2scfgr,
+            i2spr,
+        } = *self.regs;
+        let mode = if bit(i2scfgr, 11) { "I2S" } else { "SPI" };
+        let role = [
+            "slave transmit",
+            "slave receive",
+            "master transmit",
+            "master receive",
+        ];
+        let standard = ["Philips", "MSB justified", "LSB justified", "PCM"];
+        let data_len = ["16 bits", "24 bits", "32 bits", "invalid"];
+        let sync = if bit(i2scfgr, 7) { "long" } else { "short" };
+        writeln!(
+            f,
+            "  I2SMOD {}, I2SE {}, I2SCFG {}",
+            mode,
+            bit(i2scfgr, 10) as u8,
+            role[field(i2scfgr, 8, 2) as usize]
+        )?;
+        writeln!(
+            f,
+            "  I2SSTD {}, PCMSYNC {}, DATLEN {}, CHLEN {} bits, CKPOL {}",
+            standard[field(i2scfgr, 4, 2) as usize],
+            sync,
+            data_len[field(i2scfgr, 1, 2) as usize],
+            self.regs.channel_bits(),
+            if bit(i2scfgr, 3) { "high" } else { "low" }
+        )?;
+        write!(
+            f,
+            "  I2SDIV {}, ODD {}, MCKOE {}",
+            field(i2spr, 0, 8),
+            bit(i2spr, 8) as u8,
+            bit(i2spr, 9) as u8
+        )?;
+        if let Some(fs) = self.i2s_clk_hz.and_then(|clk| self.regs.sample_rate(clk)) {
+            write!(f, ", sample rate {:.1} Hz", fs)?;
+        }
+        write!(f, "\n  CR1 {:#010x}\n  CR2", cr1)?;
+        let cr2_flags = [
+            (0, "RXDMAEN"),
+            (1, "TXDMAEN"),
+            (2, "SSOE"),
+            (4, "FRF"),
+            (5, "ERRIE"),
+            (6, "RXNEIE"),
+            (7, "TXEIE"),
+        ];
+        flags(f, cr2, &cr2_flags)?;
+        write!(f, "\n  SR")?;
+        let sr_flags = [
+            (0, "RXNE"),
+            (1, "TXE"),
+            (2, "CHSIDE"),
+            (3, "UDR"),
+            (4, "CRCERR"),
+            (5, "MODF"),
+            (6, "OVR"),
+            (7, "BSY"),
+            (8, "FRE"),
+        ];
+        flags(f, sr, &sr_flags)
+    }
+}
+
+impl RccRegs {
+    /// Clock of the PLLs
+    fn pll_input_hz(&self) -> u32 {
+        if bit(self.pllcfgr, 22) {
+            self.hse_hz
+        } else {
+            HSI_HZ
+        }
+    }
+
+    /// PLLI2S input division, PLLM on chips without PLLI2SM, where it reads 0
+    fn plli2s_m(&self) -> u32 {
+        match field(self.plli2scfgr, 0, 6) {
+            0 => field(self.pllcfgr, 0, 6),
+            m => m,
+        }
+    }
+
+    /// I2S clock from PLLI2S, `None` if it's off, external or misconfigured
+    pub fn i2s_clk_hz(&self) -> Option<u32> {
+        let (m, n, r) = (
+            self.plli2s_m(),
+            field(self.plli2scfgr, 6, 9),
+            field(self.plli2scfgr, 28, 3),
+        );
+        if !bit(self.cr, 26) || bit(self.cfgr, 23) || m == 0 || r == 0 {
+            return None;
+        }
+        Some((self.pll_input_hz() as u64 * n as u64 / (m as u64 * r as u64)) as u32)
+    }
+}
+
+impl fmt::Display for RccRegs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = if bit(self.pllcfgr, 22) { "HSE" } else { "HSI" };
+        writeln!(
+            f,
+            "  PLL source {} {} Hz, PLLM {}, PLLN {}, PLLP {}, PLLQ {}, PLLON {}",
+            source,
+            self.pll_input_hz(),
+            field(self.pllcfgr, 0, 6),
+            field(self.pllcfgr, 6, 9),
+            2 * (field(self.pllcfgr, 16, 2) + 1),
+            field(self.pllcfgr, 24, 4),
+            bit(self.cr, 24) as u8
+        )?;
+        writeln!(
+            f,
+            "  PLLI2SM {}, PLLI2SN {}, PLLI2SR {}, PLLI2SON {}, PLLI2SRDY {}",
+            self.plli2s_m(),
+            field(self.plli2scfgr, 6, 9),
+            field(self.plli2scfgr, 28, 3),
+            bit(self.cr, 26) as u8,
+            bit(self.cr, 27) as u8
+        )?;
+        let i2s_src = if bit(self.cfgr, 23) {
+            "I2S_CKIN"
+        } else {
+            "PLLI2S"
+        };
+        write!(f, "  I2SSRC {}", i2s_src)?;
+        match self.i2s_clk_hz() {
+            Some(clk) => write!(f, ", I2S clock {} Hz", clk),
+            None => write!(f, ", I2S clock unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::format;
+
+    // i2s2 master receive, Philips 32 bits, I2SDIV 2 ODD 1 MCKOE, at 50 kHz from a 64 MHz I2S
+    // clock, i2s3 slave transmit
+    const SNAPSHOT: Snapshot = Snapshot {
+        instances: [
+            (
+                "i2s2",
+                SpiRegs {
+                    cr1: 0,
+                    cr2: 0x60,
+                    sr: 0x02,
+                    i2scfgr: 0x0f05,
+                    i2spr: 0x0302,
+                },
+            ),
+            (
+                "i2s3",
+                SpiRegs {
+                    cr1: 0,
+                    cr2: 0xa0,
+                    sr: 0x0106,
+                    i2scfgr: 0x0c05,
+                    i2spr: 0x0002,
+                },
+            ),
+        ],
+        rcc: RccRegs {
+            hse_hz: 8_000_000,
+            cr: 0x0f03_0000,
+            pllcfgr: 0x2440_3008,
+            cfgr: 0x0000_940a,
+            // M 4, N 192, R 6
+            plli2scfgr: 0x6000_3004,
+        },
+    };
+
+    #[test]
+    fn dump_round_trip() {
+        let dump = format!("{}", SNAPSHOT);
+        let mut lines = dump.lines().map(|line| parse_line(line).unwrap());
+        let [(a, a_regs), (b, b_regs)] = SNAPSHOT.instances;
+        assert_eq!(lines.next(), Some(Line::Spi(a, a_regs)));
+        assert_eq!(lines.next(), Some(Line::Spi(b, b_regs)));
+        assert_eq!(lines.next(), Some(Line::Rcc(SNAPSHOT.rcc)));
+        assert_eq!(lines.next(), None);
+        // from a log line, but not from other lines
+        let logged = "failed  regs i2s2 CR1 00000000 CR2 00000060 SR 00000002 I2SCFGR 00000f05 \
+                      I2SPR 00000302";
+        assert_eq!(parse_line(logged), Some(Line::Spi(a, a_regs)));
+        assert_eq!(parse_line("frequency Some(3000.0) expected"), None);
+        assert_eq!(parse_line("  regs i2s2 CR1 00000000 CR2"), None);
+    }
+
+    #[test]
+    fn clock_tree() {
+        let i2s_clk_hz = 64_000_000;
+        assert_eq!(SNAPSHOT.rcc.i2s_clk_hz(), Some(i2s_clk_hz));
+        let [(_, master), (_, slave)] = SNAPSHOT.instances;
+        assert_eq!(master.sample_rate(i2s_clk_hz), Some(50_000.0));
+        assert_eq!(slave.sample_rate(i2s_clk_hz), None);
+        // PLLI2S off
+        let off = RccRegs {
+            cr: 0x0303_0000,
+            ..SNAPSHOT.rcc
+        };
+        assert_eq!(off.i2s_clk_hz(), None);
+    }
+
+    #[test]
+    fn fields() {
+        let [(_, master), (_, slave)] = SNAPSHOT.instances;
+        assert_eq!(
+            format!("{}", master.decode(Some(64_000_000))),
+            "  I2SMOD I2S, I2SE 1, I2SCFG master receive\n  \
+             I2SSTD Philips, PCMSYNC short, DATLEN 32 bits, CHLEN 32 bits, CKPOL low\n  \
+             I2SDIV 2, ODD 1, MCKOE 1, sample rate 50000.0 Hz\n  \
+             CR1 0x00000000\n  CR2 ERRIE RXNEIE\n  SR TXE"
+        );
+        assert_eq!(
+            format!("{}", slave.decode(None)),
+            "  I2SMOD I2S, I2SE 1, I2SCFG slave transmit\n  \
+             I2SSTD Philips, PCMSYNC short, DATLEN 32 bits, CHLEN 32 bits, CKPOL low\n  \
+             I2SDIV 2, ODD 0, MCKOE 0\n  \
+             CR1 0x00000000\n  CR2 ERRIE TXEIE\n  SR TXE CHSIDE FRE"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Slave synchronisation on the WS line
+//!
+//! A slave must be enabled while WS is high, before the next left channel begins. Once armed, a
+//! [`SlaveSync`] is fed with WS rising edges by the selected strategy and tells when the slave
+//! can be enabled. Frame errors arm it again.
+
+/// How WS rising edges are detected
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SyncStrategy {
+    /// EXTI interrupt on the WS pin, the level is checked in the interrupt.
+    Exti,
+    /// The WS pin is polled from the thread starting the slave.
+    Polling,
+    /// A timer captures WS edges, their age is checked in the capture interrupt. WS lines must
+    /// be wired to the timer inputs.
+    TimerCapture,
+}
+
+/// All strategies, for scenarios comparing them
+pub const STRATEGIES: [SyncStrategy; 3] = [
+    SyncStrategy::Exti,
+    SyncStrategy::Polling,
+    SyncStrategy::TimerCapture,
+];
+
+impl SyncStrategy {
+    pub fn name(self) -> &'static str {
+        match self {
+            SyncStrategy::Exti => "exti",
+            SyncStrategy::Polling => "polling",
+            SyncStrategy::TimerCapture => "timer capture",
+        }
+    }
+}
+
+/// Synchronisation statistics, accumulated until cleared
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SyncStats {
+    /// Slave enabled on a WS edge
+    pub attempts: u32,
+    /// WS edges seen too late to enable the slave
+    pub missed: u32,
+    /// Longest time between arming and enabling, in cycles
+    pub max_latency: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum SyncState {
+    Idle,
+    Armed { since: u32 },
+}
+
+/// Slave synchronisation state machine
+pub struct SlaveSync {
+    strategy: SyncStrategy,
+    state: SyncState,
+    // last polled WS level and when it was sampled
+    last_sample: Option<(u32, bool)>,
+    pub stats: SyncStats,
+}
+
+impl SlaveSync {
+    pub const fn new(strategy: SyncStrategy) -> Self {
+        Self {
+            strategy,
+            state: SyncState::Idle,
+            last_sample: None,
+            stats: SyncStats {
+                attempts: 0,
+                missed: 0,
+                max_latency: 0,
+            },
+        }
+    }
+
+    pub fn strategy(&self) -> SyncStrategy {
+        self.strategy
+    }
+
+    /// Change strategy, disarming and clearing statistics.
+    pub fn set_strategy(&mut self, strategy: SyncStrategy) {
+        *self = Self::new(strategy);
+    }
+
+    /// Wait for the next WS edge, `now` in cycles.
+    pub fn arm(&mut self, now: u32) {
+        self.state = SyncState::Armed { since: now };
+        self.last_sample = None;
+    }
+
+    pub fn disarm(&mut self) {
+        self.state = SyncState::Idle;
+    }
+
+    pub fn is_armed(&self) -> bool {
+        matches!(self.state, SyncState::Armed { .. })
+    }
+
+    /// Handle a WS rising edge, `in_time` telling if the slave can still be enabled. Return
+    /// `true` when the slave must be enabled.
+    pub fn on_edge(&mut self, now: u32, in_time: bool) -> bool {
+        match self.state {
+            SyncState::Idle => false,
+            SyncState::Armed { .. } if !in_time => {
+                self.stats.missed += 1;
+                false
+            }
+            SyncState::Armed { since } => {
+                self.stats.attempts += 1;
+                self.stats.max_latency = self.stats.max_latency.max(now.wrapping_sub(since));
+                self.state = SyncState::Idle;
+                true
+            }
+        }
+    }
+
+    /// Handle a polled WS level. A rising edge is in time if the previous sample is at most
+    /// `window` cycles old. Return `true` when the slave must be enabled.
+    pub fn on_sample(&mut self, now: u32, high: bool, window: u32) -> bool {
+        let last = self.last_sample.replace((now, high));
+        match last {
+            Some((time, false)) if high => self.on_edge(now, now.wrapping_sub(time) <= window),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges_ignored_when_idle() {
+        let mut sync = SlaveSync::new(SyncStrategy::Exti);
+        assert!(!sync.on_edge(10, true));
+        assert_eq!(sync.stats.attempts, 0);
+    }
+
+    #[test]
+    fn late_edges_are_missed() {
+        let mut sync = SlaveSync::new(SyncStrategy::Polling);
+        sync.arm(100);
+        assert!(!sync.on_edge(150, false));
+        assert!(sync.is_armed());
+        assert!(sync.on_edge(300, true));
+        assert!(!sync.is_armed());
+        assert_eq!(sync.stats.missed, 1);
+        assert_eq!(sync.stats.attempts, 1);
+        assert_eq!(sync.stats.max_latency, 200);
+    }
+
+    #[test]
+    fn polled_rising_edge() {
+        let mut sync = SlaveSync::new(SyncStrategy::Polling);
+        sync.arm(0);
+        // WS already high when armed isn't an edge
+        assert!(!sync.on_sample(10, true, 50));
+        assert!(!sync.on_sample(20, false, 50));
+        // sampled too rarely to know when WS rose
+        assert!(!sync.on_sample(100, true, 50));
+        assert_eq!(sync.stats.missed, 1);
+        assert!(!sync.on_sample(110, false, 50));
+        assert!(sync.on_sample(130, true, 50));
+        assert_eq!(sync.stats.attempts, 1);
+        assert_eq!(sync.stats.max_latency, 130);
+    }
+
+    #[test]
+    fn rearm_after_frame_error() {
+        let mut sync = SlaveSync::new(SyncStrategy::TimerCapture);
+        sync.arm(u32::MAX - 10);
+        assert!(sync.on_edge(5, true));
+        sync.arm(1000);
+        assert!(sync.on_edge(1002, true));
+        assert_eq!(sync.stats.attempts, 2);
+        assert_eq!(sync.stats.max_latency, 16);
+        sync.set_strategy(SyncStrategy::Exti);
+        assert_eq!(sync.stats.attempts, 0);
+        assert!(!sync.is_armed());
+    }
+}
@@ -0,0 +1,74 @@
+//! Teardown of running scenarios
+//!
+//! A scenario hands its shared drivers to a [`Teardown`] guard before starting them. However the
+//! scenario ends, timed out or with drivers in an unexpected mode, they are disabled along with
+//! their WS interrupt, released and their peripheral reset, by `finish()` which gives the
+//! instances back, or when the guard goes out of scope.
+
+use crate::driver_wrap::*;
+use crate::hal::pac::EXTI;
+use rtic::mutex::prelude::*;
+
+/// Shared driver held by a [`Teardown`] guard, `()` for none
+pub trait Slot {
+    type Released;
+    /// Tear the driver down, `None` if there was none.
+    fn teardown(&mut self, exti: &mut EXTI) -> Option<Self::Released>;
+}
+
+impl Slot for () {
+    type Released = ();
+
+    fn teardown(&mut self, _exti: &mut EXTI) -> Option<()> {
+        Some(())
+    }
+}
+
+impl<'a, I: I2sInstance, M: Mutex<T = DriverWrap<I>>> Slot for &'a mut M {
+    type Released = I;
+
+    fn teardown(&mut self, exti: &mut EXTI) -> Option<I> {
+        self.lock(|driver| driver.teardown(exti))
+    }
+}
+
+pub struct Teardown<'a, E: Mutex<T = EXTI>, A: Slot, B: Slot = ()> {
+    exti: &'a mut E,
+    slots: Option<(A, B)>,
+}
+
+impl<'a, E: Mutex<T = EXTI>, A: Slot, B: Slot> Teardown<'a, E, A, B> {
+    pub fn new(exti: &'a mut E, a: A, b: B) -> Self {
+        Self {
+            exti,
+            slots: Some((a, b)),
+        }
+    }
+
+    /// The EXTI and driver resources, to lock them while the scenario runs
+    pub fn parts(&mut self) -> (&mut E, &mut A, &mut B) {
+        // slots are only taken on teardown
+        let (a, b) = self.slots.as_mut().unwrap();
+        (&mut *self.exti, a, b)
+    }
+
+    /// Tear down both drivers and give the instances back. Panics if a slot has no driver.
+    pub fn finish(mut self) -> (A::Released, B::Released) {
+        let (a, b) = self.release().unwrap();
+        (
+            a.expect("no driver to tear down"),
+            b.expect("no driver to tear down"),
+        )
+    }
+
+    fn release(&mut self) -> Option<(Option<A::Released>, Option<B::Released>)> {
+        let (mut a, mut b) = self.slots.take()?;
+        Some(self.exti.lock(|exti| (a.teardown(exti), b.teardown(exti))))
+    }
+}
+
+impl<'a, E: Mutex<T = EXTI>, A: Slot, B: Slot> Drop for Teardown<'a, E, A, B> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
@@ -1,23 +1,26 @@
 //! Contains test to be done
 
-use crate::app::{I2s2, I2s3};
 use heapless::spsc::*;
 use rtt_target::{rprint, rprintln};
 
 use crate::hal;
 
-use hal::gpio::ExtiPin;
 use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
 use hal::i2s::stm32_i2s_v12x::transfer::*;
 use hal::pac::DWT;
-use hal::pac::{EXTI, RCC, SPI2, SPI3};
-use hal::rcc::Reset;
+use hal::pac::EXTI;
 
 use rtic::mutex::prelude::*;
 
+use crate::deadline::TimedOut;
+use crate::deadline_ms;
 use crate::driver_wrap::*;
+use crate::progress::verdict;
+use crate::snapshot::Snapshot;
+use crate::teardown::Teardown;
 
-use DriverMode::*;
+use RxMode::*;
+use TxMode::*;
 
 const FRM_32: &[(i16, i16)] = &[
     (0x1111u16 as _, 0x7777u16 as _),
@@ -29,6 +32,9 @@ const FRM_32: &[(i16, i16)] = &[
     (0xB000u16 as _, 0xD000u16 as _),
 ];
 
+/// Longest wait of a test, in ms
+const WAIT_MS: u32 = 100;
+
 fn slice_contains<T>(slice: &[T], pattern: &[T]) -> bool
 where
     T: PartialEq<T>,
@@ -63,16 +69,23 @@ mod tests {
     }
 }
 
-fn check_result<const N: usize>(res: &[(u32, (i16, i16)); N]) {
+fn check_result<const N: usize>(
+    res: &[(u32, (i16, i16)); N],
+    waited: Result<(), TimedOut>,
+    regs: &Snapshot,
+) {
     let pattern = &FRM_32[1..(FRM_32.len() - 1)];
     let mut cmp = [(0, 0); N];
     for ((_, s), d) in res.iter().zip(cmp.iter_mut()) {
         *d = *s;
     }
-    if slice_contains(&cmp, pattern) {
+    if verdict(waited.is_ok() && slice_contains(&cmp, pattern)) {
         rprintln!("ok");
     } else {
         rprintln!("failed");
+        if waited.is_err() {
+            rprintln!("timed out");
+        }
         for (e, r) in FRM_32.iter().zip(res.iter()) {
             let (t, r) = r;
             rprintln!(
@@ -84,110 +97,93 @@ fn check_result<const N: usize>(res: &[(u32, (i16, i16)); N]) {
                 r.1
             );
         }
+        rprintln!("{}", regs);
     }
 }
 
-pub fn master_receive_slave_transmit_driver_interrupt(
-    mut shared_exti: &mut impl Mutex<T = EXTI>,
-    mut shared_i2s2_driver: &mut impl Mutex<T = DriverWrap<I2s2>>,
-    mut shared_i2s3_driver: &mut impl Mutex<T = DriverWrap<I2s3>>,
-    i2s2_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
-    i2s3_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn master_receive_slave_transmit_driver_interrupt<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    rx_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
+    tx_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Master Receive + Slave Transmit driver 16 bits with interrupt");
 
     // Set up drivers
-    let mut i2s2_driver = I2sDriverConfig::new_master()
+    let mut rx_driver = I2sDriverConfig::new_master()
         .receive()
         .standard(Philips)
         .data_format(DataFormat::Data16Channel32)
         .master_clock(true)
         .request_frequency(1)
-        .i2s_driver(i2s2);
-    rprint!(", SR {} ... ", i2s2_driver.sample_rate());
-    i2s2_driver.set_rx_interrupt(true);
-    i2s2_driver.set_error_interrupt(true);
+        .i2s_driver(rx);
+    rprint!(", SR {} ... ", rx_driver.sample_rate());
+    rx_driver.set_rx_interrupt(true);
+    rx_driver.set_error_interrupt(true);
 
-    let mut i2s3_driver = I2sDriverConfig::new_slave()
+    let mut tx_driver = I2sDriverConfig::new_slave()
         .transmit()
         .standard(Philips)
         .data_format(DataFormat::Data16Channel32)
-        .i2s_driver(i2s3);
-    i2s3_driver.set_tx_interrupt(true);
-    i2s3_driver.set_error_interrupt(true);
+        .i2s_driver(tx);
+    tx_driver.set_tx_interrupt(true);
+    tx_driver.set_error_interrupt(true);
 
     // prepare data to transmit
     for e in FRM_32 {
-        i2s3_data_p.enqueue(*e).ok();
+        tx_data_p.enqueue(*e).ok();
     }
 
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, shared_tx_driver);
+    let (mut shared_exti, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
     // start drivers
     (
         &mut shared_exti,
-        &mut shared_i2s2_driver,
-        &mut shared_i2s3_driver,
+        &mut shared_rx_driver,
+        &mut shared_tx_driver,
     )
-        .lock(|exti, shared_i2s2_driver, shared_i2s3_driver| {
-            i2s2_driver.enable();
-            shared_i2s2_driver.replace(MasterReceive16bits(i2s2_driver));
-            let ws_pin = i2s3_driver.i2s_peripheral_mut().ws_pin_mut();
-            ws_pin.enable_interrupt(exti);
-            shared_i2s3_driver.replace(SlaveTransmit16bits(i2s3_driver));
+        .lock(|exti, shared_rx_driver, shared_tx_driver| {
+            rx_driver.enable();
+            shared_rx_driver.replace(MasterReceive16bits(rx_driver));
+            tx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+            shared_tx_driver.replace(SlaveTransmit16bits(tx_driver));
         });
 
     //block until test finish
-    while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    let waited = deadline_ms(WAIT_MS).wait(|| rx_data_c.len() == rx_data_c.capacity());
 
-    //disable driver and release
-    let i2s2 = shared_i2s2_driver.lock(|shared_i2s2_driver| {
-        if let Some(MasterReceive16bits(mut i2s2_driver)) = shared_i2s2_driver.take() {
-            i2s2_driver.disable();
-            i2s2_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s3 = (&mut shared_i2s3_driver, &mut shared_exti).lock(|i2s3_driver, exti| {
-        if let Some(SlaveTransmit16bits(mut i2s3_driver)) = i2s3_driver.take() {
-            i2s3_driver.disable();
-            let ws_pin = i2s3_driver.i2s_peripheral_mut().ws_pin_mut();
-            ws_pin.disable_interrupt(exti);
-            i2s3_driver.release()
-        } else {
-            panic!()
-        }
-    });
+    // registers as programmed, dumped on failure
+    let regs = snapshot::<Rx, Tx>();
 
-    //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    //disable driver and release
+    let (rx, tx) = running.finish();
 
     // get test result
     for e in res.iter_mut() {
-        *e = i2s2_data_c.dequeue().unwrap_or_default();
+        *e = rx_data_c.dequeue().unwrap_or_default();
     }
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn slave_receive_master_transmit_driver_interrupt(
-    mut shared_exti: &mut impl Mutex<T = EXTI>,
-    mut shared_i2s2_driver: &mut impl Mutex<T = DriverWrap<I2s2>>,
-    mut shared_i2s3_driver: &mut impl Mutex<T = DriverWrap<I2s3>>,
-    i2s2_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
-    i2s3_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn slave_receive_master_transmit_driver_interrupt<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    rx_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
+    tx_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Slave Receive + Master Transmit driver 16 bits with interrupt");
@@ -199,87 +195,66 @@ pub fn slave_receive_master_transmit_driver_interrupt(
         .request_frequency(1);
 
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers
-    let mut i2s2_driver = drv_cfg_base.to_slave().receive().i2s_driver(i2s2);
-    i2s2_driver.set_rx_interrupt(true);
-    i2s2_driver.set_error_interrupt(true);
+    let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+    rx_driver.set_rx_interrupt(true);
+    rx_driver.set_error_interrupt(true);
 
-    let mut i2s3_driver = drv_cfg_base.transmit().i2s_driver(i2s3);
-    rprint!(", SR {} ... ", i2s3_driver.sample_rate());
-    i2s3_driver.set_tx_interrupt(true);
+    let mut tx_driver = drv_cfg_base.transmit().i2s_driver(tx);
+    rprint!(", SR {} ... ", tx_driver.sample_rate());
+    tx_driver.set_tx_interrupt(true);
 
     // prepare data to transmit
     for e in FRM_32 {
-        i2s3_data_p.enqueue(*e).ok();
+        tx_data_p.enqueue(*e).ok();
     }
 
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, shared_tx_driver);
+    let (mut shared_exti, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
     // start drivers
     (
         &mut shared_exti,
-        &mut shared_i2s2_driver,
-        &mut shared_i2s3_driver,
+        &mut shared_rx_driver,
+        &mut shared_tx_driver,
     )
-        .lock(|exti, shared_i2s2_driver, shared_i2s3_driver| {
-            i2s3_driver.enable();
-            shared_i2s3_driver.replace(MasterTransmit16bits(i2s3_driver));
-            let ws_pin = i2s2_driver.i2s_peripheral_mut().ws_pin_mut();
-            ws_pin.enable_interrupt(exti);
-            shared_i2s2_driver.replace(SlaveReceive16bits(i2s2_driver));
+        .lock(|exti, shared_rx_driver, shared_tx_driver| {
+            tx_driver.enable();
+            shared_tx_driver.replace(MasterTransmit16bits(tx_driver));
+            rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+            shared_rx_driver.replace(SlaveReceive16bits(rx_driver));
         });
 
     //block until test finish
-    while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    let waited = deadline_ms(WAIT_MS).wait(|| rx_data_c.len() == rx_data_c.capacity());
 
-    //disable driver and release
-    let i2s2 = (&mut shared_i2s2_driver, &mut shared_exti).lock(|i2s2_driver, exti| {
-        if let Some(SlaveReceive16bits(mut i2s2_driver)) = i2s2_driver.take() {
-            i2s2_driver.disable();
-            let ws_pin = i2s2_driver.i2s_peripheral_mut().ws_pin_mut();
-            ws_pin.disable_interrupt(exti);
-            i2s2_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s3 = shared_i2s3_driver.lock(|i2s3_driver| {
-        if let Some(MasterTransmit16bits(mut i2s3_driver)) = i2s3_driver.take() {
-            i2s3_driver.disable();
-            i2s3_driver.release()
-        } else {
-            panic!()
-        }
-    });
+    // registers as programmed, dumped on failure
+    let regs = snapshot::<Rx, Tx>();
 
-    //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    //disable driver and release
+    let (rx, tx) = running.finish();
 
     // get test result
     for e in res.iter_mut() {
-        *e = i2s2_data_c.dequeue().unwrap_or_default();
+        *e = rx_data_c.dequeue().unwrap_or_default();
     }
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn master_transmit_transfer_block(
-    mut shared_exti: &mut impl Mutex<T = EXTI>,
-    mut shared_i2s2_driver: &mut impl Mutex<T = DriverWrap<I2s2>>,
-    i2s2_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn master_transmit_transfer_block<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    rx_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Master Transmit Transfer 16 bits block");
@@ -298,69 +273,63 @@ pub fn master_transmit_transfer_block(
         .request_frequency(1);
 
     // reset is2 peripheral
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers and transfert
-    let mut i2s2_driver = drv_cfg_base.to_slave().receive().i2s_driver(i2s2);
-    i2s2_driver.set_rx_interrupt(true);
-    i2s2_driver.set_error_interrupt(true);
+    let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+    rx_driver.set_rx_interrupt(true);
+    rx_driver.set_error_interrupt(true);
+
+    let mut tx_transfer = transfer_cfg_base.transmit().i2s_transfer(tx);
+    rprint!(", SR {} ... ", tx_transfer.sample_rate());
 
-    let mut i2s3_transfer = transfer_cfg_base.transmit().i2s_transfer(i2s3);
-    rprint!(", SR {} ... ", i2s3_transfer.sample_rate());
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, ());
+    let (mut shared_exti, mut shared_rx_driver, _) = running.parts();
 
     // start drivers
-    (&mut shared_exti, &mut shared_i2s2_driver).lock(|exti, shared_i2s2_driver| {
-        let ws_pin = i2s2_driver.i2s_peripheral_mut().ws_pin_mut();
-        ws_pin.enable_interrupt(exti);
-        shared_i2s2_driver.replace(SlaveReceive16bits(i2s2_driver));
+    (&mut shared_exti, &mut shared_rx_driver).lock(|exti, shared_rx_driver| {
+        rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+        shared_rx_driver.replace(SlaveReceive16bits(rx_driver));
     });
 
-    //blocking transmit
-    i2s3_transfer.write_iter(FRM_32.iter().copied());
+    //blocking transmit, the master clocks itself so frames always come, the deadline is
+    //checked between them
+    let deadline = deadline_ms(WAIT_MS);
+    let mut frames = deadline.until(FRM_32.iter().copied());
+    tx_transfer.write_iter(&mut frames);
+    let waited = frames.result();
 
     //block until test finish
-    //while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    //while rx_data_c.len() < rx_data_c.capacity() {}
+
+    // registers as programmed, dumped on failure
+    let regs = snapshot::<Rx, Tx>();
 
     //disable driver and transfer and release
-    let i2s2 = (&mut shared_i2s2_driver, &mut shared_exti).lock(|i2s2_driver, exti| {
-        if let Some(SlaveReceive16bits(mut i2s2_driver)) = i2s2_driver.take() {
-            i2s2_driver.disable();
-            let ws_pin = i2s2_driver.i2s_peripheral_mut().ws_pin_mut();
-            ws_pin.disable_interrupt(exti);
-            i2s2_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s3 = i2s3_transfer.release();
+    let (rx, ()) = running.finish();
+    let tx = tx_transfer.release();
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Tx::reset_peripheral();
 
     // get test result
     for e in res.iter_mut() {
-        *e = i2s2_data_c.dequeue().unwrap_or_default();
+        *e = rx_data_c.dequeue().unwrap_or_default();
     }
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn master_transmit_transfer_nb(
-    mut shared_exti: &mut impl Mutex<T = EXTI>,
-    mut shared_i2s2_driver: &mut impl Mutex<T = DriverWrap<I2s2>>,
-    i2s2_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn master_transmit_transfer_nb<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    rx_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Master Transmit Transfer 16 bits nb");
@@ -379,75 +348,67 @@ pub fn master_transmit_transfer_nb(
         .request_frequency(1);
 
     // reset is2 peripheral
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers and transfert
-    let mut i2s2_driver = drv_cfg_base.to_slave().receive().i2s_driver(i2s2);
-    i2s2_driver.set_rx_interrupt(true);
-    i2s2_driver.set_error_interrupt(true);
+    let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+    rx_driver.set_rx_interrupt(true);
+    rx_driver.set_error_interrupt(true);
+
+    let mut tx_transfer = transfer_cfg_base.transmit().i2s_transfer(tx);
+    rprint!(", SR {} ... ", tx_transfer.sample_rate());
 
-    let mut i2s3_transfer = transfer_cfg_base.transmit().i2s_transfer(i2s3);
-    rprint!(", SR {} ... ", i2s3_transfer.sample_rate());
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, ());
+    let (mut shared_exti, mut shared_rx_driver, _) = running.parts();
 
     // start drivers
-    (&mut shared_exti, &mut shared_i2s2_driver).lock(|exti, shared_i2s2_driver| {
-        let ws_pin = i2s2_driver.i2s_peripheral_mut().ws_pin_mut();
-        ws_pin.enable_interrupt(exti);
-        shared_i2s2_driver.replace(SlaveReceive16bits(i2s2_driver));
+    (&mut shared_exti, &mut shared_rx_driver).lock(|exti, shared_rx_driver| {
+        rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+        shared_rx_driver.replace(SlaveReceive16bits(rx_driver));
     });
 
     //nb transmit
-    for data in FRM_32 {
-        while i2s3_transfer.write(*data).is_err() {}
-    }
-    while i2s3_transfer.write((0, 0)).is_err() {}
+    let deadline = deadline_ms(WAIT_MS);
+    let waited = FRM_32
+        .iter()
+        .chain(&[(0, 0)])
+        .try_for_each(|data| deadline.retry(|| tx_transfer.write(*data)));
 
     //block until test finish
-    //while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    //while rx_data_c.len() < rx_data_c.capacity() {}
+
+    // registers as programmed, dumped on failure
+    let regs = snapshot::<Rx, Tx>();
 
     //disable driver and transfer and release
-    let i2s2 = (&mut shared_i2s2_driver, &mut shared_exti).lock(|i2s2_driver, exti| {
-        if let Some(SlaveReceive16bits(mut i2s2_driver)) = i2s2_driver.take() {
-            i2s2_driver.disable();
-            let ws_pin = i2s2_driver.i2s_peripheral_mut().ws_pin_mut();
-            ws_pin.disable_interrupt(exti);
-            i2s2_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s3 = i2s3_transfer.release();
+    let (rx, ()) = running.finish();
+    let tx = tx_transfer.release();
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Tx::reset_peripheral();
 
     // get test result
     for e in res.iter_mut() {
-        *e = i2s2_data_c.dequeue().unwrap_or_default();
+        *e = rx_data_c.dequeue().unwrap_or_default();
     }
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn slave_transmit_transfer_block(
-    shared_i2s2_driver: &mut impl Mutex<T = DriverWrap<I2s2>>,
-    i2s2_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn slave_transmit_transfer_block<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    rx_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     // erase previous result
-    while i2s2_data_c.dequeue().is_some() {}
+    while rx_data_c.dequeue().is_some() {}
 
     rprint!("Slave Transmit Transfer 16 bits block");
     let drv_cfg_base = I2sDriverConfig::new_master()
@@ -465,65 +426,65 @@ pub fn slave_transmit_transfer_block(
         .request_frequency(1);
 
     // reset is2 peripheral
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers and transfert
-    let mut i2s2_driver = drv_cfg_base.receive().i2s_driver(i2s2);
-    rprint!(", SR {} ... ", i2s2_driver.sample_rate());
-    i2s2_driver.set_rx_interrupt(true);
-    i2s2_driver.set_error_interrupt(true);
+    let mut rx_driver = drv_cfg_base.receive().i2s_driver(rx);
+    rprint!(", SR {} ... ", rx_driver.sample_rate());
+    rx_driver.set_rx_interrupt(true);
+    rx_driver.set_error_interrupt(true);
+
+    let mut tx_transfer = transfer_cfg_base.to_slave().transmit().i2s_transfer(tx);
 
-    let mut i2s3_transfer = transfer_cfg_base.to_slave().transmit().i2s_transfer(i2s3);
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, ());
+    let (_, shared_rx_driver, _) = running.parts();
 
     // start drivers
-    shared_i2s2_driver.lock(|shared_i2s2_driver| {
-        i2s2_driver.enable();
-        shared_i2s2_driver.replace(MasterReceive16bits(i2s2_driver));
+    shared_rx_driver.lock(|shared_rx_driver| {
+        rx_driver.enable();
+        shared_rx_driver.replace(MasterReceive16bits(rx_driver));
     });
 
-    //blocking transmit
-    i2s3_transfer.write_iter(FRM_32[0..7].iter().copied());
+    //blocking transmit, started only once the master clocks WS so a dead master can't hang
+    //it, the deadline is then checked between frames
+    let deadline = deadline_ms(WAIT_MS);
+    let waited = Tx::wait_ws_clocking(&deadline).and_then(|_| {
+        let mut frames = deadline.until(FRM_32[0..7].iter().copied());
+        tx_transfer.write_iter(&mut frames);
+        frames.result()
+    });
 
     //block until test finish
-    //while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    //while rx_data_c.len() < rx_data_c.capacity() {}
+
+    // registers as programmed, dumped on failure
+    let regs = snapshot::<Rx, Tx>();
 
     //disable driver and transfer and release
-    let i2s2 = shared_i2s2_driver.lock(|i2s2_driver| {
-        if let Some(MasterReceive16bits(mut i2s2_driver)) = i2s2_driver.take() {
-            i2s2_driver.disable();
-            i2s2_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s3 = i2s3_transfer.release();
+    let (rx, ()) = running.finish();
+    let tx = tx_transfer.release();
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Tx::reset_peripheral();
 
     // get test result
     for e in res.iter_mut() {
-        *e = i2s2_data_c.dequeue().unwrap_or_default();
+        *e = rx_data_c.dequeue().unwrap_or_default();
     }
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn slave_transmit_transfer_nb(
-    shared_i2s2_driver: &mut impl Mutex<T = DriverWrap<I2s2>>,
-    i2s2_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn slave_transmit_transfer_nb<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    rx_data_c: &mut Consumer<'static, (u32, (i16, i16)), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Slave Transmit Transfer 16 bits nb");
@@ -542,76 +503,75 @@ pub fn slave_transmit_transfer_nb(
         .request_frequency(1);
 
     // erase previous result
-    while i2s2_data_c.dequeue().is_some() {}
+    while rx_data_c.dequeue().is_some() {}
 
     // reset is2 peripheral
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers and transfert
-    let mut i2s2_driver = drv_cfg_base.receive().i2s_driver(i2s2);
-    rprint!(", SR {} ... ", i2s2_driver.sample_rate());
-    i2s2_driver.set_rx_interrupt(true);
-    i2s2_driver.set_error_interrupt(true);
+    let mut rx_driver = drv_cfg_base.receive().i2s_driver(rx);
+    rprint!(", SR {} ... ", rx_driver.sample_rate());
+    rx_driver.set_rx_interrupt(true);
+    rx_driver.set_error_interrupt(true);
+
+    let mut tx_transfer = transfer_cfg_base.to_slave().transmit().i2s_transfer(tx);
 
-    let mut i2s3_transfer = transfer_cfg_base.to_slave().transmit().i2s_transfer(i2s3);
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, ());
+    let (_, shared_rx_driver, _) = running.parts();
 
     // start drivers
-    shared_i2s2_driver.lock(|shared_i2s2_driver| {
-        i2s2_driver.enable();
-        shared_i2s2_driver.replace(MasterReceive16bits(i2s2_driver));
+    shared_rx_driver.lock(|shared_rx_driver| {
+        rx_driver.enable();
+        shared_rx_driver.replace(MasterReceive16bits(rx_driver));
     });
 
     //nb transmit
+    let deadline = deadline_ms(WAIT_MS);
+    let mut waited = Ok(());
     'a: for data in FRM_32.iter() {
-        while i2s3_transfer.write(*data).is_err() {
-            if i2s2_data_c.len() >= i2s2_data_c.capacity() {
+        while tx_transfer.write(*data).is_err() {
+            if rx_data_c.len() >= rx_data_c.capacity() {
+                break 'a;
+            }
+            if deadline.is_expired() {
+                waited = Err(TimedOut);
                 break 'a;
             }
         }
     }
-    //while i2s3_transfer.write((0xFF,0xFE)).is_err() {}
-    //while i2s3_transfer.write((0xDD,0xCC)).is_err() {}
+    //while tx_transfer.write((0xFF,0xFE)).is_err() {}
+    //while tx_transfer.write((0xDD,0xCC)).is_err() {}
 
     //block until test finish
 
+    // registers as programmed, dumped on failure
+    let regs = snapshot::<Rx, Tx>();
+
     //disable driver and transfer and release
-    let i2s2 = shared_i2s2_driver.lock(|i2s2_driver| {
-        if let Some(MasterReceive16bits(mut i2s2_driver)) = i2s2_driver.take() {
-            i2s2_driver.disable();
-            i2s2_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s3 = i2s3_transfer.release();
+    let (rx, ()) = running.finish();
+    let tx = tx_transfer.release();
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Tx::reset_peripheral();
 
     // get test result
     for e in res.iter_mut() {
-        *e = i2s2_data_c.dequeue().unwrap_or_default();
+        *e = rx_data_c.dequeue().unwrap_or_default();
     }
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn master_receive_transfer_block(
-    mut shared_exti: &mut impl Mutex<T = EXTI>,
-    mut shared_i2s3_driver: &mut impl Mutex<T = DriverWrap<I2s3>>,
-    i2s3_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn master_receive_transfer_block<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    tx_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Master Receive Transfer 16 bits block");
@@ -630,74 +590,70 @@ pub fn master_receive_transfer_block(
         .request_frequency(1);
 
     // reset is2 peripheral
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers and transfer
-    let mut i2s2_transfer = transfer_cfg_base.receive().i2s_transfer(i2s2);
-    rprint!(", SR {} ... ", i2s2_transfer.sample_rate());
+    let mut rx_transfer = transfer_cfg_base.receive().i2s_transfer(rx);
+    rprint!(", SR {} ... ", rx_transfer.sample_rate());
 
-    let mut i2s3_driver = drv_cfg_base.to_slave().transmit().i2s_driver(i2s3);
-    i2s3_driver.set_tx_interrupt(true);
-    i2s3_driver.set_error_interrupt(true);
+    let mut tx_driver = drv_cfg_base.to_slave().transmit().i2s_driver(tx);
+    tx_driver.set_tx_interrupt(true);
+    tx_driver.set_error_interrupt(true);
 
     // prepare data to transmit
     for e in FRM_32 {
-        i2s3_data_p.enqueue(*e).ok();
+        tx_data_p.enqueue(*e).ok();
     }
 
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_tx_driver, ());
+    let (mut shared_exti, mut shared_tx_driver, _) = running.parts();
+
     // start drivers
-    (&mut shared_i2s3_driver, &mut shared_exti).lock(|shared_i2s3_driver, exti| {
-        let ws_pin = i2s3_driver.i2s_peripheral_mut().ws_pin_mut();
-        ws_pin.enable_interrupt(exti);
-        shared_i2s3_driver.replace(SlaveTransmit16bits(i2s3_driver));
+    (&mut shared_tx_driver, &mut shared_exti).lock(|shared_tx_driver, exti| {
+        tx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+        shared_tx_driver.replace(SlaveTransmit16bits(tx_driver));
     });
 
-    //blocking transmit
+    //blocking receive, the master clocks itself so frames always come, the deadline is
+    //checked between them
+    let deadline = deadline_ms(WAIT_MS);
     let mut res_iter = res.iter_mut().peekable();
-    i2s2_transfer.read_while(|s| {
+    rx_transfer.read_while(|s| {
         if let Some(r) = res_iter.next() {
             *r = (DWT::cycle_count(), s);
         }
-        res_iter.peek().is_some()
+        res_iter.peek().is_some() && !deadline.is_expired()
     });
+    let waited = match res_iter.peek() {
+        None => Ok(()),
+        Some(_) => Err(TimedOut),
+    };
 
     //block until test finish
-    //while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    //while rx_data_c.len() < rx_data_c.capacity() {}
 
     //disable driver and transfer and release
-    while i2s3_data_p.len() > 0 {}
-    let i2s3 = shared_i2s3_driver.lock(|i2s3_driver| {
-        if let Some(SlaveTransmit16bits(mut i2s3_driver)) = i2s3_driver.take() {
-            i2s3_driver.disable();
-            i2s3_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s2 = i2s2_transfer.release();
+    let waited = waited.and(deadline.wait(|| tx_data_p.len() == 0));
+    let regs = snapshot::<Rx, Tx>();
+    let (tx, ()) = running.finish();
+    let rx = rx_transfer.release();
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn master_receive_transfer_nb(
-    mut shared_exti: &mut impl Mutex<T = EXTI>,
-    mut shared_i2s3_driver: &mut impl Mutex<T = DriverWrap<I2s3>>,
-    i2s3_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn master_receive_transfer_nb<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    tx_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Master Receive Transfer 16 bits nb");
@@ -716,74 +672,63 @@ pub fn master_receive_transfer_nb(
         .request_frequency(1);
 
     // reset is2 peripheral
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers and transfer
-    let mut i2s2_transfer = transfer_cfg_base.receive().i2s_transfer(i2s2);
-    rprint!(", SR {} ... ", i2s2_transfer.sample_rate());
+    let mut rx_transfer = transfer_cfg_base.receive().i2s_transfer(rx);
+    rprint!(", SR {} ... ", rx_transfer.sample_rate());
 
-    let mut i2s3_driver = drv_cfg_base.to_slave().transmit().i2s_driver(i2s3);
-    i2s3_driver.set_tx_interrupt(true);
-    i2s3_driver.set_error_interrupt(true);
+    let mut tx_driver = drv_cfg_base.to_slave().transmit().i2s_driver(tx);
+    tx_driver.set_tx_interrupt(true);
+    tx_driver.set_error_interrupt(true);
 
     // prepare data to transmit
     for e in FRM_32 {
-        i2s3_data_p.enqueue(*e).ok();
+        tx_data_p.enqueue(*e).ok();
     }
 
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_tx_driver, ());
+    let (mut shared_exti, mut shared_tx_driver, _) = running.parts();
+
     // start drivers
-    (&mut shared_i2s3_driver, &mut shared_exti).lock(|shared_i2s3_driver, exti| {
-        let ws_pin = i2s3_driver.i2s_peripheral_mut().ws_pin_mut();
-        ws_pin.enable_interrupt(exti);
-        shared_i2s3_driver.replace(SlaveTransmit16bits(i2s3_driver));
+    (&mut shared_tx_driver, &mut shared_exti).lock(|shared_tx_driver, exti| {
+        tx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+        shared_tx_driver.replace(SlaveTransmit16bits(tx_driver));
     });
 
-    //blocking transmit
-    for r in res.iter_mut() {
-        let data = loop {
-            if let Ok(s) = i2s2_transfer.read() {
-                break s;
-            }
-        };
+    //nb receive
+    let deadline = deadline_ms(WAIT_MS);
+    let waited = res.iter_mut().try_for_each(|r| {
+        let data = deadline.retry(|| rx_transfer.read())?;
         *r = (DWT::cycle_count(), data);
-    }
+        Ok(())
+    });
 
     //block until test finish
-    //while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    //while rx_data_c.len() < rx_data_c.capacity() {}
 
     //disable driver and transfer and release
-    while i2s3_data_p.len() > 0 {}
-    let i2s3 = shared_i2s3_driver.lock(|i2s3_driver| {
-        if let Some(SlaveTransmit16bits(mut i2s3_driver)) = i2s3_driver.take() {
-            i2s3_driver.disable();
-            i2s3_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s2 = i2s2_transfer.release();
+    let waited = waited.and(deadline.wait(|| tx_data_p.len() == 0));
+    let regs = snapshot::<Rx, Tx>();
+    let (tx, ()) = running.finish();
+    let rx = rx_transfer.release();
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn slave_receive_transfer_block(
-    shared_i2s3_driver: &mut impl Mutex<T = DriverWrap<I2s3>>,
-    i2s3_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn slave_receive_transfer_block<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    tx_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Slave Receive Transfer 16 bits block");
@@ -802,70 +747,72 @@ pub fn slave_receive_transfer_block(
         .request_frequency(1);
 
     // reset is2 peripheral
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers and transfer
-    let mut i2s2_transfer = transfer_cfg_base.to_slave().receive().i2s_transfer(i2s2);
+    let mut rx_transfer = transfer_cfg_base.to_slave().receive().i2s_transfer(rx);
 
-    let mut i2s3_driver = drv_cfg_base.transmit().i2s_driver(i2s3);
-    i2s3_driver.set_tx_interrupt(true);
-    rprint!(", SR {} ... ", i2s3_driver.sample_rate());
+    let mut tx_driver = drv_cfg_base.transmit().i2s_driver(tx);
+    tx_driver.set_tx_interrupt(true);
+    rprint!(", SR {} ... ", tx_driver.sample_rate());
 
     // prepare data to transmit
     for e in FRM_32 {
-        i2s3_data_p.enqueue(*e).ok();
+        tx_data_p.enqueue(*e).ok();
     }
 
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_tx_driver, ());
+    let (_, shared_tx_driver, _) = running.parts();
+
     // start drivers
-    shared_i2s3_driver.lock(|shared_i2s3_driver| {
-        i2s3_driver.enable();
-        shared_i2s3_driver.replace(MasterTransmit16bits(i2s3_driver));
+    shared_tx_driver.lock(|shared_tx_driver| {
+        tx_driver.enable();
+        shared_tx_driver.replace(MasterTransmit16bits(tx_driver));
     });
 
-    //blocking transmit
-    let mut res_iter = res.iter_mut().peekable();
-    i2s2_transfer.read_while(|s| {
-        if let Some(r) = res_iter.next() {
-            *r = (DWT::cycle_count(), s);
+    //blocking receive, started only once the master clocks WS so a dead master can't hang
+    //it, the deadline is then checked between frames
+    let deadline = deadline_ms(WAIT_MS);
+    let waited = Rx::wait_ws_clocking(&deadline).and_then(|_| {
+        let mut res_iter = res.iter_mut().peekable();
+        rx_transfer.read_while(|s| {
+            if let Some(r) = res_iter.next() {
+                *r = (DWT::cycle_count(), s);
+            }
+            res_iter.peek().is_some() && !deadline.is_expired()
+        });
+        match res_iter.peek() {
+            None => Ok(()),
+            Some(_) => Err(TimedOut),
         }
-        res_iter.peek().is_some()
     });
 
     //block until test finish
-    //while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    //while rx_data_c.len() < rx_data_c.capacity() {}
+
+    // registers as programmed, dumped on failure
+    let regs = snapshot::<Rx, Tx>();
 
     //disable driver and transfer and release
-    let i2s3 = shared_i2s3_driver.lock(|i2s3_driver| {
-        if let Some(MasterTransmit16bits(mut i2s3_driver)) = i2s3_driver.take() {
-            i2s3_driver.disable();
-            i2s3_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s2 = i2s2_transfer.release();
+    let (tx, ()) = running.finish();
+    let rx = rx_transfer.release();
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
 
-pub fn slave_receive_transfer_nb(
-    shared_i2s3_driver: &mut impl Mutex<T = DriverWrap<I2s3>>,
-    i2s3_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
-    i2s2: I2s2,
-    i2s3: I2s3,
-) -> (I2s2, I2s3) {
+pub fn slave_receive_transfer_nb<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    tx_data_p: &mut Producer<'static, (i16, i16), 8_usize>,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
     let mut res = [(0, (0, 0)); 7];
 
     rprint!("Slave Receive Transfer 16 bits nb");
@@ -884,61 +831,52 @@ pub fn slave_receive_transfer_nb(
         .request_frequency(1);
 
     // reset is2 peripheral
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
 
     // Set up drivers and transfer
-    let mut i2s2_transfer = transfer_cfg_base.to_slave().receive().i2s_transfer(i2s2);
+    let mut rx_transfer = transfer_cfg_base.to_slave().receive().i2s_transfer(rx);
 
-    let mut i2s3_driver = drv_cfg_base.transmit().i2s_driver(i2s3);
-    i2s3_driver.set_tx_interrupt(true);
-    rprint!(", SR {} ... ", i2s3_driver.sample_rate());
+    let mut tx_driver = drv_cfg_base.transmit().i2s_driver(tx);
+    tx_driver.set_tx_interrupt(true);
+    rprint!(", SR {} ... ", tx_driver.sample_rate());
 
     // prepare data to transmit
     for e in FRM_32 {
-        i2s3_data_p.enqueue(*e).ok();
+        tx_data_p.enqueue(*e).ok();
     }
 
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_tx_driver, ());
+    let (_, shared_tx_driver, _) = running.parts();
+
     // start drivers
-    shared_i2s3_driver.lock(|shared_i2s3_driver| {
-        i2s3_driver.enable();
-        shared_i2s3_driver.replace(MasterTransmit16bits(i2s3_driver));
+    shared_tx_driver.lock(|shared_tx_driver| {
+        tx_driver.enable();
+        shared_tx_driver.replace(MasterTransmit16bits(tx_driver));
     });
 
-    //blocking transmit
-    for r in res.iter_mut() {
-        let data = loop {
-            if let Ok(s) = i2s2_transfer.read() {
-                break s;
-            }
-        };
+    //nb receive
+    let deadline = deadline_ms(WAIT_MS);
+    let waited = res.iter_mut().try_for_each(|r| {
+        let data = deadline.retry(|| rx_transfer.read())?;
         *r = (DWT::cycle_count(), data);
-    }
+        Ok(())
+    });
 
     //block until test finish
-    //while i2s2_data_c.len() < i2s2_data_c.capacity() {}
+    //while rx_data_c.len() < rx_data_c.capacity() {}
+
+    // registers as programmed, dumped on failure
+    let regs = snapshot::<Rx, Tx>();
 
     //disable driver and transfer and release
-    let i2s3 = shared_i2s3_driver.lock(|i2s3_driver| {
-        if let Some(MasterTransmit16bits(mut i2s3_driver)) = i2s3_driver.take() {
-            i2s3_driver.disable();
-            i2s3_driver.release()
-        } else {
-            panic!()
-        }
-    });
-    let i2s2 = i2s2_transfer.release();
+    let (tx, ()) = running.finish();
+    let rx = rx_transfer.release();
     //reset I2s peripherals
-    unsafe {
-        let rcc = &(*RCC::ptr());
-        SPI2::reset(rcc);
-        SPI3::reset(rcc);
-    }
+    Rx::reset_peripheral();
 
     // display result
-    check_result(&res);
-    (i2s2, i2s3)
+    check_result(&res, waited, &regs);
+    (rx, tx)
 }
@@ -0,0 +1,239 @@
+//! Channel mode tests
+//!
+//! The transmitter streams a counter to the receiver with the channel mode set on either. Each
+//! channel carries its own tag, so received frames tell if samples land on the expected channel
+//! and if idle or muted channels hold the fill value.
+
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::EXTI;
+
+use rtic::mutex::prelude::*;
+
+use crate::channel::ChannelMode;
+use crate::driver_wrap::*;
+use crate::progress::verdict;
+use crate::teardown::Teardown;
+use crate::{deadline, SYSCLK_HZ};
+
+use RxMode::*;
+use TxMode::*;
+
+/// Frames checked for each configuration
+const TEST_FRAMES: u32 = 256;
+
+/// Sample of idle and muted channels
+const FILL: i32 = 0x5A5A_A5A5_u32 as i32;
+
+/// Tag of the right channel
+const RIGHT: i32 = 1 << 30;
+
+fn stereo_frame(count: u32) -> (i32, i32) {
+    (count as i32, count as i32 ^ RIGHT)
+}
+
+fn mono_sample(mode: ChannelMode, count: u32) -> i32 {
+    match mode {
+        ChannelMode::MonoRight => count as i32 ^ RIGHT,
+        _ => count as i32,
+    }
+}
+
+/// Frame number `count` as seen on the line, or rebuilt from a received mono sample
+fn expected(mode: ChannelMode, count: u32) -> (i32, i32) {
+    if mode.is_mono() {
+        mode.frame_of(mono_sample(mode, count), FILL)
+    } else {
+        mode.mask(stereo_frame(count), FILL)
+    }
+}
+
+/// Frame number from the channel carrying data
+fn count_of(mode: ChannelMode, frame: (i32, i32)) -> u32 {
+    match mode {
+        ChannelMode::MonoRight | ChannelMode::MutedLeft => (frame.1 ^ RIGHT) as u32,
+        _ => frame.0 as u32,
+    }
+}
+
+/// Check of received frames
+#[derive(Default)]
+struct ChannelCheck {
+    frames: u32,
+    /// Frames not matching the expected one
+    errors: u32,
+    /// Idle or muted channels not holding the fill value
+    fill_errors: u32,
+    next: Option<u32>,
+}
+
+impl ChannelCheck {
+    fn check(&mut self, mode: ChannelMode, frame: (i32, i32)) {
+        let count = match self.next {
+            Some(count) => count,
+            // wait for the pattern
+            None if frame == expected(mode, count_of(mode, frame)) => count_of(mode, frame),
+            None => return,
+        };
+        let expected = expected(mode, count);
+        if frame != expected {
+            self.errors += 1;
+            if (expected.0 == FILL && frame.0 != FILL) || (expected.1 == FILL && frame.1 != FILL) {
+                self.fill_errors += 1;
+            }
+        }
+        // follow the received count to not report every frame after a lost one
+        self.next = Some(count_of(mode, frame).wrapping_add(1));
+        self.frames += 1;
+    }
+
+    fn is_ok(&self) -> bool {
+        self.frames > 0 && self.errors == 0
+    }
+}
+
+/// Stream from `Tx` as master to `Rx` as slave, `mode` being set on `Tx` if `on_transmit`, on
+/// `Rx` otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn stream_channel_mode<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    rx_data_32_c: &mut Consumer<'static, (u32, (i32, i32)), 8_usize>,
+    tx_data_32_p: &mut Producer<'static, (i32, i32), 8_usize>,
+    rx_mono_c: &mut Consumer<'static, (u32, i32), 8_usize>,
+    tx_mono_p: &mut Producer<'static, i32, 8_usize>,
+    mode: ChannelMode,
+    on_transmit: bool,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
+    rprint!(
+        "Channels {} on {} 32 bits",
+        mode.name(),
+        if on_transmit { "transmit" } else { "receive" }
+    );
+    let drv_cfg_base = I2sDriverConfig::new_master()
+        .transmit()
+        .standard(Philips)
+        .data_format(DataFormat::Data32Channel32)
+        .master_clock(true)
+        .request_frequency(48000);
+    let mono_tx = on_transmit && mode.is_mono();
+    let mono_rx = !on_transmit && mode.is_mono();
+
+    //reset I2s peripherals
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
+
+    // prepare data to transmit
+    let mut count = 0;
+    macro_rules! feed {
+        () => {
+            if mono_tx {
+                while tx_mono_p.ready() {
+                    tx_mono_p.enqueue(mono_sample(mode, count)).ok();
+                    count += 1;
+                }
+            } else {
+                while tx_data_32_p.ready() {
+                    tx_data_32_p.enqueue(stereo_frame(count)).ok();
+                    count += 1;
+                }
+            }
+        };
+    }
+    feed!();
+
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, shared_tx_driver);
+    let (mut shared_exti, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
+    // Set up and start drivers
+    let mut tx_driver = drv_cfg_base.i2s_driver(tx);
+    let sample_rate = tx_driver.sample_rate();
+    tx_driver.set_tx_interrupt(true);
+
+    let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+    rx_driver.set_rx_interrupt(true);
+    rx_driver.set_error_interrupt(true);
+
+    (
+        &mut shared_exti,
+        &mut shared_rx_driver,
+        &mut shared_tx_driver,
+    )
+        .lock(|exti, shared_rx_driver, shared_tx_driver| {
+            if on_transmit {
+                shared_tx_driver.set_channel_mode(mode, FILL);
+            } else {
+                shared_rx_driver.set_channel_mode(mode, FILL);
+            }
+            tx_driver.enable();
+            shared_tx_driver.replace(MasterTransmit32bits(tx_driver));
+            rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+            shared_rx_driver.replace(SlaveReceive32bits(rx_driver));
+        });
+    rprint!(", SR {} ... ", sample_rate);
+    let frame_cycles = SYSCLK_HZ / sample_rate;
+    // give up if the stream doesn't come
+    let timeout = 4 * TEST_FRAMES * frame_cycles;
+    let deadline = deadline(timeout);
+
+    // feed the transmitter and check frames as they come
+    let mut check = ChannelCheck::default();
+    let mut timed_out = false;
+    while check.frames < TEST_FRAMES {
+        if deadline.is_expired() {
+            timed_out = true;
+            break;
+        }
+        feed!();
+        if mono_rx {
+            if let Some((_, sample)) = rx_mono_c.dequeue() {
+                check.check(mode, mode.frame_of(sample, FILL));
+            }
+        } else if let Some((_, frame)) = rx_data_32_c.dequeue() {
+            check.check(mode, frame);
+        }
+    }
+    // let the transmitter consume what is left in its queue
+    if !timed_out {
+        timed_out = deadline
+            .wait(|| {
+                while rx_data_32_c.dequeue().is_some() || rx_mono_c.dequeue().is_some() {}
+                tx_data_32_p.len() == 0 && tx_mono_p.len() == 0
+            })
+            .is_err();
+    }
+
+    //disable driver and release
+    let rx_errors = shared_rx_driver.lock(|rx_driver| rx_driver.errors());
+    let tx_errors = shared_tx_driver.lock(|tx_driver| tx_driver.errors());
+    let (rx, tx) = running.finish();
+
+    // drop leftovers
+    while rx_data_32_c.dequeue().is_some() {}
+    while rx_mono_c.dequeue().is_some() {}
+
+    // display result
+    if verdict(!timed_out && check.is_ok()) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed");
+        rprintln!(
+            "{} frames, {} errors, {} not filled{}",
+            check.frames,
+            check.errors,
+            check.fill_errors,
+            if timed_out { ", timed out" } else { "" }
+        );
+        rprintln!("  {} {:?}", Rx::NAME, rx_errors);
+        rprintln!("  {} {:?}", Tx::NAME, tx_errors);
+    }
+    (rx, tx)
+}
@@ -0,0 +1,153 @@
+//! Codec scenario
+//!
+//! i2s3 streams a sine as master, MCLK included, to the board codec configured over I2C for the
+//! same format. Nothing comes back from the codec, the stream is checked for underruns and for
+//! clock errors when the codec reports them. See [`board`](crate::board) for the wiring.
+
+use crate::app::I2s3;
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::{DWT, EXTI};
+
+use rtic::mutex::prelude::*;
+
+use crate::codec::{self, Codec, Error, Format};
+use crate::driver_wrap::*;
+use crate::progress::{skip, verdict};
+use crate::signal::*;
+use crate::teardown::Teardown;
+use crate::{deadline_ms, SYSCLK_HZ};
+
+use TxMode::*;
+
+/// Nominal sample rate, the codec only cares about the MCLK ratio
+const SAMPLE_RATE: u32 = 48000;
+
+/// Streaming duration, in ms
+const STREAM_MS: u32 = 500;
+
+/// Longest wait for the transmitter to empty its queue, in ms
+const DRAIN_MS: u32 = 10;
+
+/// Output volume, in dB
+const VOLUME_DB: i8 = -20;
+
+/// Format seen by the codec for a driver in the Philips standard
+fn philips(data_format: DataFormat) -> Format {
+    let (data_bits, channel_bits) = match data_format {
+        DataFormat::Data16Channel16 => (16, 16),
+        DataFormat::Data16Channel32 => (16, 32),
+        DataFormat::Data24Channel32 => (24, 32),
+        DataFormat::Data32Channel32 => (32, 32),
+    };
+    Format {
+        standard: codec::Standard::Philips,
+        data_bits,
+        channel_bits,
+    }
+}
+
+// configure the codec and open its output
+fn start<C: Codec>(codec: &mut C, format: Format) -> Result<(), Error<C::BusError>> {
+    codec.init(format, SAMPLE_RATE)?;
+    // drop a clock error latched while powering up
+    codec.clock_error()?;
+    codec.set_volume(VOLUME_DB)?;
+    codec.set_mute(false)
+}
+
+/// Stream a 1 kHz sine from i2s3 as master to `codec`.
+pub fn stream_to_codec<C: Codec>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_i2s3_driver: &mut impl Mutex<T = DriverWrap<I2s3>>,
+    i2s3_data_32_p: &mut Producer<'static, (i32, i32), 8_usize>,
+    codec: &mut C,
+    i2s3: I2s3,
+) -> I2s3 {
+    let data_format = DataFormat::Data24Channel32;
+    rprint!("Codec {} Master Transmit 24 bits", C::NAME);
+    if let Err(Error::Bus(e)) = codec.probe() {
+        rprintln!(" ... skipped, no codec answering: {:?}", e);
+        skip();
+        return i2s3;
+    }
+
+    //reset I2s peripheral
+    I2s3::reset_peripheral();
+
+    // clocks must run while the codec powers up
+    let mut i2s3_driver = I2sDriverConfig::new_master()
+        .transmit()
+        .standard(Philips)
+        .data_format(data_format)
+        .master_clock(true)
+        .request_frequency(SAMPLE_RATE)
+        .i2s_driver(i2s3);
+    let sample_rate = i2s3_driver.sample_rate();
+    rprint!(", SR {} ... ", sample_rate);
+    i2s3_driver.set_tx_interrupt(true);
+
+    let signal = Signal::Sine {
+        freq: 1000.0,
+        amplitude: 0.5,
+    };
+    let mut generator = Generator::new(signal, sample_rate);
+    generator.fill(i2s3_data_32_p);
+
+    // from here on the guard tears the driver down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_i2s3_driver, ());
+    let (_, shared_i2s3_driver, _) = running.parts();
+    shared_i2s3_driver.lock(|shared_i2s3_driver| {
+        i2s3_driver.enable();
+        shared_i2s3_driver.replace(MasterTransmit32bits(i2s3_driver));
+    });
+
+    // stream for a while if the codec accepts the configuration
+    let started = start(codec, philips(data_format));
+    let mut clock_error = Ok(None);
+    if started.is_ok() {
+        let duration = STREAM_MS * (SYSCLK_HZ / 1000);
+        let begin = DWT::cycle_count();
+        while DWT::cycle_count().wrapping_sub(begin) < duration {
+            generator.fill(i2s3_data_32_p);
+        }
+        clock_error = codec.clock_error();
+    }
+    let stopped = codec.power_down();
+
+    // let the transmitter consume what is left in its queue
+    let drained = deadline_ms(DRAIN_MS).wait(|| i2s3_data_32_p.len() == 0);
+
+    //disable driver and release
+    let errors = shared_i2s3_driver.lock(|i2s3_driver| i2s3_driver.errors());
+    let (i2s3, ()) = running.finish();
+
+    // display result
+    let clock_ok = !matches!(clock_error, Ok(Some(true)) | Err(_));
+    let ok = started.is_ok() && stopped.is_ok() && drained.is_ok() && clock_ok && errors.udr == 0;
+    if verdict(ok) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed");
+        if let Err(e) = started {
+            rprintln!("  codec setup {:?}", e);
+        }
+        if let Err(e) = stopped {
+            rprintln!("  codec power down {:?}", e);
+        }
+        if drained.is_err() {
+            rprintln!("  timed out");
+        }
+        match clock_error {
+            Ok(Some(true)) => rprintln!("  codec clock error"),
+            Err(e) => rprintln!("  codec status {:?}", e),
+            _ => (),
+        }
+        rprintln!("  i2s3 {:?}", errors);
+    }
+    i2s3
+}
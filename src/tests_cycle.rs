@@ -0,0 +1,293 @@
+//! Start/stop cycling tests
+//!
+//! The transmitter streams the counter pattern to the receiver by blocks while both are stopped
+//! and started again many times, as on a track change. After each restart the pattern must come
+//! back within `REALIGN_FRAMES`, and the handlers must start from the first channel: no channel
+//! error before the first block and its first frame in place. TDM modes are left out, their
+//! slaves aren't synchronised.
+
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::EXTI;
+
+use rtic::mutex::prelude::*;
+
+use crate::block::*;
+use crate::driver_wrap::*;
+use crate::progress::verdict;
+use crate::teardown::Teardown;
+use crate::{deadline, SYSCLK_HZ};
+
+use RxMode::*;
+use TxMode::*;
+
+/// Stop/start cycles of each scenario
+const CYCLES: u32 = 100;
+
+/// Blocks checked between two restarts
+const CYCLE_BLOCKS: usize = 2;
+
+/// Frames a restarted stream may take to carry the pattern again
+const REALIGN_FRAMES: u32 = 8;
+
+/// How drivers are stopped and started again
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Cycle {
+    /// `disable()` then `restart()`
+    Enable,
+    /// `take()` then `replace()` and `restart()`
+    Replace,
+}
+
+/// All cycles, for scenarios comparing them
+pub const CYCLE_KINDS: [Cycle; 2] = [Cycle::Enable, Cycle::Replace];
+
+impl Cycle {
+    pub fn name(self) -> &'static str {
+        match self {
+            Cycle::Enable => "disable/enable",
+            Cycle::Replace => "take/replace",
+        }
+    }
+}
+
+/// Stream `bits` bits frames from `Tx` to `Rx`, `Tx` being master if `tx_master`, slave
+/// otherwise, stopping and starting both `CYCLES` times with `cycle`.
+#[allow(clippy::too_many_arguments)]
+pub fn start_stop_cycling<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    shared_rx_blocks: &mut impl Mutex<T = BlockDrainer<'static, BLOCK_LEN>>,
+    tx_data_16_p: &mut Producer<'static, (i16, i16), 8_usize>,
+    tx_data_32_p: &mut Producer<'static, (i32, i32), 8_usize>,
+    cycle: Cycle,
+    bits: u8,
+    tx_master: bool,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
+    rprint!(
+        "{} {} bits, {} x{}",
+        if tx_master {
+            "Master Transmit + Slave Receive"
+        } else {
+            "Slave Transmit + Master Receive"
+        },
+        bits,
+        cycle.name(),
+        CYCLES
+    );
+    let data_format = match bits {
+        16 => DataFormat::Data16Channel32,
+        _ => DataFormat::Data32Channel32,
+    };
+    let drv_cfg_base = I2sDriverConfig::new_master()
+        .standard(Philips)
+        .data_format(data_format)
+        .master_clock(true)
+        .request_frequency(48000);
+
+    //reset I2s peripherals
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
+
+    // keep the transmit queue full of the pattern, it runs on across cycles
+    let mut count = 0;
+    let mut feed = || {
+        if bits == 16 {
+            while tx_data_16_p.ready() {
+                let (l, r) = counter_frame(count);
+                tx_data_16_p.enqueue((l as i16, r as i16)).ok();
+                count += 1;
+            }
+        } else {
+            while tx_data_32_p.ready() {
+                tx_data_32_p.enqueue(counter_frame(count)).ok();
+                count += 1;
+            }
+        }
+    };
+    feed();
+
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, shared_tx_driver);
+    let (mut shared_exti, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
+    // Set up and start drivers
+    macro_rules! set_up {
+        ($MasterTx:ident, $SlaveRx:ident, $SlaveTx:ident, $MasterRx:ident) => {{
+            if tx_master {
+                let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+                rx_driver.set_rx_interrupt(true);
+                rx_driver.set_error_interrupt(true);
+                let mut tx_driver = drv_cfg_base.transmit().i2s_driver(tx);
+                let sample_rate = tx_driver.sample_rate();
+                tx_driver.set_tx_interrupt(true);
+                (
+                    &mut shared_exti,
+                    &mut shared_rx_driver,
+                    &mut shared_tx_driver,
+                )
+                    .lock(|exti, shared_rx_driver, shared_tx_driver| {
+                        tx_driver.enable();
+                        shared_tx_driver.replace($MasterTx(tx_driver));
+                        rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                        shared_rx_driver.set_rx_sink(RxSink::Block);
+                        shared_rx_driver.replace($SlaveRx(rx_driver));
+                    });
+                sample_rate
+            } else {
+                let mut rx_driver = drv_cfg_base.receive().i2s_driver(rx);
+                let sample_rate = rx_driver.sample_rate();
+                rx_driver.set_rx_interrupt(true);
+                let mut tx_driver = drv_cfg_base.to_slave().transmit().i2s_driver(tx);
+                tx_driver.set_tx_interrupt(true);
+                tx_driver.set_error_interrupt(true);
+                (
+                    &mut shared_exti,
+                    &mut shared_rx_driver,
+                    &mut shared_tx_driver,
+                )
+                    .lock(|exti, shared_rx_driver, shared_tx_driver| {
+                        rx_driver.enable();
+                        shared_rx_driver.set_rx_sink(RxSink::Block);
+                        shared_rx_driver.replace($MasterRx(rx_driver));
+                        tx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                        shared_tx_driver.replace($SlaveTx(tx_driver));
+                    });
+                sample_rate
+            }
+        }};
+    }
+    let sample_rate = match bits {
+        16 => set_up!(
+            MasterTransmit16bits,
+            SlaveReceive16bits,
+            SlaveTransmit16bits,
+            MasterReceive16bits
+        ),
+        _ => set_up!(
+            MasterTransmit32bits,
+            SlaveReceive32bits,
+            SlaveTransmit32bits,
+            MasterReceive32bits
+        ),
+    };
+    rprint!(", SR {} ... ", sample_rate);
+    let frame_cycles = SYSCLK_HZ / sample_rate;
+    // give up if the stream doesn't come back
+    let timeout = 4 * CYCLE_BLOCKS as u32 * BLOCK_LEN as u32 * frame_cycles;
+
+    let channel_errors = |rx_driver: &mut DriverWrap<Rx>, tx_driver: &mut DriverWrap<Tx>| {
+        rx_driver.errors().channel + tx_driver.errors().channel
+    };
+    let mut started_errors = (&mut shared_rx_driver, &mut shared_tx_driver)
+        .lock(|rx_driver, tx_driver| channel_errors(rx_driver, tx_driver));
+
+    let mut realign_max = 0;
+    // starts not realigned in time, and with handlers not starting from the first channel
+    let mut late = 0;
+    let mut stale = 0;
+    let mut timed_out = false;
+    for _ in 0..CYCLES {
+        // feed the transmitter and check blocks as they come
+        let deadline = deadline(timeout);
+        let mut check = SequenceCheck::new();
+        let mut received = 0;
+        while received < CYCLE_BLOCKS {
+            if deadline.is_expired() {
+                timed_out = true;
+                break;
+            }
+            feed();
+            let mut first_in_place = None;
+            shared_rx_blocks.lock(|rx_blocks| {
+                if let Some(block) = rx_blocks.dequeue() {
+                    if received == 0 {
+                        // a frame on the wrong channels doesn't match the pattern
+                        let (l, r) = block.frames[0];
+                        first_in_place = Some(r == !l);
+                    }
+                    check.check(block);
+                    rx_blocks.release(block);
+                    received += 1;
+                }
+            });
+            if let Some(in_place) = first_in_place {
+                let errors = (&mut shared_rx_driver, &mut shared_tx_driver)
+                    .lock(|rx_driver, tx_driver| channel_errors(rx_driver, tx_driver));
+                if !in_place || errors != started_errors {
+                    stale += 1;
+                }
+            }
+        }
+        if timed_out {
+            break;
+        }
+        // frames up to the last error, the stream must be clean after them
+        let realign = check.frames - check.run;
+        realign_max = realign_max.max(realign);
+        if check.run == 0 || realign > REALIGN_FRAMES {
+            late += 1;
+        }
+
+        // stop
+        let taken = (&mut shared_rx_driver, &mut shared_tx_driver).lock(|rx_driver, tx_driver| {
+            rx_driver.disable();
+            tx_driver.disable();
+            match cycle {
+                Cycle::Enable => None,
+                Cycle::Replace => Some((rx_driver.take(), tx_driver.take())),
+            }
+        });
+        shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+
+        // start again, the slave waiting for WS before the master runs
+        (&mut shared_rx_driver, &mut shared_tx_driver).lock(|rx_driver, tx_driver| {
+            if let Some((rx_drv, tx_drv)) = taken {
+                rx_driver.replace(rx_drv.unwrap());
+                tx_driver.replace(tx_drv.unwrap());
+            }
+            // each cycle starts on fresh blocks
+            rx_driver.set_rx_sink(RxSink::Block);
+            if tx_master {
+                rx_driver.restart();
+                tx_driver.restart();
+            } else {
+                tx_driver.restart();
+                rx_driver.restart();
+            }
+            started_errors = channel_errors(rx_driver, tx_driver);
+        });
+    }
+
+    //disable driver and release
+    let rx_errors = shared_rx_driver.lock(|rx_driver| rx_driver.errors());
+    let tx_errors = shared_tx_driver.lock(|tx_driver| tx_driver.errors());
+    let (rx, tx) = running.finish();
+
+    // drop leftovers
+    shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+
+    // display result, stop and start may underrun or overrun
+    if verdict(!timed_out && late == 0 && stale == 0) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed");
+    }
+    rprintln!(
+        "  realigned within {} frames, {} late, {} stale, {} underruns, {} overruns{}",
+        realign_max,
+        late,
+        stale,
+        rx_errors.udr + tx_errors.udr,
+        rx_errors.ovr + tx_errors.ovr,
+        if timed_out { ", timed out" } else { "" }
+    );
+    (rx, tx)
+}
@@ -0,0 +1,306 @@
+//! Fault injection tests
+//!
+//! Each scenario streams the counter pattern from the transmitter to the receiver by blocks,
+//! injects a fault once the stream is established, then checks the stream recovered with a
+//! bounded loss.
+
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::{DWT, EXTI, NVIC};
+
+use rtic::mutex::prelude::*;
+
+use crate::block::*;
+use crate::driver_wrap::*;
+use crate::progress::verdict;
+use crate::recovery::*;
+use crate::teardown::Teardown;
+use crate::{deadline, SYSCLK_HZ};
+
+use RxMode::*;
+use TxMode::*;
+
+/// Blocks received before injecting the fault
+const SETTLE_BLOCKS: usize = 4;
+
+/// Blocks received after injecting the fault
+const RECOVERY_BLOCKS: usize = 16;
+
+/// Duration of stalls and master glitches, in frames
+const FAULT_FRAMES: u32 = 16;
+
+/// Correct frames expected at the end of the stream
+const RECOVERED_FRAMES: u32 = 4 * BLOCK_LEN as u32;
+
+/// Lost or corrupted frames tolerated on top of the fault duration
+const LOSS_MARGIN: u32 = 2 * BLOCK_LEN as u32;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Slave transmitter interrupt held off
+    Underrun,
+    /// Slave receiver interrupt held off
+    Overrun,
+    /// Slave receiver enabled again in the middle of a frame, without waiting WS
+    SlaveMidFrame,
+    /// Master transmitter disabled then enabled again
+    MasterGlitch,
+    /// Master receiver frame tracking moved to the other channel
+    MasterDesync,
+}
+
+/// Faults injected by the test suite
+pub const FAULTS: [(&str, Fault); 5] = [
+    ("underrun", Fault::Underrun),
+    ("overrun", Fault::Overrun),
+    ("slave mid-frame", Fault::SlaveMidFrame),
+    ("master glitch", Fault::MasterGlitch),
+    ("master desync", Fault::MasterDesync),
+];
+
+fn busy_wait(cycles: u32) {
+    let start = DWT::cycle_count();
+    while DWT::cycle_count().wrapping_sub(start) < cycles {}
+}
+
+// hold off the SPI interrupt of `I` alone, other tasks keep running
+fn stall<I: I2sInstance>(cycles: u32) {
+    NVIC::mask(I::INTERRUPT);
+    busy_wait(cycles);
+    // the interrupt is bound to an RTIC task, the pending request is served now
+    unsafe { NVIC::unmask(I::INTERRUPT) };
+}
+
+/// Stream the counter pattern, inject `fault` and check the receiver get the pattern back, both
+/// drivers recovering with `policy`. The underrun and the master desync need `Tx` as slave
+/// transmitter and `Rx` as master receiver, other faults use `Tx` as master transmitter and `Rx`
+/// as slave receiver.
+#[allow(clippy::too_many_arguments)]
+pub fn fault_injection<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    shared_rx_blocks: &mut impl Mutex<T = BlockDrainer<'static, BLOCK_LEN>>,
+    tx_data_p: &mut Producer<'static, (i32, i32), 8_usize>,
+    name: &str,
+    fault: Fault,
+    policy: RecoveryPolicy,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
+    rprint!("Fault injection {} {} 32 bits", name, policy.name());
+    let drv_cfg_base = I2sDriverConfig::new_master()
+        .receive()
+        .standard(Philips)
+        .data_format(DataFormat::Data32Channel32)
+        .master_clock(true)
+        .request_frequency(1);
+
+    //reset I2s peripherals
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
+
+    // prepare data to transmit
+    let mut count = 0;
+    while tx_data_p.ready() {
+        tx_data_p.enqueue(counter_frame(count)).ok();
+        count += 1;
+    }
+
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, shared_tx_driver);
+    let (mut shared_exti, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
+    // Set up and start drivers
+    let sample_rate;
+    if matches!(fault, Fault::Underrun | Fault::MasterDesync) {
+        let mut rx_driver = drv_cfg_base.i2s_driver(rx);
+        sample_rate = rx_driver.sample_rate();
+        rx_driver.set_rx_interrupt(true);
+        rx_driver.set_error_interrupt(true);
+
+        let mut tx_driver = drv_cfg_base.to_slave().transmit().i2s_driver(tx);
+        tx_driver.set_tx_interrupt(true);
+        tx_driver.set_error_interrupt(true);
+
+        (
+            &mut shared_exti,
+            &mut shared_rx_driver,
+            &mut shared_tx_driver,
+        )
+            .lock(|exti, shared_rx_driver, shared_tx_driver| {
+                rx_driver.enable();
+                shared_rx_driver.set_recovery(policy);
+                shared_rx_driver.set_rx_sink(RxSink::Block);
+                shared_rx_driver.replace(MasterReceive32bits(rx_driver));
+                tx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                shared_tx_driver.set_recovery(policy);
+                shared_tx_driver.replace(SlaveTransmit32bits(tx_driver));
+            });
+    } else {
+        let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+        rx_driver.set_rx_interrupt(true);
+        rx_driver.set_error_interrupt(true);
+
+        let mut tx_driver = drv_cfg_base.transmit().i2s_driver(tx);
+        sample_rate = tx_driver.sample_rate();
+        tx_driver.set_tx_interrupt(true);
+
+        (
+            &mut shared_exti,
+            &mut shared_rx_driver,
+            &mut shared_tx_driver,
+        )
+            .lock(|exti, shared_rx_driver, shared_tx_driver| {
+                tx_driver.enable();
+                shared_tx_driver.set_recovery(policy);
+                shared_tx_driver.replace(MasterTransmit32bits(tx_driver));
+                rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                shared_rx_driver.set_recovery(policy);
+                shared_rx_driver.set_rx_sink(RxSink::Block);
+                shared_rx_driver.replace(SlaveReceive32bits(rx_driver));
+            });
+    }
+    rprint!(", SR {} ... ", sample_rate);
+    let frame_cycles = SYSCLK_HZ / sample_rate;
+    let fault_cycles = FAULT_FRAMES * frame_cycles;
+    // give up if the stream doesn't come back
+    let timeout = 4 * (SETTLE_BLOCKS + RECOVERY_BLOCKS) as u32 * BLOCK_LEN as u32 * frame_cycles;
+    let deadline = deadline(timeout);
+
+    // feed the transmitter and check blocks as they come, injecting the fault on the way
+    let mut check = SequenceCheck::new();
+    let mut received = 0;
+    let mut timed_out = false;
+    while received < SETTLE_BLOCKS + RECOVERY_BLOCKS {
+        if deadline.is_expired() {
+            timed_out = true;
+            break;
+        }
+        while tx_data_p.ready() {
+            tx_data_p.enqueue(counter_frame(count)).ok();
+            count += 1;
+        }
+        let got_block = shared_rx_blocks.lock(|rx_blocks| {
+            if let Some(block) = rx_blocks.dequeue() {
+                check.check(block);
+                rx_blocks.release(block);
+                true
+            } else {
+                false
+            }
+        });
+        if !got_block {
+            continue;
+        }
+        received += 1;
+        if received != SETTLE_BLOCKS {
+            continue;
+        }
+        match fault {
+            Fault::Underrun => stall::<Tx>(fault_cycles),
+            Fault::Overrun => stall::<Rx>(fault_cycles),
+            Fault::SlaveMidFrame => {
+                shared_rx_driver.lock(|rx_driver| {
+                    rx_driver.disable();
+                    rx_driver.reset_frame();
+                });
+                // wait the start of a left channel, then half of it
+                let mut ws_is = |level| {
+                    deadline.wait(|| {
+                        shared_rx_driver.lock(|rx_driver| rx_driver.ws_is_high()) == Some(level)
+                    })
+                };
+                timed_out = ws_is(true).and_then(|_| ws_is(false)).is_err();
+                busy_wait(frame_cycles / 4);
+                shared_rx_driver.lock(|rx_driver| rx_driver.enable());
+            }
+            Fault::MasterGlitch => {
+                shared_tx_driver.lock(|tx_driver| tx_driver.disable());
+                busy_wait(fault_cycles);
+                shared_tx_driver.lock(|tx_driver| {
+                    tx_driver.reset_frame();
+                    tx_driver.enable();
+                });
+            }
+            Fault::MasterDesync => shared_rx_driver.lock(|rx_driver| rx_driver.skew_frame()),
+        }
+    }
+    // let the transmitter consume what is left in its queue
+    if !timed_out {
+        timed_out = deadline
+            .wait(|| {
+                shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+                tx_data_p.len() == 0
+            })
+            .is_err();
+    }
+
+    //disable driver and release
+    let (rx_errors, rx_sync, rx_recovery) = shared_rx_driver.lock(|rx_driver| {
+        (
+            rx_driver.errors(),
+            rx_driver.sync_stats(),
+            rx_driver.recovery_stats(),
+        )
+    });
+    let (tx_errors, tx_sync, tx_recovery) = shared_tx_driver.lock(|tx_driver| {
+        (
+            tx_driver.errors(),
+            tx_driver.sync_stats(),
+            tx_driver.recovery_stats(),
+        )
+    });
+    let (rx, tx) = running.finish();
+
+    // drop leftovers
+    shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+
+    // display result
+    let loss = check.lost + check.data_errors;
+    // make sure the fault actually happened, the receiver must have noticed the broken frames
+    let injected = match fault {
+        Fault::Underrun => tx_errors.udr > 0,
+        Fault::Overrun => rx_errors.ovr > 0,
+        Fault::SlaveMidFrame => rx_errors.fre > 0 || rx_recovery.restarts > 0,
+        Fault::MasterGlitch => rx_errors.channel > 0,
+        // a single desynchronisation restarts a strict master, a lenient one resynchronises
+        Fault::MasterDesync => match policy {
+            RecoveryPolicy::Strict => rx_recovery.restarts > 0,
+            RecoveryPolicy::Lenient => rx_recovery.resyncs > 0 && rx_recovery.restarts == 0,
+        },
+    };
+    let recovered = !timed_out && check.run >= RECOVERED_FRAMES;
+    if verdict(injected && recovered && loss <= FAULT_FRAMES + LOSS_MARGIN) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed");
+        rprintln!(
+            "{} frames, {} lost, {} data errors, {} correct at end{}",
+            check.frames,
+            check.lost,
+            check.data_errors,
+            check.run,
+            if timed_out { ", timed out" } else { "" }
+        );
+    }
+    rprintln!(
+        "  {} {:?} {:?} {:?}",
+        Rx::NAME,
+        rx_errors,
+        rx_sync,
+        rx_recovery
+    );
+    rprintln!(
+        "  {} {:?} {:?} {:?}",
+        Tx::NAME,
+        tx_errors,
+        tx_sync,
+        tx_recovery
+    );
+    (rx, tx)
+}
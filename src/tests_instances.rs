@@ -0,0 +1,176 @@
+//! I2S instances tests
+//!
+//! Instances other than i2s2 and i2s3 transmit the counter pattern by blocks to i2s2, once as
+//! master and once as slave, so instances clocked from APB2 are validated too. See
+//! [`board`](crate::board) for the wiring.
+
+use crate::app::I2s2;
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::EXTI;
+
+use rtic::mutex::prelude::*;
+
+use crate::block::*;
+use crate::driver_wrap::*;
+use crate::progress::verdict;
+use crate::teardown::Teardown;
+use crate::{deadline, SYSCLK_HZ};
+
+use RxMode::*;
+use TxMode::*;
+
+/// Blocks checked for each configuration
+const TEST_BLOCKS: usize = 8;
+
+/// Stream the counter pattern from `tx` to i2s2, `tx` being master or slave.
+#[allow(clippy::too_many_arguments)]
+pub fn transmit_to_i2s2<T: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<T>>,
+    shared_i2s2_driver: &mut impl Mutex<T = DriverWrap<I2s2>>,
+    shared_i2s2_blocks: &mut impl Mutex<T = BlockDrainer<'static, BLOCK_LEN>>,
+    tx_data_p: &mut Producer<'static, (i32, i32), 8_usize>,
+    tx_master: bool,
+    tx: T,
+    i2s2: I2s2,
+) -> (T, I2s2) {
+    rprint!(
+        "{} {} Transmit to i2s2 32 bits",
+        T::NAME,
+        if tx_master { "Master" } else { "Slave" }
+    );
+    let drv_cfg_base = I2sDriverConfig::new_master()
+        .transmit()
+        .standard(Philips)
+        .data_format(DataFormat::Data32Channel32)
+        .master_clock(true)
+        .request_frequency(48000);
+
+    //reset I2s peripherals
+    T::reset_peripheral();
+    I2s2::reset_peripheral();
+
+    // prepare data to transmit
+    let mut count = 0;
+    while tx_data_p.ready() {
+        tx_data_p.enqueue(counter_frame(count)).ok();
+        count += 1;
+    }
+
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_tx_driver, shared_i2s2_driver);
+    let (mut shared_exti, mut shared_tx_driver, mut shared_i2s2_driver) = running.parts();
+
+    // Set up and start drivers
+    let sample_rate;
+    if tx_master {
+        let mut tx_driver = drv_cfg_base.i2s_driver(tx);
+        sample_rate = tx_driver.sample_rate();
+        tx_driver.set_tx_interrupt(true);
+
+        let mut i2s2_driver = drv_cfg_base.to_slave().receive().i2s_driver(i2s2);
+        i2s2_driver.set_rx_interrupt(true);
+        i2s2_driver.set_error_interrupt(true);
+
+        (
+            &mut shared_exti,
+            &mut shared_tx_driver,
+            &mut shared_i2s2_driver,
+        )
+            .lock(|exti, shared_tx_driver, shared_i2s2_driver| {
+                tx_driver.enable();
+                shared_tx_driver.replace(MasterTransmit32bits(tx_driver));
+                i2s2_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                shared_i2s2_driver.set_rx_sink(RxSink::Block);
+                shared_i2s2_driver.replace(SlaveReceive32bits(i2s2_driver));
+            });
+    } else {
+        let mut i2s2_driver = drv_cfg_base.receive().i2s_driver(i2s2);
+        sample_rate = i2s2_driver.sample_rate();
+        i2s2_driver.set_rx_interrupt(true);
+        i2s2_driver.set_error_interrupt(true);
+
+        let mut tx_driver = drv_cfg_base.to_slave().i2s_driver(tx);
+        tx_driver.set_tx_interrupt(true);
+        tx_driver.set_error_interrupt(true);
+
+        (
+            &mut shared_exti,
+            &mut shared_tx_driver,
+            &mut shared_i2s2_driver,
+        )
+            .lock(|exti, shared_tx_driver, shared_i2s2_driver| {
+                i2s2_driver.enable();
+                shared_i2s2_driver.set_rx_sink(RxSink::Block);
+                shared_i2s2_driver.replace(MasterReceive32bits(i2s2_driver));
+                tx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                shared_tx_driver.replace(SlaveTransmit32bits(tx_driver));
+            });
+    }
+    rprint!(", SR {} ... ", sample_rate);
+    let frame_cycles = SYSCLK_HZ / sample_rate;
+    // give up if the stream doesn't come
+    let timeout = 4 * TEST_BLOCKS as u32 * BLOCK_LEN as u32 * frame_cycles;
+    let deadline = deadline(timeout);
+
+    // feed the transmitter and check blocks as they come
+    let mut check = SequenceCheck::new();
+    let mut received = 0;
+    let mut timed_out = false;
+    while received < TEST_BLOCKS {
+        if deadline.is_expired() {
+            timed_out = true;
+            break;
+        }
+        while tx_data_p.ready() {
+            tx_data_p.enqueue(counter_frame(count)).ok();
+            count += 1;
+        }
+        shared_i2s2_blocks.lock(|i2s2_blocks| {
+            if let Some(block) = i2s2_blocks.dequeue() {
+                check.check(block);
+                i2s2_blocks.release(block);
+                received += 1;
+            }
+        });
+    }
+    // let the transmitter consume what is left in its queue
+    if !timed_out {
+        timed_out = deadline
+            .wait(|| {
+                shared_i2s2_blocks.lock(|i2s2_blocks| i2s2_blocks.flush());
+                tx_data_p.len() == 0
+            })
+            .is_err();
+    }
+
+    //disable driver and release
+    let i2s2_errors = shared_i2s2_driver.lock(|i2s2_driver| i2s2_driver.errors());
+    let tx_errors = shared_tx_driver.lock(|tx_driver| tx_driver.errors());
+    let (tx, i2s2) = running.finish();
+
+    // drop leftovers
+    shared_i2s2_blocks.lock(|i2s2_blocks| i2s2_blocks.flush());
+
+    // display result
+    if verdict(!timed_out && check.is_ok()) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed");
+        rprintln!(
+            "{} frames, {} lost, {} data errors{}",
+            check.frames,
+            check.lost,
+            check.data_errors,
+            if timed_out { ", timed out" } else { "" }
+        );
+        rprintln!("  {} {:?}", T::NAME, tx_errors);
+        rprintln!("  i2s2 {:?}", i2s2_errors);
+    }
+    (tx, i2s2)
+}
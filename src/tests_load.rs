@@ -0,0 +1,201 @@
+//! Interrupt latency tests
+//!
+//! The counter pattern streams from the transmitter to the receiver by blocks while a load task
+//! busy-waits above or below the I2S tasks. Loads below must leave the stream alone, loads above
+//! show where the late interrupts start to underrun or overrun.
+//!
+//! The drivers and `tx_data` are shared with the `set_layout` task at
+//! [`LAYOUT_CEILING`](crate::priority::LAYOUT_CEILING), so the I2S tasks lock them and the
+//! measured onsets include the cost of raising BASEPRI in each handler.
+
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::EXTI;
+
+use rtic::mutex::prelude::*;
+
+use crate::block::*;
+use crate::driver_wrap::*;
+use crate::load::{Level, Onset, Profile};
+use crate::load_timer::LoadTask;
+use crate::progress::verdict;
+use crate::teardown::Teardown;
+use crate::{deadline, SYSCLK_HZ};
+
+use RxMode::*;
+use TxMode::*;
+
+/// Blocks checked at each load
+const TEST_BLOCKS: usize = 16;
+
+/// Stream the counter pattern under `profile` applied by the load task, `Tx` being master if
+/// `tx_master`, slave otherwise. The load where errors begin is recorded in `onset`.
+#[allow(clippy::too_many_arguments)]
+pub fn stream_under_load<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    shared_rx_blocks: &mut impl Mutex<T = BlockDrainer<'static, BLOCK_LEN>>,
+    shared_load: &mut impl Mutex<T = LoadTask>,
+    tx_data_p: &mut Producer<'static, (i32, i32), 8_usize>,
+    profile: Profile,
+    tx_master: bool,
+    onset: &mut Onset,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
+    let level = shared_load.lock(|load_task| load_task.load.level());
+    rprint!(
+        "Load {} {}% {}, {} 32 bits",
+        level.name(),
+        profile.percent,
+        profile.busy.name(),
+        if tx_master {
+            "Master Transmit + Slave Receive"
+        } else {
+            "Slave Transmit + Master Receive"
+        }
+    );
+    let drv_cfg_base = I2sDriverConfig::new_master()
+        .standard(Philips)
+        .data_format(DataFormat::Data32Channel32)
+        .master_clock(true)
+        .request_frequency(48000);
+
+    //reset I2s peripherals
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
+
+    // prepare data to transmit
+    let mut count = 0;
+    while tx_data_p.ready() {
+        tx_data_p.enqueue(counter_frame(count)).ok();
+        count += 1;
+    }
+
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, shared_tx_driver);
+    let (mut shared_exti, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
+    // Set up and start drivers
+    let sample_rate;
+    if tx_master {
+        let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+        rx_driver.set_rx_interrupt(true);
+        rx_driver.set_error_interrupt(true);
+
+        let mut tx_driver = drv_cfg_base.transmit().i2s_driver(tx);
+        sample_rate = tx_driver.sample_rate();
+        tx_driver.set_tx_interrupt(true);
+
+        (
+            &mut shared_exti,
+            &mut shared_rx_driver,
+            &mut shared_tx_driver,
+        )
+            .lock(|exti, shared_rx_driver, shared_tx_driver| {
+                tx_driver.enable();
+                shared_tx_driver.replace(MasterTransmit32bits(tx_driver));
+                rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                shared_rx_driver.set_rx_sink(RxSink::Block);
+                shared_rx_driver.replace(SlaveReceive32bits(rx_driver));
+            });
+    } else {
+        let mut rx_driver = drv_cfg_base.receive().i2s_driver(rx);
+        sample_rate = rx_driver.sample_rate();
+        rx_driver.set_rx_interrupt(true);
+
+        let mut tx_driver = drv_cfg_base.to_slave().transmit().i2s_driver(tx);
+        tx_driver.set_tx_interrupt(true);
+        tx_driver.set_error_interrupt(true);
+
+        (
+            &mut shared_exti,
+            &mut shared_rx_driver,
+            &mut shared_tx_driver,
+        )
+            .lock(|exti, shared_rx_driver, shared_tx_driver| {
+                rx_driver.enable();
+                shared_rx_driver.set_rx_sink(RxSink::Block);
+                shared_rx_driver.replace(MasterReceive32bits(rx_driver));
+                tx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                shared_tx_driver.replace(SlaveTransmit32bits(tx_driver));
+            });
+    }
+    rprint!(", SR {} ... ", sample_rate);
+    let frame_cycles = SYSCLK_HZ / sample_rate;
+    // give up if the stream doesn't come
+    let timeout = 4 * TEST_BLOCKS as u32 * BLOCK_LEN as u32 * frame_cycles;
+    let deadline = deadline(timeout);
+
+    // feed the transmitter and check blocks as they come, under load
+    shared_load.lock(|load_task| load_task.set(Some(profile)));
+    let mut check = SequenceCheck::new();
+    let mut received = 0;
+    let mut timed_out = false;
+    while received < TEST_BLOCKS {
+        if deadline.is_expired() {
+            timed_out = true;
+            break;
+        }
+        while tx_data_p.ready() {
+            tx_data_p.enqueue(counter_frame(count)).ok();
+            count += 1;
+        }
+        shared_rx_blocks.lock(|rx_blocks| {
+            if let Some(block) = rx_blocks.dequeue() {
+                check.check(block);
+                rx_blocks.release(block);
+                received += 1;
+            }
+        });
+    }
+    let measured = shared_load.lock(|load_task| {
+        let measured = load_task.load.measured_percent();
+        load_task.set(None);
+        measured
+    });
+    // let the transmitter consume what is left in its queue
+    if !timed_out {
+        timed_out = deadline
+            .wait(|| {
+                shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+                tx_data_p.len() == 0
+            })
+            .is_err();
+    }
+
+    //disable driver and release
+    let rx_errors = shared_rx_driver.lock(|rx_driver| rx_driver.errors());
+    let tx_errors = shared_tx_driver.lock(|tx_driver| tx_driver.errors());
+    let (rx, tx) = running.finish();
+
+    // drop leftovers
+    shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+
+    // display result, errors are expected from some load above the I2S tasks
+    let underruns = rx_errors.udr + tx_errors.udr;
+    let overruns = rx_errors.ovr + tx_errors.ovr;
+    onset.record(profile.percent, underruns, overruns);
+    let clean = !timed_out && check.is_ok() && check.lost == 0 && underruns + overruns == 0;
+    if verdict(!timed_out && (clean || level == Level::Above)) {
+        rprintln!("{}", if clean { "ok" } else { "ok, stream errors" });
+    } else {
+        rprintln!("failed");
+    }
+    rprintln!(
+        "  {} frames, {} lost, {} data errors, {} underruns, {} overruns, load {}% measured{}",
+        check.frames,
+        check.lost,
+        check.data_errors,
+        underruns,
+        overruns,
+        measured,
+        if timed_out { ", timed out" } else { "" }
+    );
+    (rx, tx)
+}
@@ -0,0 +1,175 @@
+//! Loopback self-test
+//!
+//! i2s2 transmits the counter pattern as master and I2S2ext, sharing its CK and WS, receives
+//! it back through a single jumper between their SD pins. The jumper is checked by the
+//! [`Wiring`] pre-flight so missing wiring is reported as a skipped test instead of a failed one.
+
+use crate::app::I2s2;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+#[cfg(has_i2sext)]
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+#[cfg(has_i2sext)]
+use hal::pac::I2S2EXT;
+
+#[cfg(has_i2sext)]
+use crate::block::*;
+#[cfg(has_i2sext)]
+use crate::driver_wrap::*;
+use crate::progress::skip;
+#[cfg(has_i2sext)]
+use crate::progress::verdict;
+use crate::wiring::*;
+#[cfg(has_i2sext)]
+use crate::{deadline, SYSCLK_HZ};
+
+// I2SMOD, I2SCFG = slave receive, Philips, DATLEN = 32 bits, CHLEN = 32 bits
+#[cfg(has_i2sext)]
+const EXT_SLAVE_RECEIVE_32: u32 = 0x0905;
+#[cfg(has_i2sext)]
+const I2SE: u32 = 1 << 10;
+#[cfg(has_i2sext)]
+const SR_RXNE: u32 = 1 << 0;
+#[cfg(has_i2sext)]
+const SR_CHSIDE: u32 = 1 << 2;
+
+/// Blocks checked by the loopback test
+#[cfg(has_i2sext)]
+const TEST_BLOCKS: usize = 8;
+
+/// Run the loopback test if it is wired, report why it can't run otherwise.
+pub fn loopback(wiring: Wiring, i2s2: I2s2) -> I2s2 {
+    rprint!("Loopback i2s2 to I2S2ext 32 bits");
+    #[cfg(has_i2sext)]
+    if wiring.loopback == Link::Present {
+        return i2s2_ext_loopback(i2s2);
+    }
+    if cfg!(has_i2sext) {
+        rprintln!(" ... skipped, wiring {}", wiring.loopback.name());
+    } else {
+        rprintln!(" ... skipped, no I2S2ext on this chip");
+    }
+    skip();
+    i2s2
+}
+
+// Position in a 32 bits frame, 4 half words
+#[cfg(has_i2sext)]
+fn half_word(frame: (i32, i32), pos: usize) -> u16 {
+    let (l, r) = (frame.0 as u32, frame.1 as u32);
+    match pos {
+        0 => (l >> 16) as u16,
+        1 => l as u16,
+        2 => (r >> 16) as u16,
+        _ => r as u16,
+    }
+}
+
+#[cfg(has_i2sext)]
+fn i2s2_ext_loopback(i2s2: I2s2) -> I2s2 {
+    // I2S2ext is reset with SPI2
+    I2s2::reset_peripheral();
+
+    let mut i2s2_driver = I2sDriverConfig::new_master()
+        .transmit()
+        .standard(Philips)
+        .data_format(DataFormat::Data32Channel32)
+        .master_clock(true)
+        .request_frequency(48000)
+        .i2s_driver(i2s2);
+    let sample_rate = i2s2_driver.sample_rate();
+    rprint!(", SR {} ... ", sample_rate);
+    let frame_cycles = SYSCLK_HZ / sample_rate;
+    // give up if nothing comes back
+    let timeout = 4 * TEST_BLOCKS as u32 * BLOCK_LEN as u32 * frame_cycles;
+
+    // the slave must be ready before the master clocks it
+    let ext = unsafe { &*I2S2EXT::ptr() };
+    ext.i2scfgr
+        .write(|w| unsafe { w.bits(EXT_SLAVE_RECEIVE_32) });
+    ext.i2scfgr
+        .modify(|r, w| unsafe { w.bits(r.bits() | I2SE) });
+    i2s2_driver.enable();
+
+    // transmit and receive by polling, tracking the channel of each half word
+    let deadline = deadline(timeout);
+    let (mut count, mut tx_frame, mut tx_pos) = (0, counter_frame(0), 0);
+    let (mut rx_frame, mut rx_pos) = ((0u32, 0u32), 0);
+    let mut block = Block::<BLOCK_LEN>::EMPTY;
+    let mut block_pos = 0;
+    let mut check = SequenceCheck::new();
+    let mut received = 0;
+    let mut timed_out = false;
+    while received < TEST_BLOCKS {
+        if deadline.is_expired() {
+            timed_out = true;
+            break;
+        }
+        let status = i2s2_driver.status();
+        if status.txe() {
+            let left = matches!(status.chside(), Channel::Left);
+            if left != (tx_pos < 2) {
+                // resynchronize at the next left channel
+                tx_pos = 0;
+                i2s2_driver.write_data_register(0);
+            } else {
+                if tx_pos == 0 {
+                    tx_frame = counter_frame(count);
+                    count += 1;
+                }
+                i2s2_driver.write_data_register(half_word(tx_frame, tx_pos));
+                tx_pos = (tx_pos + 1) % 4;
+            }
+        }
+        let ext_sr = ext.sr.read().bits();
+        if (ext_sr & SR_RXNE) != 0 {
+            let data = ext.dr.read().bits() & 0xFFFF;
+            let left = (ext_sr & SR_CHSIDE) == 0;
+            if left != (rx_pos < 2) {
+                rx_pos = 0;
+                continue;
+            }
+            match rx_pos {
+                0 => rx_frame.0 = data << 16,
+                1 => rx_frame.0 |= data,
+                2 => rx_frame.1 = data << 16,
+                _ => rx_frame.1 |= data,
+            }
+            rx_pos = (rx_pos + 1) % 4;
+            if rx_pos == 0 {
+                block.frames[block_pos] = (rx_frame.0 as i32, rx_frame.1 as i32);
+                block_pos += 1;
+                if block_pos == BLOCK_LEN {
+                    check.check(&block);
+                    block.seq = block.seq.wrapping_add(1);
+                    block_pos = 0;
+                    received += 1;
+                }
+            }
+        }
+    }
+
+    //disable and release
+    i2s2_driver.disable();
+    ext.i2scfgr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !I2SE) });
+    let i2s2 = i2s2_driver.release();
+    I2s2::reset_peripheral();
+
+    // display result
+    if verdict(!timed_out && check.is_ok()) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed");
+        rprintln!(
+            "{} frames, {} lost, {} data errors{}",
+            check.frames,
+            check.lost,
+            check.data_errors,
+            if timed_out { ", timed out" } else { "" }
+        );
+    }
+    i2s2
+}
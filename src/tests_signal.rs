@@ -0,0 +1,277 @@
+//! Tests streaming generated signals through I2S
+
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::EXTI;
+
+use rtic::mutex::prelude::*;
+
+use crate::analysis::*;
+use crate::block::*;
+use crate::driver_wrap::*;
+use crate::progress::verdict;
+use crate::signal::*;
+use crate::teardown::Teardown;
+use crate::{deadline, SYSCLK_HZ};
+
+use RxMode::*;
+use TxMode::*;
+
+/// Number of received samples analysed
+const ANALYSIS_LEN: usize = 256;
+
+/// Received blocks ignored while the transmission settle
+const WARMUP_BLOCKS: usize = 2;
+
+/// Signals streamed by the test suite, built from the sample rate
+pub const SIGNALS: [(&str, fn(u32) -> Signal); 5] = [
+    ("sine", |fs| Signal::Sine {
+        freq: fs as f32 / 16.0,
+        amplitude: 0.5,
+    }),
+    ("square", |fs| Signal::Square {
+        freq: fs as f32 / 32.0,
+        amplitude: 0.5,
+    }),
+    ("sweep", |fs| Signal::Sweep {
+        start: fs as f32 / 64.0,
+        end: fs as f32 / 4.0,
+        duration: 0.1,
+        amplitude: 0.5,
+    }),
+    ("multi-tone", |fs| Signal::MultiTone {
+        freqs: [fs as f32 * 0.03, fs as f32 * 0.11, fs as f32 * 0.17, 0.0],
+        count: 3,
+        amplitude: 0.9,
+    }),
+    ("noise", |_| Signal::Noise {
+        seed: 0x1234_5678,
+        amplitude: 0.5,
+    }),
+];
+
+#[derive(Copy, Clone)]
+pub enum Depth {
+    Bits16,
+    Bits32,
+}
+
+impl Depth {
+    /// Highest acceptable THD+N of a received sine, in dB
+    fn max_thd_n_db(self) -> f32 {
+        match self {
+            Depth::Bits16 => -85.0,
+            Depth::Bits32 => -110.0,
+        }
+    }
+}
+
+/// Stream `signal` from `Tx` as master to `Rx` as slave and check what is received. `signal`
+/// is built from the actual sample rate.
+#[allow(clippy::too_many_arguments)]
+pub fn master_transmit_slave_receive_signal<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    shared_rx_blocks: &mut impl Mutex<T = BlockDrainer<'static, BLOCK_LEN>>,
+    tx_data_16_p: &mut Producer<'static, (i16, i16), 8_usize>,
+    tx_data_32_p: &mut Producer<'static, (i32, i32), 8_usize>,
+    name: &str,
+    signal: impl Fn(u32) -> Signal,
+    depth: Depth,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
+    let (data_format, bits) = match depth {
+        Depth::Bits16 => (DataFormat::Data16Channel32, 16),
+        Depth::Bits32 => (DataFormat::Data32Channel32, 32),
+    };
+    rprint!("Master Transmit + Slave Receive {} {} bits", name, bits);
+    let drv_cfg_base = I2sDriverConfig::new_master()
+        .receive()
+        .standard(Philips)
+        .data_format(data_format)
+        .master_clock(true)
+        .request_frequency(1);
+
+    //reset I2s peripherals
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
+
+    // Set up drivers
+    let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+    rx_driver.set_rx_interrupt(true);
+    rx_driver.set_error_interrupt(true);
+
+    let mut tx_driver = drv_cfg_base.transmit().i2s_driver(tx);
+    let sample_rate = tx_driver.sample_rate();
+    rprint!(", SR {} ... ", sample_rate);
+    tx_driver.set_tx_interrupt(true);
+
+    let signal = signal(sample_rate);
+    let mut generator = Generator::new(signal, sample_rate);
+    let mut fill = |generator: &mut Generator| match depth {
+        Depth::Bits16 => generator.fill_16(tx_data_16_p),
+        Depth::Bits32 => generator.fill(tx_data_32_p),
+    };
+    fill(&mut generator);
+
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, shared_tx_driver);
+    let (mut shared_exti, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
+    // start drivers
+    (
+        &mut shared_exti,
+        &mut shared_rx_driver,
+        &mut shared_tx_driver,
+    )
+        .lock(|exti, shared_rx_driver, shared_tx_driver| {
+            tx_driver.enable();
+            rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+            shared_rx_driver.set_rx_sink(RxSink::Block);
+            match depth {
+                Depth::Bits16 => {
+                    shared_tx_driver.replace(MasterTransmit16bits(tx_driver));
+                    shared_rx_driver.replace(SlaveReceive16bits(rx_driver));
+                }
+                Depth::Bits32 => {
+                    shared_tx_driver.replace(MasterTransmit32bits(tx_driver));
+                    shared_rx_driver.replace(SlaveReceive32bits(rx_driver));
+                }
+            }
+        });
+
+    // feed the transmitter and collect received frames
+    let mut capture = Capture::<ANALYSIS_LEN>::new(match depth {
+        Depth::Bits16 => 16,
+        Depth::Bits32 => 0,
+    });
+    let mut blocks = 0;
+    // give up if the stream doesn't come
+    let frame_cycles = SYSCLK_HZ / sample_rate;
+    let blocks_needed = WARMUP_BLOCKS + ANALYSIS_LEN.div_ceil(BLOCK_LEN);
+    let deadline = deadline(4 * (blocks_needed * BLOCK_LEN) as u32 * frame_cycles);
+    let mut timed_out = false;
+    while !capture.is_full() {
+        if deadline.is_expired() {
+            timed_out = true;
+            break;
+        }
+        fill(&mut generator);
+        shared_rx_blocks.lock(|rx_blocks| {
+            if let Some(block) = rx_blocks.dequeue() {
+                if blocks >= WARMUP_BLOCKS {
+                    capture.extend(block);
+                }
+                blocks += 1;
+                rx_blocks.release(block);
+            }
+        });
+    }
+
+    // let the transmitter consume what is left in its queue
+    if !timed_out {
+        timed_out = deadline
+            .wait(|| tx_data_16_p.len() == 0 && tx_data_32_p.len() == 0)
+            .is_err();
+    }
+
+    //disable driver and release
+    let (rx, tx) = running.finish();
+    shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+
+    // display result, both channels carry the signal
+    let channels = [
+        (
+            "left",
+            check_channel(signal, &capture.left, sample_rate, depth),
+        ),
+        (
+            "right",
+            check_channel(signal, &capture.right, sample_rate, depth),
+        ),
+    ];
+    if verdict(!timed_out && channels.iter().all(|(_, check)| check.ok)) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed{}", if timed_out { ", timed out" } else { "" });
+    }
+    let expected_peak = signal.amplitude() * 2147483648.0;
+    for (name, check) in channels.iter() {
+        if !check.ok {
+            rprintln!(
+                "  {}: frequency {:?} expected {:?}, peak {} expected {}",
+                name,
+                check.freq,
+                signal.frequency(),
+                check.peak,
+                expected_peak
+            );
+        }
+        if let Some(tone) = check.tone {
+            rprintln!(
+                "  {}: {:.2} Hz, amplitude {:.5}, THD+N {:.1} dB, THD {:.1} dB, SNR {:.1} dB",
+                name,
+                tone.frequency,
+                tone.amplitude,
+                tone.thd_n_db(),
+                tone.thd_db(),
+                tone.snr_db()
+            );
+        }
+    }
+    (rx, tx)
+}
+
+/// What a channel received
+struct ChannelCheck {
+    ok: bool,
+    freq: Option<f32>,
+    peak: f32,
+    tone: Option<ToneAnalysis>,
+}
+
+/// Check the `samples` of a channel against `signal`. Sines and squares are analysed, their
+/// fundamental being the dominant tone, other signals only have their peak checked.
+fn check_channel(
+    signal: Signal,
+    samples: &[i32; ANALYSIS_LEN],
+    sample_rate: u32,
+    depth: Depth,
+) -> ChannelCheck {
+    let expected_peak = signal.amplitude() * 2147483648.0;
+    let peak = peak(samples) as f32;
+    let freq = zero_crossing_frequency(samples, sample_rate);
+    let tone = signal.frequency().and_then(|_| {
+        let mut normalized = [0.0; ANALYSIS_LEN];
+        normalize(samples, &mut normalized);
+        analyze_tone(&mut normalized, sample_rate as f32)
+    });
+    let ok = match signal {
+        Signal::Sine { freq, .. } => tone.is_some_and(|tone| {
+            libm::fabsf(tone.frequency - freq) < freq * 0.001
+                && libm::fabsf(tone.amplitude - signal.amplitude()) < signal.amplitude() * 0.01
+                && tone.thd_n_db() < depth.max_thd_n_db()
+        }),
+        Signal::Square { .. } => {
+            let expected = signal.frequency().unwrap();
+            let freq_ok = freq.is_some_and(|f| libm::fabsf(f - expected) < expected * 0.01);
+            let tone_ok =
+                tone.is_some_and(|tone| libm::fabsf(tone.frequency - expected) < expected * 0.01);
+            let peak_ok = peak <= expected_peak * 1.02 && peak >= expected_peak * 0.98;
+            freq_ok && tone_ok && peak_ok
+        }
+        _ => peak > 0.0 && peak <= expected_peak * 1.01,
+    };
+    ChannelCheck {
+        ok,
+        freq,
+        peak,
+        tone,
+    }
+}
@@ -0,0 +1,204 @@
+//! Slave synchronisation tests
+//!
+//! A master transmits the counter pattern to a slave receiver, started several times with a
+//! given synchronisation strategy and sample rate. The timer capture strategy needs the receiver
+//! WS wired to its TIM2 input, see [`ws_capture`](crate::ws_capture).
+
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::EXTI;
+
+use rtic::mutex::prelude::*;
+
+use crate::block::*;
+use crate::driver_wrap::*;
+use crate::progress::verdict;
+use crate::sync::*;
+use crate::teardown::Teardown;
+use crate::ws_capture::*;
+use crate::{deadline, SYSCLK_HZ};
+
+use RxMode::*;
+use TxMode::*;
+
+/// Sample rates requested to compare strategies
+pub const SAMPLE_RATES: [u32; 4] = [8000, 16000, 32000, 48000];
+
+/// Slave start-ups for each strategy and sample rate
+const TRIALS: u32 = 8;
+
+/// Blocks checked after each start-up
+const TRIAL_BLOCKS: usize = 4;
+
+/// Start `Rx` as slave receiver `TRIALS` times using `strategy` and report how reliably it
+/// locked on the stream.
+#[allow(clippy::too_many_arguments)]
+pub fn slave_receive_sync<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    shared_rx_blocks: &mut impl Mutex<T = BlockDrainer<'static, BLOCK_LEN>>,
+    mut shared_ws_capture: &mut impl Mutex<T = WsCapture>,
+    tx_data_p: &mut Producer<'static, (i32, i32), 8_usize>,
+    strategy: SyncStrategy,
+    sample_rate: u32,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
+    rprint!(
+        "Slave Receive sync {} {} Hz 32 bits",
+        strategy.name(),
+        sample_rate
+    );
+    let drv_cfg_base = I2sDriverConfig::new_master()
+        .receive()
+        .standard(Philips)
+        .data_format(DataFormat::Data32Channel32)
+        .master_clock(true)
+        .request_frequency(sample_rate);
+
+    let (mut rx, mut tx) = (rx, tx);
+    let mut locked = 0;
+    let mut stats = SyncStats::default();
+    for trial in 0..TRIALS {
+        //reset I2s peripherals
+        Rx::reset_peripheral();
+        Tx::reset_peripheral();
+
+        // Set up drivers
+        let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+        rx_driver.set_rx_interrupt(true);
+        rx_driver.set_error_interrupt(true);
+
+        let mut tx_driver = drv_cfg_base.transmit().i2s_driver(tx);
+        let actual_rate = tx_driver.sample_rate();
+        if trial == 0 {
+            rprint!(", SR {} ... ", actual_rate);
+        }
+        tx_driver.set_tx_interrupt(true);
+        let frame_cycles = SYSCLK_HZ / actual_rate;
+        // give up if the slave doesn't lock
+        let timeout = 4 * TRIAL_BLOCKS as u32 * BLOCK_LEN as u32 * frame_cycles;
+
+        // prepare data to transmit
+        let mut count = 0;
+        while tx_data_p.ready() {
+            tx_data_p.enqueue(counter_frame(count)).ok();
+            count += 1;
+        }
+
+        // from here on the guard tears the drivers down, whatever happens
+        let mut running =
+            Teardown::new(shared_exti, &mut *shared_rx_driver, &mut *shared_tx_driver);
+        let (mut shared_exti, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
+        // start drivers
+        (
+            &mut shared_exti,
+            &mut shared_rx_driver,
+            &mut shared_tx_driver,
+            &mut shared_ws_capture,
+        )
+            .lock(|exti, shared_rx_driver, shared_tx_driver, ws_capture| {
+                tx_driver.enable();
+                shared_tx_driver.replace(MasterTransmit32bits(tx_driver));
+                match strategy {
+                    SyncStrategy::Exti => {
+                        rx_driver.i2s_peripheral_mut().ws_enable_interrupt(exti);
+                    }
+                    SyncStrategy::Polling => {}
+                    SyncStrategy::TimerCapture => {
+                        ws_capture.set_sample_rate(actual_rate);
+                        if let Some(line) = Rx::WS_CAPTURE {
+                            ws_capture.listen(line, true);
+                        }
+                    }
+                }
+                shared_rx_driver.set_sync(strategy);
+                shared_rx_driver.set_rx_sink(RxSink::Block);
+                shared_rx_driver.replace(SlaveReceive32bits(rx_driver));
+            });
+
+        // feed the transmitter and check blocks as they come
+        let deadline = deadline(timeout);
+        let mut check = SequenceCheck::new();
+        let mut received = 0;
+        let mut timed_out = false;
+        while received < TRIAL_BLOCKS {
+            if deadline.is_expired() {
+                timed_out = true;
+                break;
+            }
+            // poll tightly while the slave waits for WS
+            if strategy == SyncStrategy::Polling {
+                while shared_rx_driver.lock(|rx_driver| {
+                    rx_driver.sync_armed() && !rx_driver.receive_sync_poll(frame_cycles / 4)
+                }) {
+                    if deadline.is_expired() {
+                        break;
+                    }
+                }
+            }
+            while tx_data_p.ready() {
+                tx_data_p.enqueue(counter_frame(count)).ok();
+                count += 1;
+            }
+            shared_rx_blocks.lock(|rx_blocks| {
+                if let Some(block) = rx_blocks.dequeue() {
+                    check.check(block);
+                    rx_blocks.release(block);
+                    received += 1;
+                }
+            });
+        }
+        // let the transmitter consume what is left in its queue
+        if !timed_out {
+            timed_out = deadline
+                .wait(|| {
+                    shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+                    tx_data_p.len() == 0
+                })
+                .is_err();
+        }
+
+        //disable driver and release
+        let trial_stats =
+            (&mut shared_rx_driver, &mut shared_ws_capture).lock(|rx_driver, ws_capture| {
+                if let Some(line) = Rx::WS_CAPTURE {
+                    ws_capture.listen(line, false);
+                }
+                rx_driver.sync_stats()
+            });
+        (rx, tx) = running.finish();
+
+        // drop leftovers
+        shared_rx_blocks.lock(|rx_blocks| rx_blocks.flush());
+
+        if !timed_out && check.is_ok() {
+            locked += 1;
+        }
+        stats.attempts += trial_stats.attempts;
+        stats.missed += trial_stats.missed;
+        stats.max_latency = stats.max_latency.max(trial_stats.max_latency);
+    }
+
+    // display result
+    if verdict(locked == TRIALS) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed");
+    }
+    rprintln!(
+        "  {}/{} locked, {} attempts, {} missed edges, latency up to {} cycles",
+        locked,
+        TRIALS,
+        stats.attempts,
+        stats.missed,
+        stats.max_latency
+    );
+    (rx, tx)
+}
@@ -0,0 +1,269 @@
+//! TDM tests
+//!
+//! The transmitter streams tagged TDM frames to the receiver over PCM frame sync, each slot
+//! carrying its own index and the frame count, so received frames tell if slots come in order and
+//! in place.
+
+use heapless::spsc::*;
+use rtt_target::{rprint, rprintln};
+
+use crate::hal;
+
+use hal::i2s::stm32_i2s_v12x::driver::{DataFormat, *};
+use hal::pac::EXTI;
+
+use rtic::mutex::prelude::*;
+
+use crate::driver_wrap::*;
+use crate::progress::verdict;
+use crate::teardown::Teardown;
+use crate::{deadline, SYSCLK_HZ};
+
+use RxMode::*;
+use TxMode::*;
+
+/// Frames checked for each configuration
+const TEST_FRAMES: u32 = 256;
+
+/// PCM frame sync used to carry the slots
+#[derive(Copy, Clone)]
+pub enum FrameSync {
+    Short,
+    Long,
+}
+
+/// All frame syncs, for scenarios comparing them
+pub const FRAME_SYNCS: [FrameSync; 2] = [FrameSync::Short, FrameSync::Long];
+
+impl FrameSync {
+    pub fn name(self) -> &'static str {
+        match self {
+            FrameSync::Short => "short",
+            FrameSync::Long => "long",
+        }
+    }
+}
+
+/// Sample of `slot` in frame number `frame`, the tag in the upper half survives 16 bits slots
+fn slot_value(frame: u32, slot: usize) -> i32 {
+    let tag = (frame & 0xFFF) << 4 | slot as u32;
+    (tag << 16 | (!tag & 0xFFFF)) as i32
+}
+
+fn tdm_frame(frame: u32, slots: usize) -> SlotFrame {
+    let mut samples = [0; MAX_SLOTS];
+    for (slot, sample) in samples.iter_mut().enumerate().take(slots) {
+        *sample = slot_value(frame, slot);
+    }
+    samples
+}
+
+/// Check of received TDM frames
+#[derive(Default)]
+struct SlotCheck {
+    frames: u32,
+    /// Frames missing between received ones
+    lost: u32,
+    /// Slots not holding the expected sample
+    misplaced: u32,
+    next: Option<u32>,
+}
+
+impl SlotCheck {
+    fn check(&mut self, samples: &SlotFrame, slots: usize, mask: u32) {
+        // frame number from the tag of the first slot
+        let frame = (samples[0] as u32 >> 20) & 0xFFF;
+        if let Some(next) = self.next {
+            self.lost += frame.wrapping_sub(next) & 0xFFF;
+        }
+        self.next = Some((frame + 1) & 0xFFF);
+        self.frames += 1;
+        for (slot, &sample) in samples.iter().enumerate().take(slots) {
+            if (sample ^ slot_value(frame, slot)) as u32 & mask != 0 {
+                self.misplaced += 1;
+            }
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.frames > 0 && self.lost == 0 && self.misplaced == 0
+    }
+}
+
+/// Stream `slots` slots of `slot_bits` bits per frame from `Tx` to `Rx`, `Tx` being master if
+/// `tx_master`, slave otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn transmit_tdm<Rx: I2sInstance, Tx: I2sInstance>(
+    shared_exti: &mut impl Mutex<T = EXTI>,
+    shared_rx_driver: &mut impl Mutex<T = DriverWrap<Rx>>,
+    shared_tx_driver: &mut impl Mutex<T = DriverWrap<Tx>>,
+    rx_tdm_c: &mut Consumer<'static, (u32, SlotFrame), 8_usize>,
+    tx_tdm_p: &mut Producer<'static, SlotFrame, 8_usize>,
+    frame_sync: FrameSync,
+    slots: usize,
+    slot_bits: u8,
+    tx_master: bool,
+    rx: Rx,
+    tx: Tx,
+) -> (Rx, Tx) {
+    let (data_format, mask) = match slot_bits {
+        16 => (DataFormat::Data16Channel16, 0xFFFF_0000),
+        _ => (DataFormat::Data32Channel32, 0xFFFF_FFFF),
+    };
+    rprint!(
+        "{} TDM {}x{} bits {} sync",
+        if tx_master {
+            "Master Transmit + Slave Receive"
+        } else {
+            "Slave Transmit + Master Receive"
+        },
+        slots,
+        slot_bits,
+        frame_sync.name()
+    );
+
+    //reset I2s peripherals
+    Rx::reset_peripheral();
+    Tx::reset_peripheral();
+
+    // prepare data to transmit
+    let mut count = 0;
+    while tx_tdm_p.ready() {
+        tx_tdm_p.enqueue(tdm_frame(count, slots)).ok();
+        count += 1;
+    }
+
+    // from here on the guard tears the drivers down, whatever happens
+    let mut running = Teardown::new(shared_exti, shared_rx_driver, shared_tx_driver);
+    let (_, mut shared_rx_driver, mut shared_tx_driver) = running.parts();
+
+    // Set up drivers, left disabled so the transmitter interrupt loads the first slot
+    macro_rules! set_up {
+        ($standard:expr, $MasterTx:ident, $SlaveRx:ident, $SlaveTx:ident, $MasterRx:ident) => {{
+            let drv_cfg_base = I2sDriverConfig::new_master()
+                .transmit()
+                .standard($standard)
+                .data_format(data_format)
+                .master_clock(true)
+                .request_frequency(48000);
+            if tx_master {
+                let mut tx_driver = drv_cfg_base.i2s_driver(tx);
+                let sample_rate = tx_driver.sample_rate();
+                tx_driver.set_tx_interrupt(true);
+                let mut rx_driver = drv_cfg_base.to_slave().receive().i2s_driver(rx);
+                rx_driver.set_rx_interrupt(true);
+                rx_driver.set_error_interrupt(true);
+                (&mut shared_rx_driver, &mut shared_tx_driver).lock(
+                    |shared_rx_driver, shared_tx_driver| {
+                        shared_rx_driver.set_slots(slots, slot_bits);
+                        shared_rx_driver.replace($SlaveRx(rx_driver));
+                        shared_tx_driver.set_slots(slots, slot_bits);
+                        shared_tx_driver.replace($MasterTx(tx_driver));
+                    },
+                );
+                sample_rate
+            } else {
+                let mut rx_driver = drv_cfg_base.receive().i2s_driver(rx);
+                let sample_rate = rx_driver.sample_rate();
+                rx_driver.set_rx_interrupt(true);
+                let mut tx_driver = drv_cfg_base.to_slave().i2s_driver(tx);
+                tx_driver.set_tx_interrupt(true);
+                tx_driver.set_error_interrupt(true);
+                (&mut shared_rx_driver, &mut shared_tx_driver).lock(
+                    |shared_rx_driver, shared_tx_driver| {
+                        shared_rx_driver.set_slots(slots, slot_bits);
+                        shared_rx_driver.replace($MasterRx(rx_driver));
+                        shared_tx_driver.set_slots(slots, slot_bits);
+                        shared_tx_driver.replace($SlaveTx(tx_driver));
+                    },
+                );
+                sample_rate
+            }
+        }};
+    }
+    let sample_rate = match frame_sync {
+        FrameSync::Short => set_up!(
+            PcmShortSync,
+            MasterTransmitTdmShort,
+            SlaveReceiveTdmShort,
+            SlaveTransmitTdmShort,
+            MasterReceiveTdmShort
+        ),
+        FrameSync::Long => set_up!(
+            PcmLongSync,
+            MasterTransmitTdmLong,
+            SlaveReceiveTdmLong,
+            SlaveTransmitTdmLong,
+            MasterReceiveTdmLong
+        ),
+    };
+    rprint!(", SR {} ... ", sample_rate);
+
+    // the slave counts slots from the first word, so it starts before the master
+    cortex_m::asm::delay(1000);
+    (&mut shared_rx_driver, &mut shared_tx_driver).lock(|rx_driver, tx_driver| {
+        if tx_master {
+            rx_driver.enable();
+            tx_driver.enable();
+        } else {
+            tx_driver.enable();
+            rx_driver.enable();
+        }
+    });
+
+    let frame_cycles = SYSCLK_HZ / sample_rate * slots as u32;
+    // give up if the stream doesn't come
+    let timeout = 4 * TEST_FRAMES * frame_cycles;
+    let deadline = deadline(timeout);
+
+    // feed the transmitter and check frames as they come
+    let mut check = SlotCheck::default();
+    let mut timed_out = false;
+    while check.frames < TEST_FRAMES {
+        if deadline.is_expired() {
+            timed_out = true;
+            break;
+        }
+        while tx_tdm_p.ready() {
+            tx_tdm_p.enqueue(tdm_frame(count, slots)).ok();
+            count += 1;
+        }
+        if let Some((_, samples)) = rx_tdm_c.dequeue() {
+            check.check(&samples, slots, mask);
+        }
+    }
+    // let the transmitter consume what is left in its queue
+    if !timed_out {
+        timed_out = deadline
+            .wait(|| {
+                while rx_tdm_c.dequeue().is_some() {}
+                tx_tdm_p.len() == 0
+            })
+            .is_err();
+    }
+
+    //disable driver and release
+    let rx_errors = shared_rx_driver.lock(|rx_driver| rx_driver.errors());
+    let tx_errors = shared_tx_driver.lock(|tx_driver| tx_driver.errors());
+    let (rx, tx) = running.finish();
+
+    // drop leftovers
+    while rx_tdm_c.dequeue().is_some() {}
+
+    // display result
+    if verdict(!timed_out && check.is_ok()) {
+        rprintln!("ok");
+    } else {
+        rprintln!("failed");
+        rprintln!(
+            "{} frames, {} lost, {} misplaced slots{}",
+            check.frames,
+            check.lost,
+            check.misplaced,
+            if timed_out { ", timed out" } else { "" }
+        );
+        rprintln!("  {} {:?}", Rx::NAME, rx_errors);
+        rprintln!("  {} {:?}", Tx::NAME, tx_errors);
+    }
+    (rx, tx)
+}
@@ -0,0 +1,251 @@
+//! GPIO level checks of the jumpers between pins
+//!
+//! Pins are driven and read through the GPIO registers whoever owns them, their configuration
+//! is restored afterwards. Peripherals using the pins must not drive them during a check, so the
+//! [`Wiring`] pre-flight runs before any I2S configuration.
+
+use rtt_target::{rprint, rprintln};
+
+use crate::board;
+use crate::hal;
+
+use hal::pac::gpioa::RegisterBlock;
+use hal::pac::GPIOA;
+
+// MODER and PUPDR values
+const INPUT: u32 = 0b00;
+const OUTPUT: u32 = 0b01;
+const PULL_UP: u32 = 0b01;
+const PULL_DOWN: u32 = 0b10;
+
+/// Cycles given to a line to settle after a change
+const SETTLE_CYCLES: u32 = 200;
+
+/// State of a connection between two lines
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Link {
+    /// The second line follows the first one
+    Present,
+    /// The second line doesn't follow the first one
+    Open,
+    /// The first line can't be driven, or drives a line carrying another signal
+    Shorted,
+}
+
+impl Link {
+    pub fn name(self) -> &'static str {
+        match self {
+            Link::Present => "present",
+            Link::Open => "open",
+            Link::Shorted => "shorted",
+        }
+    }
+}
+
+/// A pin, by port and number
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Line {
+    pub port: char,
+    pub pin: u8,
+}
+
+impl Line {
+    pub const fn new(port: char, pin: u8) -> Self {
+        Self { port, pin }
+    }
+
+    fn gpio(self) -> &'static RegisterBlock {
+        // GPIO ports are 0x400 apart
+        let offset = 0x400 * (self.port as u32 - 'A' as u32);
+        unsafe { &*((GPIOA::ptr() as u32 + offset) as *const RegisterBlock) }
+    }
+
+    fn set_mode(self, mode: u32) {
+        let shift = 2 * self.pin;
+        self.gpio()
+            .moder
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (mode << shift)) });
+    }
+
+    fn set_pull(self, pull: u32) {
+        let shift = 2 * self.pin;
+        self.gpio()
+            .pupdr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (pull << shift)) });
+    }
+
+    fn set_output(self, high: bool) {
+        let bit = if high { self.pin } else { self.pin + 16 };
+        self.gpio().bsrr.write(|w| unsafe { w.bits(1 << bit) });
+    }
+
+    /// Input level, read whatever the pin mode, alternate function included
+    pub fn is_high(self) -> bool {
+        (self.gpio().idr.read().bits() & (1 << self.pin)) != 0
+    }
+
+    fn save(self) -> (u32, u32, bool) {
+        let gpio = self.gpio();
+        let shift = 2 * self.pin;
+        (
+            (gpio.moder.read().bits() >> shift) & 0b11,
+            (gpio.pupdr.read().bits() >> shift) & 0b11,
+            (gpio.odr.read().bits() & (1 << self.pin)) != 0,
+        )
+    }
+
+    fn restore(self, (mode, pull, high): (u32, u32, bool)) {
+        self.set_output(high);
+        self.set_pull(pull);
+        self.set_mode(mode);
+    }
+}
+
+/// Drive `from` both ways while `to` is pulled the other way, `true` if `to` follows.
+pub fn linked(from: Line, to: Line) -> bool {
+    let (from_saved, to_saved) = (from.save(), to.save());
+    to.set_mode(INPUT);
+    from.set_mode(OUTPUT);
+    let mut follows = true;
+    for high in [true, false] {
+        to.set_pull(if high { PULL_DOWN } else { PULL_UP });
+        from.set_output(high);
+        cortex_m::asm::delay(SETTLE_CYCLES);
+        follows &= to.is_high() == high;
+    }
+    from.restore(from_saved);
+    to.restore(to_saved);
+    follows
+}
+
+/// `true` if `line` reads back the levels it is driven to.
+fn drives(line: Line) -> bool {
+    let saved = line.save();
+    line.set_mode(OUTPUT);
+    let mut ok = true;
+    for high in [true, false] {
+        line.set_output(high);
+        cortex_m::asm::delay(SETTLE_CYCLES);
+        ok &= line.is_high() == high;
+    }
+    line.restore(saved);
+    ok
+}
+
+/// State of the connection from `from` to `to`, `others` carrying other signals.
+pub fn link(from: Line, to: Line, others: &[Line]) -> Link {
+    if !drives(from) || others.iter().any(|&other| linked(from, other)) {
+        Link::Shorted
+    } else if linked(from, to) {
+        Link::Present
+    } else {
+        Link::Open
+    }
+}
+
+/// States of the (WS, CK, SD) connections from `from` to `to`
+fn bus_links(from: [Line; 3], to: [Line; 3]) -> [Link; 3] {
+    let mut links = [Link::Open; 3];
+    for (i, link_i) in links.iter_mut().enumerate() {
+        // other signals on both sides
+        let mut others = [from[0]; 4];
+        let mut n = 0;
+        for j in (0..3).filter(|&j| j != i) {
+            others[n] = from[j];
+            others[n + 1] = to[j];
+            n += 2;
+        }
+        *link_i = link(from[i], to[i], &others);
+    }
+    links
+}
+
+fn all_present(links: &[Link]) -> bool {
+    links.iter().all(|&link| link == Link::Present)
+}
+
+/// Jumpers found on the board
+#[derive(Copy, Clone, Debug)]
+pub struct Wiring {
+    /// i2s2 (WS, CK, SD) to i2s3
+    pub i2s3: [Link; 3],
+    /// i2s2 WS and i2s3 WS to the TIM2 inputs
+    pub ws_capture: [Link; 2],
+    /// i2s2 (WS, CK, SD) to i2s1
+    #[cfg(has_i2s1)]
+    pub i2s1: [Link; 3],
+    /// i2s2 (WS, CK, SD) to i2s5
+    #[cfg(has_i2s5)]
+    pub i2s5: [Link; 3],
+    /// i2s2 SD to I2S2ext SD
+    pub loopback: Link,
+}
+
+impl Wiring {
+    /// Check every connection used by the suite. I2S peripherals must be disabled.
+    pub fn detect() -> Self {
+        let (i2s2, i2s3) = (board::I2S2_LINES, board::I2S3_LINES);
+        let capture = board::WS_CAPTURE_LINES;
+        Self {
+            i2s3: bus_links(i2s2, i2s3),
+            ws_capture: [
+                link(i2s2[0], capture[0], &[i2s2[1], i2s2[2]]),
+                link(i2s3[0], capture[1], &[i2s3[1], i2s3[2]]),
+            ],
+            #[cfg(has_i2s1)]
+            i2s1: bus_links(i2s2, board::I2S1_LINES),
+            #[cfg(has_i2s5)]
+            i2s5: bus_links(i2s2, board::I2S5_LINES),
+            #[cfg(has_i2sext)]
+            loopback: link(i2s2[2], board::I2S2EXT_SD, &[i2s2[0], i2s2[1]]),
+            #[cfg(not(has_i2sext))]
+            loopback: Link::Open,
+        }
+    }
+
+    /// i2s2 and i2s3 can run paired scenarios
+    pub fn i2s3_ok(&self) -> bool {
+        all_present(&self.i2s3)
+    }
+
+    /// WS lines can be captured by the timer
+    pub fn ws_capture_ok(&self) -> bool {
+        all_present(&self.ws_capture)
+    }
+
+    #[cfg(has_i2s1)]
+    pub fn i2s1_ok(&self) -> bool {
+        all_present(&self.i2s1)
+    }
+
+    #[cfg(has_i2s5)]
+    pub fn i2s5_ok(&self) -> bool {
+        all_present(&self.i2s5)
+    }
+
+    /// Print the state of each connection
+    pub fn report(&self) {
+        rprintln!("Wiring check");
+        report_bus("i2s2 - i2s3", &self.i2s3);
+        rprintln!(
+            "  WS capture: i2s2 WS {}, i2s3 WS {}",
+            self.ws_capture[0].name(),
+            self.ws_capture[1].name()
+        );
+        #[cfg(has_i2s1)]
+        report_bus("i2s2 - i2s1", &self.i2s1);
+        #[cfg(has_i2s5)]
+        report_bus("i2s2 - i2s5", &self.i2s5);
+        if cfg!(has_i2sext) {
+            rprintln!("  i2s2 - I2S2ext SD: {}", self.loopback.name());
+        }
+    }
+}
+
+fn report_bus(name: &str, links: &[Link; 3]) {
+    rprint!("  {}:", name);
+    for (signal, link) in ["WS", "CK", "SD"].iter().zip(links) {
+        rprint!(" {} {}", signal, link.name());
+    }
+    rprintln!();
+}
@@ -0,0 +1,89 @@
+//! Timer capture of the WS lines, for [`SyncStrategy::TimerCapture`](crate::sync::SyncStrategy)
+//!
+//! TIM2 captures rising edges on CH1 (PA0) and CH2 (PA1), that must be wired to the i2s2 WS
+//! (PB12) and i2s3 WS (PA4) pins. The edge time tells if WS is still high when the capture
+//! interrupt runs, where the EXTI strategy can only read the pin level.
+
+use crate::hal;
+
+use hal::pac::{RCC, TIM2};
+use hal::rcc::{Enable, Reset};
+
+// CC1S and CC2S = 0b01 (TIx inputs), IC1F and IC2F = 0b0011 (8 samples filter)
+const CCMR1_CAPTURE: u32 = 0x3131;
+
+/// Capture channel of a WS line
+#[derive(Copy, Clone)]
+pub enum WsLine {
+    I2s2,
+    I2s3,
+}
+
+pub struct WsCapture {
+    tim: TIM2,
+    timclk: u32,
+    // largest acceptable edge age, in timer ticks
+    window: u32,
+}
+
+impl WsCapture {
+    /// Set up TIM2 as a free running counter capturing both WS lines. `timclk` is the TIM2
+    /// clock frequency, pins must already be in TIM2 alternate mode.
+    pub fn new(tim: TIM2, timclk: u32) -> Self {
+        unsafe {
+            let rcc = &(*RCC::ptr());
+            TIM2::enable(rcc);
+            TIM2::reset(rcc);
+        }
+        tim.ccmr1_input()
+            .write(|w| unsafe { w.bits(CCMR1_CAPTURE) });
+        tim.ccer.write(|w| w.cc1e().set_bit().cc2e().set_bit());
+        tim.psc.write(|w| unsafe { w.bits(0) });
+        tim.arr.write(|w| unsafe { w.bits(u32::MAX) });
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+        Self {
+            tim,
+            timclk,
+            window: 0,
+        }
+    }
+
+    /// Edges are in time during the first half of the WS high level at `sample_rate`.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.window = self.timclk / sample_rate / 4;
+    }
+
+    /// Enable or disable the capture interrupt of a line.
+    pub fn listen(&mut self, line: WsLine, enable: bool) {
+        self.tim.sr.modify(|_, w| match line {
+            WsLine::I2s2 => w.cc1if().clear_bit().cc1of().clear_bit(),
+            WsLine::I2s3 => w.cc2if().clear_bit().cc2of().clear_bit(),
+        });
+        self.tim.dier.modify(|_, w| match line {
+            WsLine::I2s2 => w.cc1ie().bit(enable),
+            WsLine::I2s3 => w.cc2ie().bit(enable),
+        });
+    }
+
+    /// Edges captured since the last call, `Some(in_time)` for each line with a new edge.
+    pub fn take_edges(&mut self) -> (Option<bool>, Option<bool>) {
+        let now = self.tim.cnt.read().bits();
+        let sr = self.tim.sr.read();
+        // reading a capture register clears its flag
+        let i2s2 = sr
+            .cc1if()
+            .bit_is_set()
+            .then(|| now.wrapping_sub(self.tim.ccr1.read().bits()) <= self.window);
+        let i2s3 = sr
+            .cc2if()
+            .bit_is_set()
+            .then(|| now.wrapping_sub(self.tim.ccr2.read().bits()) <= self.window);
+        // an overcapture means we are late anyway
+        self.tim
+            .sr
+            .modify(|_, w| w.cc1of().clear_bit().cc2of().clear_bit());
+        (i2s2, i2s3)
+    }
+}
@@ -0,0 +1,181 @@
+//! # Host decoder
+//!
+//! Decodes what the firmware leaves for the host, one subcommand per format:
+//!
+//! - `crash`, the crash record the firmware keeps in its `CRASH` RAM region, from a dump of that
+//!   region: either raw bytes, or the hex text printed by a probe, bytes or little endian 32 bits
+//!   words.
+//! - `snapshot`, field by field, the register snapshots a failed scenario dumps in the test log,
+//!   the `regs` lines. Other lines of the log are skipped, so a whole log can be given.
+//!
+//! For instance
+//!
+//! ```text
+//! probe-rs read --chip STM32F411RETx b32 <address of CRASH> 49 > crash.txt
+//! cd tools/decode && cargo run -- crash ../../crash.txt
+//! cd tools/decode && cargo run -- snapshot ../../test.log
+//! ```
+//!
+//! The address of `CRASH` is in the symbols of the firmware, and 49 words are `RECORD_LEN`
+//! bytes. Without a file the input is read from stdin.
+//!
+//! The formats are shared with the firmware, their tests run here with `cargo test`.
+
+use std::fmt::Write;
+use std::io::Read;
+use std::process::ExitCode;
+
+// the firmware side of the modules is unused here
+#[allow(dead_code)]
+#[path = "../../../src/crash.rs"]
+mod crash;
+#[allow(dead_code)]
+#[path = "../../../src/snapshot.rs"]
+mod snapshot;
+
+use snapshot::{parse_line, Line};
+
+const USAGE: &str = "usage: decode crash|snapshot [file]";
+
+/// Bytes of the dump, parsed from hex text unless it's binary
+fn parse(dump: &[u8]) -> Result<Vec<u8>, String> {
+    let Ok(text) = std::str::from_utf8(dump) else {
+        return Ok(dump.to_vec());
+    };
+    let mut bytes = Vec::new();
+    // skip addresses ending with ':'
+    for token in text
+        .split_whitespace()
+        .filter(|token| !token.ends_with(':'))
+    {
+        let digits = token.trim_start_matches("0x");
+        let value = u32::from_str_radix(digits, 16).map_err(|_| format!("not hex: {:?}", token))?;
+        match digits.len() {
+            1 | 2 => bytes.push(value as u8),
+            8 => bytes.extend(value.to_le_bytes()),
+            _ => return Err(format!("neither a byte nor a word: {:?}", token)),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode the crash record of a dump.
+fn decode_crash(dump: &[u8]) -> Result<String, String> {
+    let bytes = parse(dump)?;
+    let record = crash::CrashRecord::decode(&bytes).map_err(|e| match e {
+        crash::DecodeError::Short => format!(
+            "{} bytes dumped, a record has {}",
+            bytes.len(),
+            crash::RECORD_LEN
+        ),
+        crash::DecodeError::NoRecord => "no crash recorded".into(),
+        crash::DecodeError::Checksum => "damaged record, bad checksum".into(),
+        crash::DecodeError::Invalid => "damaged record, field out of range".into(),
+    })?;
+    Ok(format!("{}\n", record))
+}
+
+/// Decode the snapshots of `log`, the sample rates are computed once the clock tree is known.
+fn decode_log(log: &str) -> String {
+    let mut out = String::new();
+    let mut instances = Vec::new();
+    for line in log.lines().filter_map(parse_line) {
+        match line {
+            Line::Spi(name, regs) => instances.push((name, regs)),
+            // the clock tree ends a snapshot
+            Line::Rcc(rcc) => {
+                writeln!(out, "rcc\n{}", rcc).unwrap();
+                for (name, regs) in instances.drain(..) {
+                    writeln!(out, "{}\n{}", name, regs.decode(rcc.i2s_clk_hz())).unwrap();
+                }
+                writeln!(out).unwrap();
+            }
+        }
+    }
+    // a truncated log
+    for (name, regs) in instances {
+        writeln!(out, "{}\n{}", name, regs.decode(None)).unwrap();
+    }
+    out
+}
+
+/// Decode the register snapshots of a log.
+fn decode_snapshot(log: &[u8]) -> Result<String, String> {
+    let log = std::str::from_utf8(log).map_err(|_| "the log isn't text".to_string())?;
+    let decoded = decode_log(log);
+    if decoded.is_empty() {
+        return Err("no register snapshot in the log".into());
+    }
+    Ok(decoded)
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args_os().skip(1);
+    let decode = match args.next().as_ref().and_then(|format| format.to_str()) {
+        Some("crash") => decode_crash,
+        Some("snapshot") => decode_snapshot,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut input = Vec::new();
+    let read = match args.next() {
+        Some(path) => std::fs::read(path).map(|content| input = content),
+        None => std::io::stdin().read_to_end(&mut input).map(|_| ()),
+    };
+    if let Err(e) = read {
+        eprintln!("can't read the input: {}", e);
+        return ExitCode::FAILURE;
+    }
+    match decode(&input) {
+        Ok(decoded) => {
+            print!("{}", decoded);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+#3 Master Receive + Slave Transmit driver 32 bits with interrupt, SR 48000 ... failed
+timed out
+0x11113333 0x7777eeee,          0 0x00000000 0x00000000
+  regs i2s2 CR1 00000000 CR2 00000060 SR 00000002 I2SCFGR 00000f05 I2SPR 00000302
+  regs i2s3 CR1 00000000 CR2 000000a0 SR 00000106 I2SCFGR 00000c05 I2SPR 00000002
+  regs rcc HSE 8000000 CR 0f030000 PLLCFGR 24403008 CFGR 0000940a PLLI2SCFGR 60003004
+#4 Slave Receive + Master Transmit driver 32 bits with interrupt, SR 48000 ... ok
+";
+
+    #[test]
+    fn hex_dumps() {
+        assert_eq!(parse(b"0x48 53 52\n43").unwrap(), [0x48, 0x53, 0x52, 0x43]);
+        assert_eq!(
+            parse(b"20000000: 43525348 0x00000001").unwrap(),
+            [0x48, 0x53, 0x52, 0x43, 1, 0, 0, 0]
+        );
+        assert!(parse(b"123").is_err());
+        assert_eq!(parse(&[0xff, 0x48]).unwrap(), [0xff, 0x48]);
+    }
+
+    #[test]
+    fn snapshot_log() {
+        let decoded = decode_log(LOG);
+        let lines: Vec<_> = decoded.lines().collect();
+        assert_eq!(lines[0], "rcc");
+        assert!(lines[3].ends_with("I2S clock 64000000 Hz"));
+        assert_eq!(lines[4], "i2s2");
+        assert!(lines[7].ends_with("sample rate 50000.0 Hz"));
+        assert_eq!(lines[11], "i2s3");
+        assert_eq!(lines[14], "  I2SDIV 2, ODD 0, MCKOE 0");
+        assert_eq!(lines.len(), 19);
+        assert!(decode_snapshot(b"#4 ... ok").is_err());
+    }
+}
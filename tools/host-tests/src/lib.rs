@@ -0,0 +1,52 @@
+//! # Host unit tests
+//!
+//! The firmware only builds for the chip, so the unit tests of its modules that don't touch the
+//! hardware are run here, the modules being pulled in from `src`:
+//!
+//! ```text
+//! cd tools/host-tests && cargo test
+//! ```
+//!
+//! The crash record and register snapshot formats are tested with their decoder, `tools/decode`.
+
+#![no_std]
+
+/// The frame traits the block pool implements, the rest of the module needs the chip.
+pub mod driver_wrap {
+    pub trait FrameSink<T> {
+        fn push(&mut self, time: u32, frame: (T, T)) -> bool;
+        fn is_full(&self) -> bool;
+    }
+
+    pub trait FrameSource<T> {
+        fn pop(&mut self) -> Option<(T, T)>;
+    }
+}
+
+#[path = "../../../src/analysis.rs"]
+pub mod analysis;
+#[path = "../../../src/block.rs"]
+pub mod block;
+pub use firmware::codec;
+#[path = "../../../src/channel.rs"]
+pub mod channel;
+#[path = "../../../src/deadline.rs"]
+pub mod deadline;
+#[path = "../../../src/load.rs"]
+pub mod load;
+#[path = "../../../src/pipeline.rs"]
+pub mod pipeline;
+#[path = "../../../src/progress.rs"]
+pub mod progress;
+#[path = "../../../src/recovery.rs"]
+pub mod recovery;
+#[path = "../../../src/signal.rs"]
+pub mod signal;
+#[path = "../../../src/sync.rs"]
+pub mod sync;
+
+// the drivers are in src/codec, where a module pulled in with a path to its file doesn't look
+#[path = "../../../src"]
+mod firmware {
+    pub mod codec;
+}